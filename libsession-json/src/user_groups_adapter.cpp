@@ -38,6 +38,7 @@ namespace session::config
         j["id"] = info.id;
         j["secret_key"] = Base64{info.secretkey};
         j["auth_data"] = Base64{info.auth_data};
+        j["kicked"] = info.kicked();
     }
 
     void to_json(json &j, const community_info &info)
@@ -0,0 +1,432 @@
+-- Conversation summary table.
+--
+-- The `conversations` view used to join `messages` twice with broad `OR` conditions to work out
+-- the last message and unread count of every conversation, on every query. These aggregates now
+-- live in `conversation_summaries`, which is maintained by the triggers below as messages are
+-- inserted/updated/deleted and as the configs/settings they depend on are saved. The counts are
+-- adjusted in place, and only the conversations whose config entry changed are recomputed.
+
+-- Every known conversation, together with what decides its membership and unread state
+CREATE VIEW conversation_keys AS
+WITH
+convo AS (
+  SELECT
+	coalesce(nullif(session_id, ''), nullif(id, ''), nullif(community_url, '')) AS id,
+	type,
+	unread,
+	last_read
+  FROM config_convo_info
+  WHERE type != 'community'
+  UNION
+  SELECT
+	community_url AS id,
+	type,
+	0 AS unread,
+	0 AS last_read
+  FROM config_user_groups
+  WHERE type = 'community'
+),
+identities AS (
+	SELECT value->>'$.session_id' AS session_id
+	FROM app_settings
+	WHERE name = 'identity' AND id = ''
+	LIMIT 1
+)
+SELECT
+	convo.id,
+	convo.type,
+	convo.last_read,
+	identities.session_id AS my_id,
+	community_identity.value AS my_blinded_id
+FROM convo
+LEFT JOIN identities ON convo.type != 'community'
+LEFT JOIN app_settings community_identity ON convo.type = 'community' AND community_identity.name = 'blinded_id' AND community_identity.id = convo.id
+WHERE convo.id IS NOT NULL;
+
+CREATE INDEX messages_receiver_created ON messages (receiver, created_at);
+
+CREATE INDEX messages_sender_receiver_created ON messages (sender, receiver, created_at);
+
+CREATE TABLE conversation_summaries (
+    conversation_id TEXT NOT NULL PRIMARY KEY COLLATE NOCASE,
+    last_message_id INTEGER DEFAULT NULL,
+    last_message_created TIMESTAMP DEFAULT NULL,
+    unread_count INTEGER NOT NULL DEFAULT 0
+);
+
+-- Summaries computed from scratch, used to (re)build `conversation_summaries`
+CREATE VIEW conversation_summaries_computed AS
+SELECT
+	k.id AS conversation_id,
+	lm.id AS last_message_id,
+	lm.created_at AS last_message_created,
+	(
+		CASE k.type
+		 WHEN 'one_to_one' THEN (
+			SELECT COUNT(mc.hash) FROM messages mc
+			WHERE mc.sender = k.id AND mc.receiver = k.my_id AND mc.created_at > k.last_read
+		 )
+		 WHEN 'group' THEN (
+			SELECT COUNT(mc.hash) FROM messages mc
+			WHERE mc.receiver = k.id AND mc.sender != k.my_id AND mc.created_at > k.last_read
+		 )
+		 WHEN 'community' THEN (
+			SELECT COUNT(mc.hash) FROM messages mc
+			WHERE mc.receiver = k.id AND mc.sender != k.my_blinded_id AND mc.created_at > k.last_read
+		 )
+		 ELSE 0
+		END
+	) AS unread_count
+FROM conversation_keys k
+LEFT JOIN messages lm ON lm.id = (
+	SELECT id FROM (
+		SELECT m.id, m.created_at FROM messages m WHERE m.receiver = k.id
+		UNION ALL
+		SELECT m.id, m.created_at FROM messages m
+		WHERE k.type = 'one_to_one' AND m.sender = k.id AND m.receiver = k.my_id
+	)
+	ORDER BY created_at DESC
+	LIMIT 1
+);
+
+INSERT INTO conversation_summaries SELECT * FROM conversation_summaries_computed;
+
+CREATE TRIGGER conversation_summaries_message_inserted AFTER INSERT ON messages
+BEGIN
+	INSERT INTO conversation_summaries (conversation_id, last_message_id, last_message_created, unread_count)
+	SELECT
+		k.id,
+		NEW.id,
+		NEW.created_at,
+		(
+			NEW.hash IS NOT NULL AND NEW.created_at > k.last_read AND (
+				(k.type = 'one_to_one' AND NEW.sender = k.id AND NEW.receiver = k.my_id) OR
+				(k.type = 'group' AND NEW.receiver = k.id AND NEW.sender != k.my_id) OR
+				(k.type = 'community' AND NEW.receiver = k.id AND NEW.sender != k.my_blinded_id)
+			)
+		) IS 1
+	FROM conversation_keys k
+	WHERE k.id = NEW.receiver OR (k.type = 'one_to_one' AND k.id = NEW.sender AND NEW.receiver = k.my_id)
+	ON CONFLICT (conversation_id) DO UPDATE SET
+		last_message_id = (
+			CASE WHEN last_message_created IS NULL OR excluded.last_message_created > last_message_created
+			 THEN excluded.last_message_id
+			 ELSE last_message_id
+			END
+		),
+		last_message_created = max(coalesce(last_message_created, 0), excluded.last_message_created),
+		unread_count = unread_count + excluded.unread_count;
+END;
+
+CREATE TRIGGER conversation_summaries_message_deleted AFTER DELETE ON messages
+BEGIN
+	UPDATE conversation_summaries SET unread_count = max(unread_count - 1, 0)
+	WHERE conversation_id IN (
+		SELECT k.id FROM conversation_keys k
+		WHERE OLD.hash IS NOT NULL AND OLD.created_at > k.last_read AND (
+			(k.type = 'one_to_one' AND OLD.sender = k.id AND OLD.receiver = k.my_id) OR
+			(k.type = 'group' AND OLD.receiver = k.id AND OLD.sender != k.my_id) OR
+			(k.type = 'community' AND OLD.receiver = k.id AND OLD.sender != k.my_blinded_id)
+		)
+	);
+
+	UPDATE conversation_summaries SET (last_message_id, last_message_created) = (
+		SELECT id, created_at FROM (
+			SELECT m.id, m.created_at FROM messages m
+			WHERE m.receiver = conversation_summaries.conversation_id
+			UNION ALL
+			SELECT m.id, m.created_at FROM messages m, conversation_keys k
+			WHERE k.id = conversation_summaries.conversation_id AND k.type = 'one_to_one'
+			  AND m.sender = k.id AND m.receiver = k.my_id
+		)
+		ORDER BY created_at DESC
+		LIMIT 1
+	)
+	WHERE last_message_id = OLD.id;
+END;
+
+-- Sending a message changes its hash
+CREATE TRIGGER conversation_summaries_message_updated
+AFTER UPDATE OF hash, sender, receiver, created_at ON messages
+BEGIN
+	-- Take the old row out of the counts...
+	UPDATE conversation_summaries SET unread_count = max(unread_count - 1, 0)
+	WHERE conversation_id IN (
+		SELECT k.id FROM conversation_keys k
+		WHERE OLD.hash IS NOT NULL AND OLD.created_at > k.last_read AND (
+			(k.type = 'one_to_one' AND OLD.sender = k.id AND OLD.receiver = k.my_id) OR
+			(k.type = 'group' AND OLD.receiver = k.id AND OLD.sender != k.my_id) OR
+			(k.type = 'community' AND OLD.receiver = k.id AND OLD.sender != k.my_blinded_id)
+		)
+	);
+
+	-- ...and put the new one in, like a newly inserted message
+	INSERT INTO conversation_summaries (conversation_id, last_message_id, last_message_created, unread_count)
+	SELECT
+		k.id,
+		NEW.id,
+		NEW.created_at,
+		(
+			NEW.hash IS NOT NULL AND NEW.created_at > k.last_read AND (
+				(k.type = 'one_to_one' AND NEW.sender = k.id AND NEW.receiver = k.my_id) OR
+				(k.type = 'group' AND NEW.receiver = k.id AND NEW.sender != k.my_id) OR
+				(k.type = 'community' AND NEW.receiver = k.id AND NEW.sender != k.my_blinded_id)
+			)
+		) IS 1
+	FROM conversation_keys k
+	WHERE k.id = NEW.receiver OR (k.type = 'one_to_one' AND k.id = NEW.sender AND NEW.receiver = k.my_id)
+	ON CONFLICT (conversation_id) DO UPDATE SET
+		last_message_id = (
+			CASE WHEN last_message_created IS NULL OR excluded.last_message_created > last_message_created
+			 THEN excluded.last_message_id
+			 ELSE last_message_id
+			END
+		),
+		last_message_created = max(coalesce(last_message_created, 0), excluded.last_message_created),
+		unread_count = unread_count + excluded.unread_count;
+
+	-- The message may have moved or gone back in time
+	UPDATE conversation_summaries SET (last_message_id, last_message_created) = (
+		SELECT id, created_at FROM (
+			SELECT m.id, m.created_at FROM messages m
+			WHERE m.receiver = conversation_summaries.conversation_id
+			UNION ALL
+			SELECT m.id, m.created_at FROM messages m, conversation_keys k
+			WHERE k.id = conversation_summaries.conversation_id AND k.type = 'one_to_one'
+			  AND m.sender = k.id AND m.receiver = k.my_id
+		)
+		ORDER BY created_at DESC
+		LIMIT 1
+	)
+	WHERE last_message_id = OLD.id;
+END;
+
+-- The set of conversations and their last read time come from these configs. Replacing a config
+-- row only fires this trigger, so conversations that are gone are dropped here
+CREATE TRIGGER conversation_summaries_config_inserted AFTER INSERT ON configs
+WHEN NEW.config_type IN ('ConvoInfoVolatileConfig', 'UserGroupsConfig') AND NEW.id = ''
+BEGIN
+	DELETE FROM conversation_summaries
+	WHERE conversation_id NOT IN (SELECT id FROM conversation_keys);
+
+	DELETE FROM conversation_summaries WHERE conversation_id IN (
+		SELECT coalesce(nullif(jt.value ->> '$.session_id', ''), nullif(jt.value ->> '$.id', ''), nullif(jt.value ->> '$.url', ''))
+		FROM json_each(NEW.value) jt
+	);
+	INSERT INTO conversation_summaries
+	SELECT * FROM conversation_summaries_computed WHERE conversation_id IN (
+		SELECT coalesce(nullif(jt.value ->> '$.session_id', ''), nullif(jt.value ->> '$.id', ''), nullif(jt.value ->> '$.url', ''))
+		FROM json_each(NEW.value) jt
+	);
+END;
+
+-- Only the conversations whose entry changed are recomputed
+CREATE TRIGGER conversation_summaries_config_updated AFTER UPDATE OF value ON configs
+WHEN NEW.config_type IN ('ConvoInfoVolatileConfig', 'UserGroupsConfig') AND NEW.id = ''
+  AND OLD.value IS NOT NEW.value
+BEGIN
+	DELETE FROM conversation_summaries WHERE conversation_id IN (
+		SELECT coalesce(nullif(o.value ->> '$.session_id', ''), nullif(o.value ->> '$.id', ''), nullif(o.value ->> '$.url', ''))
+		FROM json_each(OLD.value) o
+		WHERE o.value NOT IN (SELECT value FROM json_each(NEW.value))
+		UNION
+		SELECT coalesce(nullif(n.value ->> '$.session_id', ''), nullif(n.value ->> '$.id', ''), nullif(n.value ->> '$.url', ''))
+		FROM json_each(NEW.value) n
+		WHERE n.value NOT IN (SELECT value FROM json_each(OLD.value))
+	);
+	INSERT INTO conversation_summaries
+	SELECT * FROM conversation_summaries_computed WHERE conversation_id IN (
+		SELECT coalesce(nullif(n.value ->> '$.session_id', ''), nullif(n.value ->> '$.id', ''), nullif(n.value ->> '$.url', ''))
+		FROM json_each(NEW.value) n
+		WHERE n.value NOT IN (SELECT value FROM json_each(OLD.value))
+		UNION
+		SELECT coalesce(nullif(o.value ->> '$.session_id', ''), nullif(o.value ->> '$.id', ''), nullif(o.value ->> '$.url', ''))
+		FROM json_each(OLD.value) o
+		WHERE o.value NOT IN (SELECT value FROM json_each(NEW.value))
+	);
+END;
+
+CREATE TRIGGER conversation_summaries_config_deleted AFTER DELETE ON configs
+WHEN OLD.config_type IN ('ConvoInfoVolatileConfig', 'UserGroupsConfig') AND OLD.id = ''
+BEGIN
+	DELETE FROM conversation_summaries WHERE conversation_id IN (
+		SELECT coalesce(nullif(jt.value ->> '$.session_id', ''), nullif(jt.value ->> '$.id', ''), nullif(jt.value ->> '$.url', ''))
+		FROM json_each(OLD.value) jt
+	);
+	-- The other config may still know the conversation
+	INSERT INTO conversation_summaries
+	SELECT * FROM conversation_summaries_computed WHERE conversation_id IN (
+		SELECT coalesce(nullif(jt.value ->> '$.session_id', ''), nullif(jt.value ->> '$.id', ''), nullif(jt.value ->> '$.url', ''))
+		FROM json_each(OLD.value) jt
+	);
+END;
+
+-- Our own ID decides which messages are unread
+CREATE TRIGGER conversation_summaries_identity_inserted AFTER INSERT ON app_settings
+WHEN NEW.name = 'identity'
+BEGIN
+	DELETE FROM conversation_summaries;
+	INSERT INTO conversation_summaries SELECT * FROM conversation_summaries_computed;
+END;
+
+CREATE TRIGGER conversation_summaries_identity_deleted AFTER DELETE ON app_settings
+WHEN OLD.name = 'identity'
+BEGIN
+	DELETE FROM conversation_summaries;
+	INSERT INTO conversation_summaries SELECT * FROM conversation_summaries_computed;
+END;
+
+-- Saving the identity again only matters if it's a different one
+CREATE TRIGGER conversation_summaries_identity_updated AFTER UPDATE OF value ON app_settings
+WHEN NEW.name = 'identity' AND OLD.value ->> '$.session_id' IS NOT NEW.value ->> '$.session_id'
+BEGIN
+	DELETE FROM conversation_summaries;
+	INSERT INTO conversation_summaries SELECT * FROM conversation_summaries_computed;
+END;
+
+-- So does our blinded ID in a community, but only for that community
+CREATE TRIGGER conversation_summaries_blinded_id_inserted AFTER INSERT ON app_settings
+WHEN NEW.name = 'blinded_id'
+BEGIN
+	DELETE FROM conversation_summaries WHERE conversation_id = NEW.id;
+	INSERT INTO conversation_summaries
+	SELECT * FROM conversation_summaries_computed WHERE conversation_id = NEW.id;
+END;
+
+CREATE TRIGGER conversation_summaries_blinded_id_deleted AFTER DELETE ON app_settings
+WHEN OLD.name = 'blinded_id'
+BEGIN
+	DELETE FROM conversation_summaries WHERE conversation_id = OLD.id;
+	INSERT INTO conversation_summaries
+	SELECT * FROM conversation_summaries_computed WHERE conversation_id = OLD.id;
+END;
+
+CREATE TRIGGER conversation_summaries_blinded_id_updated AFTER UPDATE OF value ON app_settings
+WHEN NEW.name = 'blinded_id' AND OLD.value IS NOT NEW.value
+BEGIN
+	DELETE FROM conversation_summaries WHERE conversation_id = NEW.id;
+	INSERT INTO conversation_summaries
+	SELECT * FROM conversation_summaries_computed WHERE conversation_id = NEW.id;
+END;
+
+DROP VIEW conversations;
+
+CREATE VIEW conversations AS
+WITH
+convo AS (
+  SELECT
+	coalesce(nullif(session_id, ''), nullif(id, ''), nullif(community_url, '')) AS id,
+	type,
+	unread,
+	last_read
+  FROM config_convo_info
+  WHERE type != 'community'
+  UNION
+  SELECT
+	community_url AS id,
+	type,
+	0 AS unread,
+	0 AS last_read
+  FROM config_user_groups
+  WHERE type = 'community'
+),
+identities AS (
+	SELECT value->>'$.session_id' AS session_id
+	FROM app_settings
+	WHERE name = 'identity' AND id = ''
+	LIMIT 1
+),
+contacts AS (
+	SELECT
+	  coalesce(nullif(nickname, ''), nullif(name, '')) AS display_name,
+	  priority, session_id, approved, approved_me, blocked,
+	  (
+		CASE json_type(profile_picture)
+		 WHEN 'object' THEN json_patch(profile_picture, json_object('fallback_text', coalesce(nullif(nickname, ''), nullif(name, ''))))
+		 ELSE json_object('fallback_text', coalesce(nullif(nickname, ''), nullif(name, '')))
+		END
+	  ) AS avatar
+	FROM config_contacts
+),
+group_members AS (
+	SELECT
+		gm.group_id, gm.session_id, gm.admin, gm.invite_status, gm.promotion_status, gm.removed_status, gm.supplement,
+		(identities.session_id = gm.session_id) AS is_me,
+		json_patch(
+			CASE json_type(gm.profile_picture)
+			 WHEN 'object' THEN gm.profile_picture
+			 ELSE coalesce(contacts.avatar, '{}')
+			END,
+			json_object(
+				'fallback_text', coalesce(contacts.display_name, gm.name),
+				'is_admin', gm.admin,
+				'is_me', identities.session_id = gm.session_id
+			)
+		) AS avatar,
+		coalesce(contacts.display_name, name) AS display_name
+	FROM config_group_members gm
+	LEFT JOIN contacts ON contacts.session_id = gm.session_id
+	LEFT JOIN identities
+),
+ginfo AS (
+	SELECT
+		group_id, name, description, delete_attach_before, delete_before, expiry_timer, created,
+		CASE json_type(g.profile_pic)
+		 WHEN 'object' THEN g.profile_pic
+		 ELSE (
+			SELECT json_group_array(json(avatar))
+			FROM (SELECT * FROM group_members ORDER BY is_me DESC, admin DESC, display_name ASC, session_id ASC LIMIT 10)
+		 )
+		END AS avatar
+	FROM config_group_info g
+)
+SELECT
+	convo.id,
+	coalesce(
+		nullif(contacts.display_name, ''),
+		nullif(cinfo.name, ''),
+		nullif(ginfo.name, ''),
+		nullif(ugroup.name, ''),
+		''
+	) AS name,
+	summary.last_message_created,
+	(
+		CASE
+		  WHEN (convo.type = 'community' AND messages.sender = community_identity.value) OR (messages.sender = identities.session_id) THEN
+			json_object(
+			'from_me', true,
+			'content', messages.content,
+			'created', messages.created_at)
+		  WHEN messages.sender IS NOT NULL THEN
+		    json_object(
+			'sender', coalesce(
+				(SELECT display_name FROM contacts WHERE session_id = messages.sender),
+				messages.sender
+			),
+			'content', messages.content,
+			'created', messages.created_at)
+		  ELSE NULL
+		END
+	) AS last_message,
+	coalesce(contacts.avatar, ginfo.avatar) AS avatar,
+	(
+		CASE convo.type
+		 WHEN 'one_to_one' THEN coalesce(contacts.approved, 0)
+		 WHEN 'group' THEN (gm.invite_status = 3 OR gm.invite_status = 0)
+		 ELSE 1
+		END
+	) AS approved,
+	coalesce(summary.unread_count, 0) AS unread_count
+FROM convo
+LEFT JOIN contacts ON convo.type = 'one_to_one' AND contacts.session_id = convo.id
+LEFT JOIN config_user_groups ugroup ON convo.type = 'group' AND ugroup.id = convo.id AND ugroup.type = 'group'
+LEFT JOIN ginfo ON convo.type = 'group' AND ginfo.group_id = convo.id
+LEFT JOIN config_user_groups cinfo ON convo.type = 'community' AND cinfo.community_url = convo.id AND cinfo.type = 'community'
+LEFT JOIN identities ON convo.type != 'community'
+LEFT JOIN app_settings community_identity ON convo.type = 'community' AND community_identity.name = 'blinded_id' AND community_identity.id = cinfo.community_url
+LEFT JOIN group_members gm ON convo.type = 'group' AND gm.group_id = convo.id AND gm.session_id = identities.session_id
+LEFT JOIN conversation_summaries summary ON summary.conversation_id = convo.id
+LEFT JOIN messages ON messages.id = summary.last_message_id
+WHERE convo.id IS NOT NULL
+GROUP BY convo.id
+ORDER BY coalesce(contacts.priority, 0) DESC, last_message_created DESC, name ASC;
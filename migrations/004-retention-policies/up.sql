@@ -0,0 +1,22 @@
+-- The row with an empty conversation_id is the global policy. A conversation's own policy replaces
+-- the global one entirely. Ages are in milliseconds.
+CREATE TABLE retention_policies (
+    conversation_id TEXT NOT NULL PRIMARY KEY COLLATE NOCASE,
+    max_messages INTEGER DEFAULT NULL CHECK (max_messages IS NULL OR max_messages > 0),
+    max_age INTEGER DEFAULT NULL CHECK (max_age IS NULL OR max_age > 0),
+    attachment_max_age INTEGER DEFAULT NULL CHECK (attachment_max_age IS NULL OR attachment_max_age > 0)
+);
+
+-- Evicted attachments keep their row, minus the content
+ALTER TABLE message_attachments ADD COLUMN evicted_at TIMESTAMP DEFAULT NULL;
+
+CREATE INDEX messages_expiration_at ON messages (expiration_at);
+
+CREATE VIEW message_conversations AS
+SELECT m.id AS message_id, k.id AS conversation_id
+FROM messages m
+JOIN conversation_keys k ON m.receiver = k.id AND (k.type != 'one_to_one' OR m.sender = k.my_id)
+UNION
+SELECT m.id AS message_id, k.id AS conversation_id
+FROM messages m
+JOIN conversation_keys k ON k.type = 'one_to_one' AND m.sender = k.id AND m.receiver = k.my_id;
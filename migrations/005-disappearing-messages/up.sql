@@ -0,0 +1,21 @@
+ALTER TABLE messages ADD COLUMN expiry_mode TEXT NOT NULL DEFAULT 'none' CHECK (
+    expiry_mode IN ('none', 'after_send', 'after_read')
+);
+
+-- When the after-read countdown started, i.e. when the message was read
+ALTER TABLE messages ADD COLUMN expiry_started_at TIMESTAMP DEFAULT NULL;
+
+UPDATE messages SET expiry_mode = (
+    CASE content ->> '$.expirationType'
+     WHEN 'DELETE_AFTER_SEND' THEN 'after_send'
+     WHEN 'DELETE_AFTER_READ' THEN 'after_read'
+     ELSE 'none'
+    END
+)
+WHERE expire_timer IS NOT NULL;
+
+UPDATE messages SET expiration_at = min(expiration_at, created_at + expire_timer * 1000)
+WHERE expiry_mode = 'after_send';
+
+CREATE INDEX messages_after_read_pending ON messages (created_at)
+WHERE expiry_mode = 'after_read' AND expiry_started_at IS NULL;
@@ -0,0 +1,158 @@
+-- Group membership.
+--
+-- Groups we were removed from are kept around, read-only, until we leave them.
+DROP VIEW config_user_groups;
+
+CREATE VIEW config_user_groups AS
+SELECT
+    jt.value ->> '$.id' AS id,
+    jt.value ->> '$.type' AS type,
+    jt.value ->> '$.url' AS community_url,
+    jt.value ->> '$.pub_key' AS community_pub_key,
+    jt.value ->> '$.auth_data' AS auth_data,
+    jt.value ->> '$.secret_key' AS secret_key,
+    jt.value ->> '$.invited' AS invited,
+    jt.value ->> '$.kicked' AS kicked,
+    jt.value ->> '$.joined_at' AS joined_at,
+    jt.value ->> '$.mute_until' AS mute_until,
+    jt.value ->> '$.name' AS name,
+    jt.value ->> '$.notifications' AS notifications,
+    jt.value ->> '$.priority' AS priority
+FROM
+    configs c,
+    json_each (c.value) jt
+WHERE
+    c.config_type = 'UserGroupsConfig'
+    AND c.id = '';
+
+DROP VIEW conversations;
+
+CREATE VIEW conversations AS
+WITH
+convo AS (
+  SELECT
+	coalesce(nullif(session_id, ''), nullif(id, ''), nullif(community_url, '')) AS id,
+	type,
+	unread,
+	last_read
+  FROM config_convo_info
+  WHERE type != 'community'
+  UNION
+  SELECT
+	community_url AS id,
+	type,
+	0 AS unread,
+	0 AS last_read
+  FROM config_user_groups
+  WHERE type = 'community'
+),
+identities AS (
+	SELECT value->>'$.session_id' AS session_id
+	FROM app_settings
+	WHERE name = 'identity' AND id = ''
+	LIMIT 1
+),
+contacts AS (
+	SELECT
+	  coalesce(nullif(nickname, ''), nullif(name, '')) AS display_name,
+	  priority, session_id, approved, approved_me, blocked,
+	  (
+		CASE json_type(profile_picture)
+		 WHEN 'object' THEN json_patch(profile_picture, json_object('fallback_text', coalesce(nullif(nickname, ''), nullif(name, ''))))
+		 ELSE json_object('fallback_text', coalesce(nullif(nickname, ''), nullif(name, '')))
+		END
+	  ) AS avatar
+	FROM config_contacts
+),
+group_members AS (
+	SELECT
+		gm.group_id, gm.session_id, gm.admin, gm.invite_status, gm.promotion_status, gm.removed_status, gm.supplement,
+		(identities.session_id = gm.session_id) AS is_me,
+		json_patch(
+			CASE json_type(gm.profile_picture)
+			 WHEN 'object' THEN gm.profile_picture
+			 ELSE coalesce(contacts.avatar, '{}')
+			END,
+			json_object(
+				'fallback_text', coalesce(contacts.display_name, gm.name),
+				'is_admin', gm.admin,
+				'is_me', identities.session_id = gm.session_id
+			)
+		) AS avatar,
+		coalesce(contacts.display_name, name) AS display_name
+	FROM config_group_members gm
+	LEFT JOIN contacts ON contacts.session_id = gm.session_id
+	LEFT JOIN identities
+),
+ginfo AS (
+	SELECT
+		group_id, name, description, delete_attach_before, delete_before, expiry_timer, created,
+		CASE json_type(g.profile_pic)
+		 WHEN 'object' THEN g.profile_pic
+		 ELSE (
+			SELECT json_group_array(json(avatar))
+			FROM (SELECT * FROM group_members ORDER BY is_me DESC, admin DESC, display_name ASC, session_id ASC LIMIT 10)
+		 )
+		END AS avatar
+	FROM config_group_info g
+)
+SELECT
+	convo.id,
+	coalesce(
+		nullif(contacts.display_name, ''),
+		nullif(cinfo.name, ''),
+		nullif(ginfo.name, ''),
+		nullif(ugroup.name, ''),
+		''
+	) AS name,
+	summary.last_message_created,
+	(
+		CASE
+		  WHEN (convo.type = 'community' AND messages.sender = community_identity.value) OR (messages.sender = identities.session_id) THEN
+			json_object(
+			'from_me', true,
+			'content', messages.content,
+			'body', messages.body,
+			'attachment_count', messages.attachment_count,
+			'created', messages.created_at)
+		  WHEN messages.sender IS NOT NULL THEN
+		    json_object(
+			'sender', coalesce(
+				(SELECT display_name FROM contacts WHERE session_id = messages.sender),
+				messages.sender
+			),
+			'content', messages.content,
+			'body', messages.body,
+			'attachment_count', messages.attachment_count,
+			'created', messages.created_at)
+		  ELSE NULL
+		END
+	) AS last_message,
+	coalesce(contacts.avatar, ginfo.avatar) AS avatar,
+	(
+		CASE convo.type
+		 WHEN 'one_to_one' THEN coalesce(contacts.approved, 0)
+		 -- Until we accept the invite
+		 WHEN 'group' THEN NOT coalesce(ugroup.invited, 0)
+		 ELSE 1
+		END
+	) AS approved,
+	coalesce(summary.unread_count, 0) AS unread_count,
+	(
+		CASE convo.type
+		 WHEN 'group' THEN coalesce(ugroup.kicked, 0)
+		 ELSE 0
+		END
+	) AS read_only
+FROM convo
+LEFT JOIN contacts ON convo.type = 'one_to_one' AND contacts.session_id = convo.id
+LEFT JOIN config_user_groups ugroup ON convo.type = 'group' AND ugroup.id = convo.id AND ugroup.type = 'group'
+LEFT JOIN ginfo ON convo.type = 'group' AND ginfo.group_id = convo.id
+LEFT JOIN config_user_groups cinfo ON convo.type = 'community' AND cinfo.community_url = convo.id AND cinfo.type = 'community'
+LEFT JOIN identities ON convo.type != 'community'
+LEFT JOIN app_settings community_identity ON convo.type = 'community' AND community_identity.name = 'blinded_id' AND community_identity.id = cinfo.community_url
+LEFT JOIN conversation_summaries summary ON summary.conversation_id = convo.id
+LEFT JOIN messages ON messages.id = summary.last_message_id
+WHERE convo.id IS NOT NULL
+GROUP BY convo.id
+ORDER BY coalesce(contacts.priority, 0) DESC, last_message_created DESC, name ASC;
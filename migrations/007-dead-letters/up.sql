@@ -0,0 +1,20 @@
+-- Messages we couldn't decrypt.
+--
+-- Group messages regularly arrive before the keys that decrypt them. They wait here until the
+-- keys change, as the poller has already moved past their hash.
+CREATE TABLE dead_letters (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    source TEXT NOT NULL COLLATE NOCASE,
+    namespace INTEGER NOT NULL,
+    hash TEXT NOT NULL,
+    data BLOB NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    expiration_at TIMESTAMP NOT NULL,
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    last_attempt TIMESTAMP NOT NULL
+);
+
+CREATE UNIQUE INDEX dead_letters_source_namespace_hash ON dead_letters (source, namespace, hash);
+
+CREATE INDEX dead_letters_expiration_at ON dead_letters (expiration_at);
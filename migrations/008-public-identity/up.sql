@@ -0,0 +1,7 @@
+-- The identity setting only keeps the public keys.
+--
+-- The secret key lives in the passphrase protected keystore, it must not sit in the database in
+-- plain text next to it.
+UPDATE app_settings
+SET value = json_remove(value, '$.ed25519_sec_key')
+WHERE name = 'identity';
@@ -114,7 +114,7 @@ impl<C: Config + AsRef<bindings::config_object>> super::ConfigExt for C {
 
 impl bindings::config_push_data {
     pub fn config_data(&self) -> &[u8] {
-        unsafe { std::slice::from_raw_parts(self.config, self.config as usize) }
+        unsafe { std::slice::from_raw_parts(self.config, self.config_len) }
     }
 
     pub fn obsolete_message_hashes(&self) -> impl Iterator<Item = &CStr> {
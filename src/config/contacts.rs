@@ -1 +1,249 @@
+use super::{ConfigExt, ContactsConfig};
+use crate::bindings;
+use crate::cwrapper::CIteratorWrapper;
+use crate::db::models::{ExpiryMode, ExpirySetting};
+use crate::session_id::IndividualID;
+use crate::utils::CArrayExt;
+use anyhow::{bail, Context};
+use derive_more::Deref;
+use std::fmt::{Debug, Formatter};
+use std::mem::MaybeUninit;
 
+#[derive(Deref, Clone)]
+pub struct Contact {
+    #[deref]
+    contact: bindings::contacts_contact,
+    id: IndividualID,
+}
+
+impl Contact {
+    pub fn new(contact: bindings::contacts_contact) -> Option<Self> {
+        let id = IndividualID::from_c_string_array(&contact.session_id)?;
+        Some(Self { contact, id })
+    }
+
+    pub fn session_id(&self) -> &IndividualID {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        self.contact.name.cstr_to_str().unwrap_or_default()
+    }
+
+    pub fn set_name(&mut self, name: &str) -> anyhow::Result<()> {
+        if !self.contact.name.write_cstr(name) {
+            bail!(
+                "Name is too long, at most {} bytes are allowed",
+                self.contact.name.len() - 1
+            );
+        }
+        Ok(())
+    }
+
+    pub fn nickname(&self) -> &str {
+        self.contact.nickname.cstr_to_str().unwrap_or_default()
+    }
+
+    pub fn set_nickname(&mut self, nickname: &str) -> anyhow::Result<()> {
+        if !self.contact.nickname.write_cstr(nickname) {
+            bail!(
+                "Nickname is too long, at most {} bytes are allowed",
+                self.contact.nickname.len() - 1
+            );
+        }
+        Ok(())
+    }
+
+    pub fn approved(&self) -> bool {
+        self.contact.approved
+    }
+
+    pub fn set_approved(&mut self, approved: bool) {
+        self.contact.approved = approved;
+    }
+
+    pub fn approved_me(&self) -> bool {
+        self.contact.approved_me
+    }
+
+    pub fn set_approved_me(&mut self, approved_me: bool) {
+        self.contact.approved_me = approved_me;
+    }
+
+    pub fn blocked(&self) -> bool {
+        self.contact.blocked
+    }
+
+    pub fn set_blocked(&mut self, blocked: bool) {
+        self.contact.blocked = blocked;
+    }
+
+    pub fn profile_pic(&self) -> Option<&bindings::user_profile_pic> {
+        Some(&self.contact.profile_pic).filter(|p| !p.is_empty())
+    }
+
+    pub fn set_profile_pic(&mut self, pic: bindings::user_profile_pic) {
+        self.contact.profile_pic = pic;
+    }
+
+    /// Negative when hidden, 0 when unpinned, otherwise the higher the more important.
+    pub fn priority(&self) -> i32 {
+        self.contact.priority as i32
+    }
+
+    pub fn set_priority(&mut self, priority: i32) {
+        self.contact.priority = priority as _;
+    }
+
+    pub fn expiry_setting(&self) -> ExpirySetting {
+        ExpirySetting::new(
+            ExpiryMode::from_c(self.contact.exp_mode),
+            self.contact.exp_seconds.try_into().unwrap_or_default(),
+        )
+    }
+
+    pub fn set_expiry_setting(&mut self, setting: ExpirySetting) {
+        self.contact.exp_mode = setting.mode.to_c();
+        self.contact.exp_seconds = setting.timer_seconds.try_into().unwrap_or(i32::MAX);
+    }
+}
+
+impl Debug for Contact {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Contact")
+            .field("session_id", self.session_id())
+            .field("name", &self.name())
+            .field("nickname", &self.nickname())
+            .field("approved", &self.approved())
+            .field("approved_me", &self.approved_me())
+            .field("blocked", &self.blocked())
+            .finish()
+    }
+}
+
+impl ContactsConfig {
+    pub fn contacts(&self) -> impl Iterator<Item = Contact> + 'static {
+        CIteratorWrapper::new(
+            unsafe { bindings::contacts_iterator_new(self.as_ref() as *const _) },
+            bindings::contacts_iterator_free,
+            bindings::contacts_iterator_done,
+            bindings::contacts_iterator_advance,
+        )
+        .filter_map(Contact::new)
+    }
+
+    pub fn get_contact(&self, session_id: &IndividualID) -> Option<Contact> {
+        unsafe {
+            let mut contact = MaybeUninit::zeroed().assume_init();
+            if bindings::contacts_get(
+                self.as_ref() as *const _ as *mut _,
+                &mut contact,
+                session_id.as_c_str().as_ptr(),
+            ) {
+                Contact::new(contact)
+            } else {
+                None
+            }
+        }
+    }
+
+    pub fn get_or_construct_contact(
+        &mut self,
+        session_id: &IndividualID,
+    ) -> anyhow::Result<Contact> {
+        let contact = unsafe {
+            let mut contact = MaybeUninit::zeroed().assume_init();
+            bindings::contacts_get_or_construct(
+                self.as_mut() as *mut _,
+                &mut contact,
+                session_id.as_c_str().as_ptr(),
+            )
+            .then_some(contact)
+        };
+
+        contact.and_then(Contact::new).with_context(|| {
+            format!(
+                "Unable to create contact {session_id}: {}",
+                self.last_error().unwrap_or("Unknown error")
+            )
+        })
+    }
+
+    pub fn set_contact(&mut self, contact: &Contact) {
+        unsafe { bindings::contacts_set(self.as_mut() as *mut _, &contact.contact) }
+    }
+
+    pub fn erase_contact(&mut self, session_id: &IndividualID) -> bool {
+        unsafe { bindings::contacts_erase(self.as_mut() as *mut _, session_id.as_c_str().as_ptr()) }
+    }
+
+    /// The disappearing messages setting of the one-to-one conversation with `session_id`.
+    pub fn expiry_setting(&self, session_id: &IndividualID) -> ExpirySetting {
+        self.get_contact(session_id)
+            .map(|c| c.expiry_setting())
+            .unwrap_or_default()
+    }
+}
+
+impl ExpiryMode {
+    pub fn from_c(mode: bindings::CONVO_EXPIRATION_MODE) -> Self {
+        match mode {
+            bindings::CONVO_EXPIRATION_MODE_CONVO_EXPIRATION_AFTER_SEND => Self::AfterSend,
+            bindings::CONVO_EXPIRATION_MODE_CONVO_EXPIRATION_AFTER_READ => Self::AfterRead,
+            _ => Self::None,
+        }
+    }
+
+    pub fn to_c(self) -> bindings::CONVO_EXPIRATION_MODE {
+        match self {
+            Self::None => bindings::CONVO_EXPIRATION_MODE_CONVO_EXPIRATION_NONE,
+            Self::AfterSend => bindings::CONVO_EXPIRATION_MODE_CONVO_EXPIRATION_AFTER_SEND,
+            Self::AfterRead => bindings::CONVO_EXPIRATION_MODE_CONVO_EXPIRATION_AFTER_READ,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IndividualConfig;
+    use crate::ed25519::gen_pair;
+    use crate::test_utils::BOB;
+
+    #[test]
+    fn contact_edits_round_trip() {
+        let (_, sec_key) = gen_pair();
+        let mut config = ContactsConfig::new(&sec_key, None).unwrap();
+        let bob: IndividualID = BOB.parse().unwrap();
+        assert!(config.get_contact(&bob).is_none());
+
+        let mut contact = config.get_or_construct_contact(&bob).unwrap();
+        contact.set_name("Bob").unwrap();
+        contact.set_nickname("Bobby").unwrap();
+        contact.set_approved(true);
+        contact.set_blocked(true);
+        contact.set_priority(-1);
+        contact.set_expiry_setting(ExpirySetting::new(ExpiryMode::AfterSend, 300));
+        assert!(contact.set_name(&"x".repeat(200)).is_err());
+        config.set_contact(&contact);
+
+        let contact = config.get_contact(&bob).unwrap();
+        assert_eq!(contact.session_id(), &bob);
+        assert_eq!(contact.name(), "Bob");
+        assert_eq!(contact.nickname(), "Bobby");
+        assert!(contact.approved());
+        assert!(!contact.approved_me());
+        assert!(contact.blocked());
+        assert_eq!(contact.priority(), -1);
+        assert!(contact.profile_pic().is_none());
+        assert_eq!(
+            config.expiry_setting(&bob),
+            ExpirySetting::new(ExpiryMode::AfterSend, 300)
+        );
+        assert_eq!(config.contacts().count(), 1);
+
+        assert!(config.erase_contact(&bob));
+        assert!(config.get_contact(&bob).is_none());
+        assert_eq!(config.expiry_setting(&bob), ExpirySetting::default());
+    }
+}
@@ -0,0 +1,281 @@
+use super::ConvoInfoVolatileConfig;
+use crate::bindings;
+use crate::clock::Timestamp;
+use crate::session_id::{GroupID, IndividualID, SessionID};
+use crate::utils::{CArrayExt, StringExt};
+use anyhow::{bail, Context};
+use derive_more::{Deref, DerefMut};
+use std::mem::MaybeUninit;
+
+#[derive(Deref, DerefMut, Clone)]
+pub struct OneToOneConvoInfo(bindings::convo_info_volatile_1to1);
+
+#[derive(Deref, DerefMut, Clone)]
+pub struct GroupConvoInfo(bindings::convo_info_volatile_group);
+
+#[derive(Deref, DerefMut, Clone)]
+pub struct CommunityConvoInfo(bindings::convo_info_volatile_community);
+
+macro_rules! impl_convo_info {
+    ($name:ident) => {
+        impl $name {
+            pub fn last_read(&self) -> Option<Timestamp> {
+                Timestamp::from_mills(self.0.last_read)
+            }
+
+            pub fn set_last_read(&mut self, last_read: Timestamp) {
+                self.0.last_read = last_read.as_millis() as i64;
+            }
+
+            /// Whether the conversation was explicitly marked as unread.
+            pub fn is_marked_unread(&self) -> bool {
+                self.0.unread
+            }
+
+            pub fn set_marked_unread(&mut self, unread: bool) {
+                self.0.unread = unread;
+            }
+
+            /// Moves the read marker forward to `up_to` and clears the unread mark. Returns the
+            /// previous marker in millis, 0 if there was none, if anything changed.
+            fn mark_read(&mut self, up_to: Timestamp) -> Option<u64> {
+                let previous = self.last_read().map_or(0, |t| t.as_millis());
+                if previous >= up_to.as_millis() && !self.is_marked_unread() {
+                    return None;
+                }
+
+                if previous < up_to.as_millis() {
+                    self.set_last_read(up_to);
+                }
+                self.set_marked_unread(false);
+                Some(previous)
+            }
+        }
+    };
+}
+
+impl_convo_info!(OneToOneConvoInfo);
+impl_convo_info!(GroupConvoInfo);
+impl_convo_info!(CommunityConvoInfo);
+
+impl OneToOneConvoInfo {
+    pub fn session_id(&self) -> Option<IndividualID> {
+        self.0.session_id.cstr_to_str()?.parse().ok()
+    }
+}
+
+impl GroupConvoInfo {
+    pub fn group_id(&self) -> Option<GroupID> {
+        self.0.id.cstr_to_str()?.parse().ok()
+    }
+}
+
+impl CommunityConvoInfo {
+    pub fn base_url(&self) -> &str {
+        self.0.base_url.cstr_to_str().unwrap_or_default()
+    }
+
+    pub fn room(&self) -> &str {
+        self.0.room.cstr_to_str().unwrap_or_default()
+    }
+}
+
+impl ConvoInfoVolatileConfig {
+    fn as_mut_ptr_unchecked(&self) -> *mut bindings::config_object {
+        // The getters only touch the error message of the config object
+        self.as_ref() as *const _ as *mut _
+    }
+
+    pub fn get_one_to_one(&self, session_id: &IndividualID) -> Option<OneToOneConvoInfo> {
+        unsafe {
+            let mut info = MaybeUninit::zeroed().assume_init();
+            bindings::convo_info_volatile_get_1to1(
+                self.as_mut_ptr_unchecked(),
+                &mut info,
+                session_id.as_c_str().as_ptr(),
+            )
+            .then(|| OneToOneConvoInfo(info))
+        }
+    }
+
+    pub fn get_or_construct_one_to_one(
+        &mut self,
+        session_id: &IndividualID,
+    ) -> anyhow::Result<OneToOneConvoInfo> {
+        unsafe {
+            let mut info = MaybeUninit::zeroed().assume_init();
+            if !bindings::convo_info_volatile_get_or_construct_1to1(
+                self.as_mut(),
+                &mut info,
+                session_id.as_c_str().as_ptr(),
+            ) {
+                bail!("Unable to create conversation info for {session_id}");
+            }
+            Ok(OneToOneConvoInfo(info))
+        }
+    }
+
+    pub fn set_one_to_one(&mut self, info: &OneToOneConvoInfo) {
+        unsafe { bindings::convo_info_volatile_set_1to1(self.as_mut(), &info.0) }
+    }
+
+    pub fn get_group(&self, group_id: &GroupID) -> Option<GroupConvoInfo> {
+        unsafe {
+            let mut info = MaybeUninit::zeroed().assume_init();
+            bindings::convo_info_volatile_get_group(
+                self.as_mut_ptr_unchecked(),
+                &mut info,
+                group_id.as_c_str().as_ptr(),
+            )
+            .then(|| GroupConvoInfo(info))
+        }
+    }
+
+    pub fn get_or_construct_group(&mut self, group_id: &GroupID) -> anyhow::Result<GroupConvoInfo> {
+        unsafe {
+            let mut info = MaybeUninit::zeroed().assume_init();
+            if !bindings::convo_info_volatile_get_or_construct_group(
+                self.as_mut(),
+                &mut info,
+                group_id.as_c_str().as_ptr(),
+            ) {
+                bail!("Unable to create conversation info for {group_id}");
+            }
+            Ok(GroupConvoInfo(info))
+        }
+    }
+
+    pub fn set_group(&mut self, info: &GroupConvoInfo) {
+        unsafe { bindings::convo_info_volatile_set_group(self.as_mut(), &info.0) }
+    }
+
+    pub fn erase_group(&mut self, group_id: &GroupID) -> bool {
+        unsafe {
+            bindings::convo_info_volatile_erase_group(self.as_mut(), group_id.as_c_str().as_ptr())
+        }
+    }
+
+    pub fn get_community(&self, base_url: &str, room: &str) -> Option<CommunityConvoInfo> {
+        let (base_url, room) = (base_url.to_cstr(), room.to_cstr());
+        unsafe {
+            let mut info = MaybeUninit::zeroed().assume_init();
+            bindings::convo_info_volatile_get_community(
+                self.as_mut_ptr_unchecked(),
+                &mut info,
+                base_url.as_ref().as_ptr(),
+                room.as_ref().as_ptr(),
+            )
+            .then(|| CommunityConvoInfo(info))
+        }
+    }
+
+    pub fn get_or_construct_community(
+        &mut self,
+        base_url: &str,
+        room: &str,
+        pub_key: &[u8; 32],
+    ) -> anyhow::Result<CommunityConvoInfo> {
+        let (c_base_url, c_room) = (base_url.to_cstr(), room.to_cstr());
+        unsafe {
+            let mut info = MaybeUninit::zeroed().assume_init();
+            if !bindings::convo_info_volatile_get_or_construct_community(
+                self.as_mut(),
+                &mut info,
+                c_base_url.as_ref().as_ptr(),
+                c_room.as_ref().as_ptr(),
+                pub_key.as_ptr(),
+            ) {
+                bail!("Unable to create conversation info for {base_url}/{room}");
+            }
+            Ok(CommunityConvoInfo(info))
+        }
+    }
+
+    pub fn set_community(&mut self, info: &CommunityConvoInfo) {
+        unsafe { bindings::convo_info_volatile_set_community(self.as_mut(), &info.0) }
+    }
+
+    /// Marks everything up to `up_to` as read in the conversation, identified the same way as
+    /// in the `conversations` view. Returns the previous read marker in millis if it changed.
+    pub fn mark_read(
+        &mut self,
+        conversation_id: &str,
+        up_to: Timestamp,
+    ) -> anyhow::Result<Option<u64>> {
+        if let Ok(session_id) = conversation_id.parse::<SessionID>() {
+            return match session_id {
+                SessionID::Individual(id) => {
+                    let mut info = self.get_or_construct_one_to_one(&id)?;
+                    let previous = info.mark_read(up_to);
+                    if previous.is_some() {
+                        self.set_one_to_one(&info);
+                    }
+                    Ok(previous)
+                }
+                SessionID::Group(id) => {
+                    let mut info = self.get_or_construct_group(&id)?;
+                    let previous = info.mark_read(up_to);
+                    if previous.is_some() {
+                        self.set_group(&info);
+                    }
+                    Ok(previous)
+                }
+                _ => bail!("Can't mark {conversation_id} as read"),
+            };
+        }
+
+        // Communities are keyed by their room URL. Entries can only be created along with the
+        // community's public key, so the community must have been seen before.
+        let (base_url, room) = conversation_id
+            .rsplit_once('/')
+            .context("Invalid conversation ID")?;
+        let mut info = self
+            .get_community(base_url, room)
+            .with_context(|| format!("Unknown community {conversation_id}"))?;
+        let previous = info.mark_read(up_to);
+        if previous.is_some() {
+            self.set_community(&info);
+        }
+        Ok(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::local_timestamp;
+    use crate::config::IndividualConfig;
+    use crate::ed25519::gen_pair;
+    use crate::test_utils::BOB;
+
+    #[test]
+    fn read_marker_only_moves_forward() {
+        let (_, sec_key) = gen_pair();
+        let mut config = ConvoInfoVolatileConfig::new(&sec_key, None).unwrap();
+        // Markers older than a month are dropped by libsession
+        let now = local_timestamp();
+        let earlier = Timestamp::from_mills(now.as_millis() - 1000).unwrap();
+
+        assert_eq!(config.mark_read(BOB, now).unwrap(), Some(0));
+        assert_eq!(config.mark_read(BOB, earlier).unwrap(), None);
+
+        let bob = BOB.parse().unwrap();
+        let mut info = config.get_one_to_one(&bob).unwrap();
+        assert_eq!(info.last_read(), Some(now));
+        info.set_marked_unread(true);
+        config.set_one_to_one(&info);
+
+        // Clears the unread mark but keeps the later marker
+        assert_eq!(
+            config.mark_read(BOB, earlier).unwrap(),
+            Some(now.as_millis())
+        );
+        let info = config.get_one_to_one(&bob).unwrap();
+        assert!(!info.is_marked_unread());
+        assert_eq!(info.last_read(), Some(now));
+
+        assert!(config
+            .mark_read("https://example.com/unknown", now)
+            .is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use super::{ConfigExt, GroupInfoConfig};
 use crate::bindings;
 use crate::clock::Timestamp;
+use crate::db::models::{ExpiryMode, ExpirySetting};
 use crate::utils::StringExt;
 use anyhow::bail;
 use std::ffi::CStr;
@@ -30,6 +31,33 @@ impl GroupInfoConfig {
         Ok(())
     }
 
+    pub fn description(&self) -> &str {
+        unsafe {
+            let description = bindings::groups_info_get_description(self.as_ref() as *const _);
+            if description.is_null() {
+                return "";
+            }
+            CStr::from_ptr(description).to_str().unwrap_or_default()
+        }
+    }
+
+    pub fn set_description(&mut self, description: &str) -> anyhow::Result<()> {
+        if unsafe {
+            bindings::groups_info_set_description(
+                self.as_mut() as *mut _,
+                description.to_cstr().as_ref().as_ptr(),
+            )
+        } != 0
+        {
+            bail!(
+                "Error setting description: {}",
+                self.last_error().unwrap_or("Unknown error")
+            )
+        }
+
+        Ok(())
+    }
+
     pub fn profile_pic(&self) -> bindings::user_profile_pic {
         unsafe { bindings::groups_info_get_pic(self.as_ref() as *const _) }
     }
@@ -45,6 +73,54 @@ impl GroupInfoConfig {
         Ok(())
     }
 
+    /// Disappearing messages timer in seconds, 0 when off.
+    pub fn expiry_timer(&self) -> u32 {
+        let seconds = unsafe { bindings::groups_info_get_expiry_timer(self.as_ref() as *const _) };
+        seconds.try_into().unwrap_or_default()
+    }
+
+    /// Group messages can only disappear after send.
+    pub fn expiry_setting(&self) -> ExpirySetting {
+        ExpirySetting::new(ExpiryMode::AfterSend, self.expiry_timer())
+    }
+
+    pub fn set_expiry_timer(&mut self, seconds: u32) {
+        unsafe {
+            bindings::groups_info_set_expiry_timer(self.as_mut() as *mut _, seconds as _);
+        }
+    }
+
+    /// Messages sent before this are deleted by every member.
+    pub fn delete_before(&self) -> Option<Timestamp> {
+        let seconds = unsafe { bindings::groups_info_get_delete_before(self.as_ref() as *const _) };
+        seconds_to_timestamp(seconds)
+    }
+
+    pub fn set_delete_before(&mut self, before: Option<Timestamp>) {
+        unsafe {
+            bindings::groups_info_set_delete_before(
+                self.as_mut() as *mut _,
+                before.map_or(0, |t| (t.as_millis() / 1000) as _),
+            );
+        }
+    }
+
+    /// Attachments of messages sent before this are deleted by every member.
+    pub fn delete_attach_before(&self) -> Option<Timestamp> {
+        let seconds =
+            unsafe { bindings::groups_info_get_attach_delete_before(self.as_ref() as *const _) };
+        seconds_to_timestamp(seconds)
+    }
+
+    pub fn set_delete_attach_before(&mut self, before: Option<Timestamp>) {
+        unsafe {
+            bindings::groups_info_set_attach_delete_before(
+                self.as_mut() as *mut _,
+                before.map_or(0, |t| (t.as_millis() / 1000) as _),
+            );
+        }
+    }
+
     pub fn created(&self) -> Option<Timestamp> {
         let created = unsafe { bindings::groups_info_get_created(self.as_ref() as *const _) };
         if created == 0 {
@@ -54,3 +130,43 @@ impl GroupInfoConfig {
         }
     }
 }
+
+fn seconds_to_timestamp(seconds: i64) -> Option<Timestamp> {
+    Timestamp::from_mills(seconds.checked_mul(1000)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GroupConfig;
+    use crate::ed25519::gen_pair;
+    use crate::session_id::GroupID;
+
+    #[test]
+    fn group_info_edits_round_trip() {
+        let (group_key, admin_key) = gen_pair();
+        let group_id = GroupID::from(group_key);
+        let mut config = GroupInfoConfig::new(&group_id, Some(&admin_key), None).unwrap();
+        assert_eq!(config.delete_before(), None);
+        assert_eq!(config.delete_attach_before(), None);
+        assert!(!config.expiry_setting().is_enabled());
+
+        config.set_expiry_timer(3600);
+        assert_eq!(config.expiry_timer(), 3600);
+        assert_eq!(
+            config.expiry_setting(),
+            ExpirySetting::new(ExpiryMode::AfterSend, 3600)
+        );
+
+        // Only whole seconds are kept
+        let before = Timestamp::from_mills(1_700_000_000_500u64).unwrap();
+        let second = Timestamp::from_mills(1_700_000_000_000u64).unwrap();
+        config.set_delete_before(Some(before));
+        config.set_delete_attach_before(Some(before));
+        assert_eq!(config.delete_before(), Some(second));
+        assert_eq!(config.delete_attach_before(), Some(second));
+
+        config.set_delete_before(None);
+        assert_eq!(config.delete_before(), None);
+    }
+}
@@ -5,7 +5,7 @@ use crate::config::{GroupAuthData, GroupInfoConfig, GroupMemberConfig};
 use crate::cwrapper::{CArrayWrapper, CWrapper};
 use crate::ed25519::{ED25519PubKey, ED25519SecKey};
 use crate::oxenss::retrieve::Message;
-use crate::session_id::SessionID;
+use crate::session_id::{IndividualID, SessionID};
 use crate::utils::{CArrayExt, StringExt};
 use anyhow::{bail, Context};
 use serde::Serialize;
@@ -16,9 +16,12 @@ use std::ptr::{null, null_mut};
 pub struct GroupKeys {
     wrapper: CWrapper<bindings::config_group_keys>,
     group_pub_key: ED25519PubKey,
+    // The pending config stays around until we see it back from the swarm
+    pushed_config: Option<Vec<u8>>,
 }
 
 pub type AdminKey = [u8; 32];
+pub type SubaccountToken = [u8; 36];
 
 #[derive(Serialize)]
 pub struct SubaccountAuth {
@@ -77,6 +80,7 @@ impl GroupKeys {
             .map(|wrapper| Self {
                 wrapper,
                 group_pub_key: group_pub_key.clone(),
+                pushed_config: None,
             })
             .context("Failed to create GroupKeys instance")
     }
@@ -127,6 +131,38 @@ impl GroupKeys {
         CArrayWrapper::new(plaintext, plaintext_len).map(|plaintext| (session_id, plaintext))
     }
 
+    /// Creates the auth data a member needs to access the group swarm. Admins only.
+    pub fn make_subaccount(&self, member: &IndividualID) -> anyhow::Result<GroupAuthData> {
+        let mut auth_data = [0u8; 100];
+        if !unsafe {
+            bindings::groups_keys_swarm_make_subaccount(
+                self.wrapper.as_mut_ptr(),
+                member.as_c_str().as_ptr(),
+                auth_data.as_mut_ptr(),
+            )
+        } {
+            bail!("Error creating sub-account for {member}");
+        }
+
+        Ok(auth_data)
+    }
+
+    /// The token the swarm knows a member's sub-account by, used to revoke it.
+    pub fn subaccount_token(&self, member: &IndividualID) -> anyhow::Result<SubaccountToken> {
+        let mut token = [0u8; 36];
+        if !unsafe {
+            bindings::groups_keys_swarm_subaccount_token(
+                self.wrapper.as_mut_ptr(),
+                member.as_c_str().as_ptr(),
+                token.as_mut_ptr(),
+            )
+        } {
+            bail!("Error getting sub-account token for {member}");
+        }
+
+        Ok(token)
+    }
+
     pub fn sub_key_sign(
         &self,
         data: &[u8],
@@ -160,10 +196,36 @@ impl GroupKeys {
         Ok(auth)
     }
 
+    /// Makes us an admin of the group, after being promoted.
+    pub fn load_admin_key(
+        &mut self,
+        sec_key: &ED25519SecKey,
+        info: &mut GroupInfoConfig,
+        members: &mut GroupMemberConfig,
+    ) -> anyhow::Result<()> {
+        if !unsafe {
+            bindings::groups_keys_load_admin_key(
+                self.wrapper.as_mut(),
+                sec_key.as_ptr(),
+                info.as_mut(),
+                members.as_mut(),
+            )
+        } {
+            bail!("Error loading admin key");
+        }
+
+        Ok(())
+    }
+
     pub fn is_admin(&self) -> bool {
         unsafe { bindings::groups_keys_is_admin(self.wrapper.as_ptr()) }
     }
 
+    /// How many of the group's keys we can decrypt with.
+    pub fn key_count(&self) -> usize {
+        unsafe { bindings::groups_keys_size(self.wrapper.as_ptr()) }
+    }
+
     pub fn rekey(
         &mut self,
         info: &mut GroupInfoConfig,
@@ -188,16 +250,18 @@ impl GroupKeys {
         Ok(unsafe { std::slice::from_raw_parts(out, out_len) })
     }
 
-    fn pending_config(&self) -> Option<CArrayWrapper<u8>> {
+    /// Borrowed from libsession, valid until the keys change.
+    fn pending_config(&self) -> Option<&[u8]> {
         let mut data = null();
         let mut data_len = 0;
         if !unsafe {
             bindings::groups_keys_pending_config(self.wrapper.as_ptr(), &mut data, &mut data_len)
-        } {
+        } || data.is_null()
+        {
             return None;
         }
 
-        CArrayWrapper::new(data as *mut c_uchar, data_len)
+        Some(unsafe { std::slice::from_raw_parts(data, data_len) })
     }
 }
 
@@ -207,7 +271,7 @@ impl super::NamedConfig for GroupKeys {
 
 impl super::Config for GroupKeys {
     type MergeArg<'a> = (&'a mut GroupInfoConfig, &'a mut GroupMemberConfig);
-    type PushData = Option<CArrayWrapper<u8>>;
+    type PushData = Option<Vec<u8>>;
 
     fn merge<'a>(
         &mut self,
@@ -245,13 +309,16 @@ impl super::Config for GroupKeys {
     }
 
     fn push(&mut self) -> anyhow::Result<Self::PushData> {
-        Ok(self.pending_config())
+        Ok(self.pending_config().map(<[u8]>::to_vec))
     }
 
-    fn confirm_pushed(&mut self, _seq: seqno_t, _msg_hash: &str) {}
+    fn confirm_pushed(&mut self, _seq: seqno_t, _msg_hash: &str) {
+        self.pushed_config = self.pending_config().map(<[u8]>::to_vec);
+    }
 
     fn needs_push(&self) -> bool {
-        self.pending_config().is_some()
+        self.pending_config()
+            .is_some_and(|c| self.pushed_config.as_deref() != Some(c))
     }
 
     fn needs_dump(&self) -> bool {
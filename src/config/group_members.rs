@@ -3,10 +3,41 @@ use crate::bindings;
 use crate::cwrapper::CIteratorWrapper;
 use crate::session_id::IndividualID;
 use crate::utils::CArrayExt;
+use anyhow::bail;
 use derive_more::Deref;
+use std::ffi::c_int;
 use std::fmt::{Debug, Formatter};
 use std::mem::MaybeUninit;
 
+/// Where an invitation or a promotion is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberStatus {
+    Accepted,
+    Sent,
+    Failed,
+    NotSent,
+}
+
+impl MemberStatus {
+    fn from_c(status: c_int) -> Self {
+        match status {
+            1 => Self::Sent,
+            2 => Self::Failed,
+            3 => Self::NotSent,
+            _ => Self::Accepted,
+        }
+    }
+
+    fn to_c(self) -> c_int {
+        match self {
+            Self::Accepted => 0,
+            Self::Sent => 1,
+            Self::Failed => 2,
+            Self::NotSent => 3,
+        }
+    }
+}
+
 #[derive(Deref)]
 pub struct GroupMember {
     #[deref]
@@ -24,9 +55,44 @@ impl GroupMember {
         self.member.name.cstr_to_str().unwrap_or_default()
     }
 
+    pub fn set_name(&mut self, name: &str) -> anyhow::Result<()> {
+        if !self.member.name.write_cstr(name) {
+            bail!(
+                "Name is too long, at most {} bytes are allowed",
+                self.member.name.len() - 1
+            );
+        }
+        Ok(())
+    }
+
     pub fn session_id(&self) -> &IndividualID {
         &self.id
     }
+
+    pub fn is_admin(&self) -> bool {
+        self.member.admin
+    }
+
+    pub fn set_admin(&mut self, admin: bool) {
+        self.member.admin = admin;
+    }
+
+    pub fn invite_status(&self) -> MemberStatus {
+        MemberStatus::from_c(self.member.invited as c_int)
+    }
+
+    pub fn set_invite_status(&mut self, status: MemberStatus) {
+        self.member.invited = status.to_c() as _;
+    }
+
+    /// Only meaningful for admins; `Accepted` once they have the group's secret key.
+    pub fn promotion_status(&self) -> MemberStatus {
+        MemberStatus::from_c(self.member.promoted as c_int)
+    }
+
+    pub fn set_promotion_status(&mut self, status: MemberStatus) {
+        self.member.promoted = status.to_c() as _;
+    }
 }
 
 impl Debug for GroupMember {
@@ -34,6 +100,9 @@ impl Debug for GroupMember {
         f.debug_struct("GroupMember")
             .field("name", &self.name())
             .field("session_id", &self.session_id())
+            .field("admin", &self.is_admin())
+            .field("invite_status", &self.invite_status())
+            .field("promotion_status", &self.promotion_status())
             .finish()
     }
 }
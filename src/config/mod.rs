@@ -2,6 +2,7 @@ use crate::bindings;
 
 mod config_auto_impl;
 mod contacts;
+mod convo_info_volatile;
 mod group_info;
 mod group_keys;
 mod group_members;
@@ -11,7 +12,10 @@ mod user_groups;
 mod user_profile;
 
 use crate::oxenss::retrieve::Message;
+pub use contacts::*;
+pub use convo_info_volatile::*;
 pub use group_keys::*;
+pub use group_members::*;
 pub use groups::*;
 pub use individuals::*;
 pub use user_groups::*;
@@ -6,7 +6,7 @@ use crate::ed25519::ED25519SecKey;
 use crate::session_id::GroupID;
 use crate::utils::CArrayExt;
 use anyhow::bail;
-use derive_more::{Deref, DerefMut};
+use derive_more::{Deref, DerefMut, From};
 use std::ffi::{c_char, CStr};
 use std::mem::MaybeUninit;
 
@@ -14,7 +14,7 @@ struct GroupIter(CWrapper<bindings::user_groups_iterator>);
 
 pub struct LegacyGroupInfo(OwnedCWrapper<bindings::ugroups_legacy_group_info>);
 
-#[derive(Deref, DerefMut, Eq, PartialEq, Clone)]
+#[derive(Deref, DerefMut, From, Eq, PartialEq, Clone)]
 pub struct GroupInfo(bindings::ugroups_group_info);
 
 #[derive(Deref, DerefMut)]
@@ -64,6 +64,11 @@ impl GroupInfo {
         }
     }
 
+    pub fn set_sec_key(&mut self, sec_key: &ED25519SecKey) {
+        self.0.secretkey.copy_from_slice(sec_key);
+        self.0.have_secretkey = true;
+    }
+
     pub fn clear_sec_key(&mut self) {
         self.0.secretkey.fill(0);
         self.0.have_secretkey = false;
@@ -77,11 +82,25 @@ impl GroupInfo {
         }
     }
 
+    pub fn set_auth_data(&mut self, auth_data: &GroupAuthData) {
+        self.auth_data = *auth_data;
+        self.have_auth_data = true;
+    }
+
     pub fn clear_auth_data(&mut self) {
         self.auth_data.fill(0);
         self.have_auth_data = false;
     }
 
+    /// Whether we were invited to the group but haven't accepted yet.
+    pub fn is_invited(&self) -> bool {
+        self.invited
+    }
+
+    pub fn set_invited(&mut self, invited: bool) {
+        self.invited = invited;
+    }
+
     pub fn joined_at(&self) -> Option<Timestamp> {
         Timestamp::from_mills(self.joined_at)
     }
@@ -176,6 +195,18 @@ impl super::UserGroupsConfig {
         }
     }
 
+    pub fn get_group(&self, group_id: &GroupID) -> Option<GroupInfo> {
+        unsafe {
+            let mut out: bindings::ugroups_group_info = MaybeUninit::zeroed().assume_init();
+            bindings::user_groups_get_group(
+                self.as_ref() as *const _ as *mut _,
+                &mut out,
+                group_id.as_c_str().as_ptr(),
+            )
+            .then(|| GroupInfo(out))
+        }
+    }
+
     pub fn get_or_create_group(
         &mut self,
         group_id: &str,
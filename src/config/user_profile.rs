@@ -1,7 +1,8 @@
 use crate::bindings;
 use crate::clock::Timestamp;
 use crate::config::ConfigExt;
-use crate::utils::CArrayExt;
+use crate::db::models::{ExpiryMode, ExpirySetting};
+use crate::utils::{CArrayExt, StringExt};
 use anyhow::bail;
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
@@ -9,6 +10,23 @@ use std::ffi::CStr;
 use url::Url;
 
 impl crate::bindings::user_profile_pic {
+    /// No picture. Setting this removes the current one.
+    pub fn empty() -> Self {
+        Self {
+            url: std::array::from_fn(|_| 0),
+            key: [0; 32],
+        }
+    }
+
+    pub fn new(url: &Url, key: [u8; 32]) -> anyhow::Result<Self> {
+        let mut pic = Self::empty();
+        if !pic.url.write_cstr(url.as_str()) {
+            bail!("Profile picture URL is too long");
+        }
+        pic.key = key;
+        Ok(pic)
+    }
+
     pub fn url(&self) -> Option<Url> {
         let url_str = self.url.cstr_to_str()?;
         url_str.parse().ok()
@@ -46,6 +64,23 @@ impl super::UserProfileConfig {
         }
     }
 
+    pub fn set_name(&mut self, name: &str) -> anyhow::Result<()> {
+        let c_name = name.to_cstr();
+        if unsafe {
+            crate::bindings::user_profile_set_name(
+                self.as_mut() as *mut _,
+                c_name.as_ref().as_ptr(),
+            )
+        } != 0
+        {
+            bail!(
+                "Failed to set name: {}",
+                self.last_error().unwrap_or("Unknown error")
+            );
+        }
+        Ok(())
+    }
+
     pub fn set_profile_pic(
         &mut self,
         pic: crate::bindings::user_profile_pic,
@@ -70,15 +105,47 @@ impl super::UserProfileConfig {
         }
     }
 
+    /// `None` leaves the choice to the client's default.
+    pub fn set_accepts_blinded_msgreqs(&mut self, accepts: Option<bool>) {
+        let value = match accepts {
+            None => -1,
+            Some(false) => 0,
+            Some(true) => 1,
+        };
+        unsafe { bindings::user_profile_set_blinded_msgreqs(self.as_mut() as *mut _, value) }
+    }
+
     pub fn nts_expiry(&self) -> Option<Timestamp> {
         Timestamp::from_mills(unsafe {
             bindings::user_profile_get_nts_expiry(self.as_ref() as *const _)
         })
     }
 
+    /// Note to self only supports disappearing after send.
+    pub fn nts_expiry_setting(&self) -> ExpirySetting {
+        let seconds = unsafe { bindings::user_profile_get_nts_expiry(self.as_ref() as *const _) };
+        ExpirySetting::new(
+            ExpiryMode::AfterSend,
+            seconds.try_into().unwrap_or_default(),
+        )
+    }
+
+    pub fn set_nts_expiry_timer(&mut self, timer_seconds: u32) {
+        unsafe {
+            bindings::user_profile_set_nts_expiry(
+                self.as_mut() as *mut _,
+                timer_seconds.try_into().unwrap_or(i32::MAX),
+            )
+        }
+    }
+
     pub fn nts_priority(&self) -> isize {
         unsafe { bindings::user_profile_get_nts_priority(self.as_ref() as *const _) as isize }
     }
+
+    pub fn set_nts_priority(&mut self, priority: isize) {
+        unsafe { bindings::user_profile_set_nts_priority(self.as_mut() as *mut _, priority as _) }
+    }
 }
 
 impl Serialize for super::UserProfileConfig {
@@ -95,3 +162,50 @@ impl Serialize for super::UserProfileConfig {
         st.end()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::bindings::user_profile_pic;
+    use crate::config::{IndividualConfig, UserProfileConfig};
+    use crate::db::models::{ExpiryMode, ExpirySetting};
+    use crate::ed25519::gen_pair;
+    use url::Url;
+
+    #[test]
+    fn profile_edits_round_trip() {
+        let (_, sec_key) = gen_pair();
+        let mut config = UserProfileConfig::new(&sec_key, None).unwrap();
+
+        config.set_name("Alice").unwrap();
+        assert_eq!(config.name(), "Alice");
+
+        let url: Url = "http://example.com/file/1".parse().unwrap();
+        config
+            .set_profile_pic(user_profile_pic::new(&url, [7; 32]).unwrap())
+            .unwrap();
+        let pic = config.profile_pic().unwrap();
+        assert_eq!(pic.url(), Some(url));
+        assert_eq!(pic.key, [7; 32]);
+
+        config.set_profile_pic(user_profile_pic::empty()).unwrap();
+        assert!(config.profile_pic().unwrap().is_empty());
+
+        let long_url: Url = format!("http://example.com/{}", "x".repeat(300))
+            .parse()
+            .unwrap();
+        assert!(user_profile_pic::new(&long_url, [0; 32]).is_err());
+
+        config.set_accepts_blinded_msgreqs(Some(false));
+        assert_eq!(config.accepts_blinded_msgreqs(), Some(false));
+        config.set_accepts_blinded_msgreqs(None);
+        assert_eq!(config.accepts_blinded_msgreqs(), None);
+
+        config.set_nts_expiry_timer(60);
+        assert_eq!(
+            config.nts_expiry_setting(),
+            ExpirySetting::new(ExpiryMode::AfterSend, 60)
+        );
+        config.set_nts_expiry_timer(0);
+        assert!(!config.nts_expiry_setting().is_enabled());
+    }
+}
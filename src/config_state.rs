@@ -6,13 +6,17 @@ use crate::oxenss::namespace::{
     MessageNamespace, UserGroupsConfigNamespace, UserProfileConfigNamespace,
 };
 
+use crate::clock::Timestamp;
 use crate::config::{
-    Config, ContactsConfig, ConvoInfoVolatileConfig, IndividualConfig, UserGroupsConfig,
-    UserProfileConfig,
+    Config, Contact, ContactsConfig, ConvoInfoVolatileConfig, Group, GroupInfo, IndividualConfig,
+    UserGroupsConfig, UserProfileConfig,
 };
-use crate::db::config::ConfigRepositoryExt;
+use crate::db::config::{ConfigRecord, ConfigRepositoryExt};
+use crate::db::models::ExpirySetting;
 use crate::db::Repository;
 use crate::ed25519::ED25519SecKey;
+use crate::groups::{GroupInvite, GroupPromotion};
+use crate::session_id::{GroupID, IndividualID};
 
 pub struct ConfigState {
     pub user_profile_config: watch::Sender<UserProfileConfig>,
@@ -22,32 +26,214 @@ pub struct ConfigState {
 }
 
 impl ConfigState {
-    fn new_config_from_db_dump<C: IndividualConfig>(
-        repo: &Repository,
+    async fn config_dump<C: IndividualConfig>(repo: &Repository) -> anyhow::Result<Vec<u8>> {
+        let name = C::CONFIG_TYPE_NAME;
+        repo.read(move |conn| conn.get_config_dump(name, None))
+            .await
+            .with_context(|| format!("Getting {name}'s dump from db"))
+    }
+
+    fn new_config_from_dump<C: IndividualConfig>(
         key: &ED25519SecKey,
+        dump: &[u8],
     ) -> anyhow::Result<C> {
         let name = C::CONFIG_TYPE_NAME;
-        let dump = repo
-            .obtain_connection()?
-            .get_config_dump(name, None)
-            .with_context(|| format!("Getting {name}'s dump from db"))?;
-
-        C::new(key, Some(dump.as_slice()))
-            .with_context(|| format!("Unable to create {name} from dump"))
+        C::new(key, Some(dump)).with_context(|| format!("Unable to create {name} from dump"))
     }
 
-    pub fn new(repo: &Repository, sec_key: &ED25519SecKey) -> anyhow::Result<Self> {
+    pub async fn new(repo: &Repository, sec_key: &ED25519SecKey) -> anyhow::Result<Self> {
+        let (user_profile, user_groups, convo_info_volatile, contacts) = try_join!(
+            Self::config_dump::<UserProfileConfig>(repo),
+            Self::config_dump::<UserGroupsConfig>(repo),
+            Self::config_dump::<ConvoInfoVolatileConfig>(repo),
+            Self::config_dump::<ContactsConfig>(repo),
+        )?;
+
         Ok(Self {
-            user_profile_config: watch::channel(Self::new_config_from_db_dump(repo, sec_key)?).0,
-            user_groups_config: watch::channel(Self::new_config_from_db_dump(repo, sec_key)?).0,
-            convo_info_volatile_config: watch::channel(Self::new_config_from_db_dump(
-                repo, sec_key,
+            user_profile_config: watch::channel(Self::new_config_from_dump(
+                sec_key,
+                &user_profile,
             )?)
             .0,
-            contacts_config: watch::channel(Self::new_config_from_db_dump(repo, sec_key)?).0,
+            user_groups_config: watch::channel(Self::new_config_from_dump(sec_key, &user_groups)?)
+                .0,
+            convo_info_volatile_config: watch::channel(Self::new_config_from_dump(
+                sec_key,
+                &convo_info_volatile,
+            )?)
+            .0,
+            contacts_config: watch::channel(Self::new_config_from_dump(sec_key, &contacts)?).0,
         })
     }
 
+    /// The disappearing messages setting of a one-to-one conversation, or note to self.
+    pub fn expiry_setting(&self, me: &IndividualID, other: &IndividualID) -> ExpirySetting {
+        if me == other {
+            self.user_profile_config.borrow().nts_expiry_setting()
+        } else {
+            self.contacts_config.borrow().expiry_setting(other)
+        }
+    }
+
+    /// Marks the conversation as read up to `up_to`. Returns the previous read marker in millis
+    /// if it moved; the change is then saved and pushed like any other config change.
+    pub fn mark_read(
+        &self,
+        conversation_id: &str,
+        up_to: Timestamp,
+    ) -> anyhow::Result<Option<u64>> {
+        let mut result = Ok(None);
+        self.convo_info_volatile_config.send_if_modified(|c| {
+            result = c.mark_read(conversation_id, up_to);
+            matches!(result, Ok(Some(_)))
+        });
+        result
+    }
+
+    /// Changes the contact, creating it if needed.
+    pub fn update_contact(
+        &self,
+        session_id: &IndividualID,
+        update: impl FnOnce(&mut Contact) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Contact> {
+        let mut result = Err(anyhow::anyhow!("Contact not updated"));
+        self.contacts_config.send_if_modified(|c| {
+            result = c
+                .get_or_construct_contact(session_id)
+                .and_then(|mut contact| {
+                    update(&mut contact)?;
+                    c.set_contact(&contact);
+                    Ok(contact)
+                });
+            result.is_ok()
+        });
+        result
+    }
+
+    /// Lists the group we were invited to as pending, until the invite is accepted or declined.
+    pub fn add_pending_group(&self, group_id: &GroupID, name: &str) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        self.user_groups_config.send_if_modified(|c| {
+            if c.get_group(group_id).is_some() {
+                return false;
+            }
+
+            result = c.get_or_create_group(group_id.as_str()).and_then(|info| {
+                let mut info = GroupInfo::from(info);
+                info.set_name(name);
+                info.set_invited(true);
+                c.set_group(&Group::Group(info))
+            });
+            result.is_ok()
+        });
+        result?;
+
+        self.add_group_conversation(group_id)
+    }
+
+    /// Joins the group with the auth data from its invite.
+    pub fn accept_group_invite(&self, invite: &GroupInvite) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        self.user_groups_config.send_if_modified(|c| {
+            result = c
+                .get_or_create_group(invite.group_id.as_str())
+                .and_then(|info| {
+                    let mut info = GroupInfo::from(info);
+                    if info.name().is_empty() {
+                        info.set_name(&invite.name);
+                    }
+                    info.set_invited(false);
+                    info.set_auth_data(&invite.auth_data);
+                    c.set_group(&Group::Group(info))
+                });
+            result.is_ok()
+        });
+        result?;
+
+        self.add_group_conversation(&invite.group_id)
+    }
+
+    /// Stores the group's secret key, which makes us sign as an admin from now on.
+    pub fn accept_group_promotion(&self, promotion: &GroupPromotion) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        self.user_groups_config.send_if_modified(|c| {
+            let Some(mut info) = c.get_group(&promotion.group_id) else {
+                result = Err(anyhow::anyhow!("Not in group {}", promotion.group_id));
+                return false;
+            };
+
+            if info.sec_key().as_ref() == Some(&promotion.sec_key) {
+                return false;
+            }
+
+            if info.name().is_empty() {
+                info.set_name(&promotion.name);
+            }
+            info.set_sec_key(&promotion.sec_key);
+            result = c.set_group(&Group::Group(info));
+            result.is_ok()
+        });
+        result
+    }
+
+    /// Keeps the group around as read-only once we're no longer a member.
+    pub fn mark_group_kicked(&self, group_id: &GroupID) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        self.user_groups_config.send_if_modified(|c| {
+            let Some(mut info) = c.get_group(group_id) else {
+                return false;
+            };
+
+            if info.is_kicked() {
+                return false;
+            }
+
+            info.set_kicked();
+            result = c.set_group(&Group::Group(info));
+            result.is_ok()
+        });
+        result
+    }
+
+    /// Forgets the group, which also stops its swarm from being polled.
+    pub fn remove_group(&self, group_id: &GroupID) {
+        self.user_groups_config
+            .send_modify(|c| c.remove_group(group_id));
+        self.convo_info_volatile_config
+            .send_if_modified(|c| c.erase_group(group_id));
+    }
+
+    fn add_group_conversation(&self, group_id: &GroupID) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        self.convo_info_volatile_config.send_if_modified(|c| {
+            if c.get_group(group_id).is_some() {
+                return false;
+            }
+
+            result = c
+                .get_or_construct_group(group_id)
+                .map(|info| c.set_group(&info));
+            result.is_ok()
+        });
+        result
+    }
+
+    /// Saves a config right away, for when there are no workers running to do it.
+    pub async fn save_now<C: Config>(
+        tx: &watch::Sender<C>,
+        repo: &Repository,
+    ) -> anyhow::Result<()> {
+        let mut record = Err(anyhow::anyhow!("Config not saved"));
+        tx.send_if_modified(|c| {
+            record = ConfigRecord::take(c);
+            false
+        });
+
+        let record = record?;
+        repo.write(move |conn| conn.save_config_record(&record, None))
+            .await
+    }
+
     pub async fn log_configs(&self) -> anyhow::Result<()> {
         try_join!(
             Self::log_config(&self.user_profile_config),
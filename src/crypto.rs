@@ -1,6 +1,9 @@
 use std::ptr::null_mut;
 
-use anyhow::{bail, Context};
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use anyhow::{anyhow, bail, Context};
+use rand::RngCore;
 
 use crate::session_id::SessionID;
 use crate::{
@@ -71,3 +74,22 @@ pub fn decrypt_incoming(
 
     Ok((session_id, plaintext))
 }
+
+const PROFILE_PIC_NONCE_LEN: usize = 12;
+
+/// Encrypts a profile picture the way clients expect to find it on the file server: a random
+/// nonce followed by the AES-GCM ciphertext under the picture's key.
+pub fn encrypt_profile_pic(key: &[u8; 32], plaintext: impl AsRef<[u8]>) -> anyhow::Result<Vec<u8>> {
+    let mut nonce = [0u8; PROFILE_PIC_NONCE_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+    let encrypted = Aes256Gcm::new_from_slice(key)
+        .context("Creating cipher")?
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+        .map_err(|_| anyhow!("Encrypting profile picture"))?;
+
+    let mut out = Vec::with_capacity(nonce.len() + encrypted.len());
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&encrypted);
+    Ok(out)
+}
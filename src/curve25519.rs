@@ -1,7 +1,7 @@
 use crate::define_key_type;
 
 define_key_type!(Curve25519PubKey, 32);
-define_key_type!(Curve25519SecKey, 32);
+define_key_type!(Curve25519SecKey, 32, secret);
 
 pub fn gen_pair() -> (Curve25519PubKey, Curve25519SecKey) {
     let mut pubkey = [0u8; 32];
@@ -11,6 +11,9 @@ pub trait AppSettingRepositoryExt {
         value: &T,
     ) -> anyhow::Result<()>;
     fn remove_settings_by_name(&self, name: &str) -> anyhow::Result<()>;
+
+    /// Whether any of the rows holds a `T` setting.
+    fn is_setting_row<T: AppSetting>(&self, row_ids: &[i64]) -> anyhow::Result<bool>;
 }
 
 impl AppSettingRepositoryExt for Connection {
@@ -29,7 +32,8 @@ impl AppSettingRepositoryExt for Connection {
         value: &T,
     ) -> anyhow::Result<()> {
         self.execute(
-            "INSERT OR REPLACE INTO app_settings (name, id, value) VALUES (?, ?, ?)",
+            "INSERT INTO app_settings (name, id, value) VALUES (?, ?, ?)
+             ON CONFLICT (name, id) DO UPDATE SET value = excluded.value",
             params![T::NAME, id.unwrap_or_default(), value],
         )
         .context("Error setting setting")?;
@@ -42,4 +46,49 @@ impl AppSettingRepositoryExt for Connection {
             .context("Error removing settings")?;
         Ok(())
     }
+
+    fn is_setting_row<T: AppSetting>(&self, row_ids: &[i64]) -> anyhow::Result<bool> {
+        let mut stmt = self.prepare_cached(
+            "SELECT EXISTS (SELECT 1 FROM app_settings WHERE rowid = ? AND name = ?)",
+        )?;
+        for row_id in row_ids {
+            if stmt
+                .query_row(params![row_id, T::NAME], |row| row.get(0))
+                .context("Error checking setting row")?
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_setting::identity::PublicIdentity;
+    use crate::test_utils::open_db;
+
+    #[test]
+    fn setting_rows_are_told_apart_by_name() {
+        let conn = open_db();
+
+        conn.execute(
+            "INSERT INTO app_settings (name, id, value) VALUES ('gen_blinded_ids', '', '[]')",
+            [],
+        )
+        .unwrap();
+        let other = conn.last_insert_rowid();
+        conn.execute(
+            "INSERT INTO app_settings (name, value) VALUES ('identity', '{}')",
+            [],
+        )
+        .unwrap();
+        let identity = conn.last_insert_rowid();
+
+        assert!(!conn.is_setting_row::<PublicIdentity>(&[other]).unwrap());
+        assert!(conn
+            .is_setting_row::<PublicIdentity>(&[other, identity])
+            .unwrap());
+    }
 }
@@ -0,0 +1,308 @@
+use super::app_setting::AppSettingRepositoryExt;
+use crate::app_setting::identity::PublicIdentity;
+use crate::ed25519::{ED25519PubKey, ED25519SecKey};
+use crate::identity::Identity;
+use crate::network::swarm::SwarmAuth;
+use anyhow::{anyhow, bail, ensure, Context};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+// The archive is itself a SQLCipher database, encrypted with the backup passphrase, holding a
+// copy of the tables below plus a `backup_info` row describing where it came from.
+const FORMAT_VERSION: i64 = 1;
+
+// In the order they are restored: parents first.
+const TABLES: &[&str] = &[
+    "configs",
+    "message_retrieve_state",
+    "messages",
+    "message_attachments",
+    "message_reactions",
+];
+
+#[derive(Debug)]
+pub struct BackupInfo {
+    pub schema_version: i64,
+    pub identity: Identity,
+    pub created_at_millis: i64,
+}
+
+/// Writes everything needed to move this account to another install into an encrypted archive
+/// at `archive`. The identity comes from the keystore, the database doesn't have its secret key.
+pub fn backup(
+    conn: &Connection,
+    identity: &Identity,
+    archive: &Path,
+    passphrase: &str,
+) -> anyhow::Result<BackupInfo> {
+    ensure!(!passphrase.is_empty(), "The passphrase must not be empty");
+    ensure!(
+        !archive.exists(),
+        "{} already exists, not overwriting it",
+        archive.display()
+    );
+
+    let info = BackupInfo {
+        schema_version: schema_version(conn, "main")?,
+        identity: identity.clone(),
+        created_at_millis: SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_millis()
+            .try_into()?,
+    };
+
+    let tmp_path = archive.with_extension("partial");
+    let _ = std::fs::remove_file(&tmp_path);
+    let _cleanup = scopeguard::guard(&tmp_path, |path| {
+        let _ = std::fs::remove_file(path);
+    });
+
+    conn.execute(
+        "ATTACH DATABASE ?1 AS backup KEY ?2",
+        (tmp_path.to_string_lossy(), passphrase),
+    )
+    .context("Creating archive")?;
+    let _detach = scopeguard::guard(conn, |conn| {
+        let _ = conn.execute("DETACH DATABASE backup", []);
+    });
+
+    // One transaction so the copied tables are consistent with each other
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "CREATE TABLE backup.backup_info (
+            format_version INTEGER NOT NULL,
+            schema_version INTEGER NOT NULL,
+            identity TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    tx.execute(
+        "INSERT INTO backup.backup_info VALUES (?, ?, ?, ?)",
+        params![
+            FORMAT_VERSION,
+            info.schema_version,
+            identity_to_json(&info.identity)?.as_str(),
+            info.created_at_millis
+        ],
+    )?;
+
+    for table in TABLES {
+        tx.execute(
+            &format!("CREATE TABLE backup.{table} AS SELECT * FROM main.{table}"),
+            [],
+        )
+        .with_context(|| format!("Copying {table}"))?;
+    }
+    tx.commit()?;
+
+    drop(_detach);
+    std::fs::rename(&tmp_path, archive).context("Moving archive into place")?;
+    Ok(info)
+}
+
+/// Replaces the account data in this database with the content of the archive. The database must
+/// be migrated to the same schema version the archive was taken at, and belong to the same
+/// identity unless `force` is set. Must run in a transaction.
+pub fn restore(
+    conn: &Connection,
+    archive: &Path,
+    passphrase: &str,
+    force: bool,
+) -> anyhow::Result<BackupInfo> {
+    ensure!(archive.exists(), "{} doesn't exist", archive.display());
+
+    // Read through a connection of its own: databases can't be attached inside a transaction
+    let source = Connection::open_with_flags(archive, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .context("Opening archive")?;
+    super::encryption::unlock(&source, passphrase).context("Opening archive")?;
+
+    let (format_version, schema_version, identity, created_at_millis) = source
+        .query_row(
+            "SELECT format_version, schema_version, identity, created_at FROM backup_info",
+            [],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get(3)?,
+                ))
+            },
+        )
+        .context("Unable to read archive: wrong passphrase or not a backup?")?;
+    let info = BackupInfo {
+        schema_version,
+        identity: identity_from_json(&identity)?,
+        created_at_millis,
+    };
+
+    ensure!(
+        format_version == FORMAT_VERSION,
+        "Unsupported backup format version {format_version}"
+    );
+
+    let target_version = schema_version(conn, "main")?;
+    if info.schema_version != target_version {
+        bail!(
+            "The archive was taken at schema version {} but this database is at {target_version}",
+            info.schema_version
+        );
+    }
+
+    let target_identity = conn
+        .query_row(
+            "SELECT value FROM app_settings WHERE name = 'identity' AND id = ''",
+            [],
+            |row| row.get::<_, PublicIdentity>(0),
+        )
+        .optional()
+        .context("Reading current identity")?;
+
+    if let Some(target_identity) = target_identity {
+        if target_identity.session_id != info.identity.session_id().as_str() && !force {
+            bail!(
+                "The archive belongs to {} but this database belongs to {}",
+                info.identity.session_id(),
+                target_identity.session_id
+            );
+        }
+    }
+
+    for table in TABLES.iter().rev() {
+        conn.execute(&format!("DELETE FROM main.{table}"), [])
+            .with_context(|| format!("Clearing {table}"))?;
+    }
+
+    for table in TABLES {
+        copy_table(&source, conn, table).with_context(|| format!("Restoring {table}"))?;
+    }
+
+    conn.save_setting(None, &PublicIdentity::from(&info.identity))?;
+
+    Ok(info)
+}
+
+fn copy_table(source: &Connection, target: &Connection, table: &str) -> anyhow::Result<()> {
+    let columns: Vec<String> = target
+        .prepare("SELECT name FROM pragma_table_info(?1, 'main')")?
+        .query_map([table], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let columns_list = columns.join(", ");
+
+    let mut insert = target.prepare(&format!(
+        "INSERT INTO main.{table} ({columns_list}) VALUES ({placeholders})"
+    ))?;
+    let mut select = source.prepare(&format!("SELECT {columns_list} FROM {table}"))?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let values = (0..columns.len())
+            .map(|i| row.get::<_, Value>(i))
+            .collect::<Result<Vec<_>, _>>()?;
+        insert.execute(params_from_iter(values))?;
+    }
+
+    Ok(())
+}
+
+// Unlike the identity setting, the archive holds the secret key: it's how the account moves
+#[derive(Serialize, Deserialize)]
+struct ArchivedIdentity<'a> {
+    ed25519_pub_key: Cow<'a, str>,
+    ed25519_sec_key: Cow<'a, str>,
+}
+
+fn identity_to_json(identity: &Identity) -> anyhow::Result<Zeroizing<String>> {
+    Ok(Zeroizing::new(serde_json::to_string(&ArchivedIdentity {
+        ed25519_pub_key: Cow::Borrowed(identity.ed25519_pub_key().hex()),
+        ed25519_sec_key: Cow::Borrowed(identity.ed25519_sec_key().hex()),
+    })?))
+}
+
+fn identity_from_json(json: &str) -> anyhow::Result<Identity> {
+    let value: ArchivedIdentity =
+        serde_json::from_str(json).context("Reading archived identity")?;
+    Ok(Identity::new((
+        ED25519PubKey::from_hex(&value.ed25519_pub_key)?,
+        // Not passing on the error, it would quote the key
+        ED25519SecKey::from_hex(&value.ed25519_sec_key)
+            .map_err(|_| anyhow!("Invalid archived secret key"))?,
+    )))
+}
+
+fn schema_version(conn: &Connection, schema: &str) -> anyhow::Result<i64> {
+    conn.pragma_query_value(Some(schema), "user_version", |row| row.get(0))
+        .context("Reading schema version")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{self, temp_path};
+
+    fn open_db(path: &Path, identity: &Identity) -> Connection {
+        let conn = test_utils::open_db_at(path);
+        conn.save_setting(None, &PublicIdentity::from(identity))
+            .expect("To save identity");
+        conn
+    }
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT count(*) FROM {table}"), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn backup_restores_into_another_database() {
+        let identity = Identity::gen();
+        let me = identity.session_id().to_string();
+        let (source_path, target_path, archive) = (
+            temp_path("sqlite3"),
+            temp_path("sqlite3"),
+            temp_path("backup"),
+        );
+
+        let source = open_db(&source_path, &identity);
+        source
+            .execute_batch(&format!(
+                r#"
+                INSERT INTO configs (config_type, id, value, dump) VALUES ('UserProfileConfig', '', '{{}}', x'0102');
+                INSERT INTO message_retrieve_state (source, namespace, last_message_hash) VALUES ('{me}', 0, 'h1');
+                INSERT INTO messages (source, hash, content, sender, receiver, created_at, expiration_at, kind)
+                    VALUES ('{me}', 'h1', '{{}}', '{me}', '{me}', 1, 2, 'visible');
+                INSERT INTO message_attachments (message_id, id, url, content_type, content)
+                    VALUES (1, 'a1', 'http://example.com', 'image/png', x'00ff');
+                INSERT INTO message_reactions (message_id, sender, emoji, created_at) VALUES (1, '{me}', '👍', 3);
+                "#
+            ))
+            .expect("To insert data");
+
+        backup(&source, &identity, &archive, "secret").expect("To back up");
+        assert!(backup(&source, &identity, &archive, "secret").is_err());
+
+        let target = open_db(&target_path, &identity);
+        assert!(restore(&target, &archive, "wrong", false).is_err());
+
+        let info = restore(&target, &archive, "secret", false).expect("To restore");
+        assert_eq!(info.identity.session_id(), identity.session_id());
+        for table in TABLES {
+            assert_eq!(count(&target, table), 1, "{table}");
+        }
+
+        // A database of someone else is only overwritten when forced
+        let other_path = temp_path("sqlite3");
+        let other = open_db(&other_path, &Identity::gen());
+        assert!(restore(&other, &archive, "secret", false).is_err());
+        assert_eq!(count(&other, "messages"), 0);
+        restore(&other, &archive, "secret", true).expect("To force restore");
+        assert_eq!(count(&other, "messages"), 1);
+    }
+}
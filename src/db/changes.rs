@@ -0,0 +1,259 @@
+use super::TableName;
+use r2d2::CustomizeConnection;
+use rusqlite::hooks::Action;
+use rusqlite::Connection;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeAction {
+    Insert,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableChange {
+    pub table: TableName,
+    pub action: ChangeAction,
+    pub row_id: i64,
+}
+
+/// All the row changes made by one committed transaction.
+pub type TableChanges = Arc<[TableChange]>;
+
+/// Installs the hooks that collect the changes committed on every connection the pool opens.
+/// They are published by [ChangeNotifier::publish] once `COMMIT` has returned: the commit hook
+/// fires before the commit is durable.
+#[derive(Debug, Clone)]
+pub struct ChangeNotifier {
+    tx: broadcast::Sender<TableChanges>,
+    logs: Arc<Mutex<HashMap<usize, Arc<Mutex<ChangeLog>>>>>,
+}
+
+#[derive(Debug, Default)]
+struct ChangeLog {
+    // Rows changed by the transaction open on the connection
+    pending: PendingChanges,
+    // Transactions committed on the connection, waiting for COMMIT to return
+    committed: Vec<TableChanges>,
+    // Whether the last of them may still be rolled back, until the connection moves on
+    committing: bool,
+}
+
+impl ChangeNotifier {
+    pub fn new(tx: broadcast::Sender<TableChanges>) -> Self {
+        Self {
+            tx,
+            logs: Default::default(),
+        }
+    }
+
+    /// Publishes the changes of the transactions just committed on `conn`.
+    pub(super) fn publish(&self, conn: &Connection) {
+        let committed = self.with_log(conn, |log| {
+            log.committing = false;
+            std::mem::take(&mut log.committed)
+        });
+        for changes in committed.into_iter().flatten() {
+            let _ = self.tx.send(changes);
+        }
+    }
+
+    /// Drops the changes of a commit that failed on `conn`.
+    pub(super) fn discard_committed(&self, conn: &Connection) {
+        self.with_log(conn, |log| {
+            log.committing = false;
+            log.committed.clear()
+        });
+    }
+
+    /// Marks where a savepoint starts, to [Self::forget_pending_since] it when it's rolled back.
+    /// SQLite doesn't report `ROLLBACK TO` to any hook.
+    pub(super) fn pending_mark(&self, conn: &Connection) -> usize {
+        self.with_log(conn, |log| log.pending.len())
+            .unwrap_or_default()
+    }
+
+    pub(super) fn forget_pending_since(&self, conn: &Connection, mark: usize) {
+        self.with_log(conn, |log| log.pending.truncate(mark));
+    }
+
+    fn with_log<T>(&self, conn: &Connection, f: impl FnOnce(&mut ChangeLog) -> T) -> Option<T> {
+        let log = lock(&self.logs).get(&connection_key(conn)).cloned()?;
+        let mut log = lock(&log);
+        Some(f(&mut log))
+    }
+}
+
+impl CustomizeConnection<Connection, rusqlite::Error> for ChangeNotifier {
+    fn on_acquire(&self, conn: &mut Connection) -> Result<(), rusqlite::Error> {
+        let log = Arc::new(Mutex::new(ChangeLog::default()));
+
+        conn.update_hook(Some({
+            let log = log.clone();
+            move |action: Action, _: &str, table: &str, row_id: i64| {
+                let action = match action {
+                    Action::SQLITE_INSERT => ChangeAction::Insert,
+                    Action::SQLITE_UPDATE => ChangeAction::Update,
+                    Action::SQLITE_DELETE => ChangeAction::Delete,
+                    _ => return,
+                };
+
+                let mut log = lock(&log);
+                log.committing = false;
+                log.pending.push(
+                    table.parse().unwrap_or(TableName::Other(table.into())),
+                    action,
+                    row_id,
+                );
+            }
+        }));
+
+        conn.commit_hook(Some({
+            let log = log.clone();
+            move || {
+                let mut log = lock(&log);
+                let changes = log.pending.take();
+                if !changes.is_empty() {
+                    log.committed.push(changes.into());
+                    log.committing = true;
+                }
+
+                // Never turn the commit into a rollback
+                false
+            }
+        }));
+
+        conn.rollback_hook(Some({
+            let log = log.clone();
+            move || {
+                // Either the open transaction or a failed COMMIT is being rolled back
+                let mut log = lock(&log);
+                log.pending.take();
+                if std::mem::take(&mut log.committing) {
+                    log.committed.pop();
+                }
+            }
+        }));
+
+        lock(&self.logs).insert(connection_key(conn), log);
+        Ok(())
+    }
+
+    fn on_release(&self, conn: Connection) {
+        lock(&self.logs).remove(&connection_key(&conn));
+    }
+}
+
+// Tells the pool's connections apart for as long as they are open
+fn connection_key(conn: &Connection) -> usize {
+    // SAFETY: the handle is only used for its address, it's never dereferenced
+    unsafe { conn.handle() as usize }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Debug, Default)]
+struct PendingChanges {
+    log: Vec<(TableName, ChangeAction, i64)>,
+}
+
+impl PendingChanges {
+    fn push(&mut self, table: TableName, action: ChangeAction, row_id: i64) {
+        self.log.push((table, action, row_id));
+    }
+
+    fn len(&self) -> usize {
+        self.log.len()
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.log.truncate(len);
+    }
+
+    fn take(&mut self) -> Vec<TableChange> {
+        let mut order = Vec::new();
+        let mut actions: HashMap<(TableName, i64), Option<ChangeAction>> = HashMap::new();
+
+        for (table, action, row_id) in std::mem::take(&mut self.log) {
+            match actions.entry((table, row_id)) {
+                Entry::Vacant(e) => {
+                    order.push(e.key().clone());
+                    e.insert(Some(action));
+                }
+
+                Entry::Occupied(mut e) => {
+                    let collapsed = match (*e.get(), action) {
+                        (Some(ChangeAction::Insert), ChangeAction::Update) => {
+                            Some(ChangeAction::Insert)
+                        }
+                        (Some(ChangeAction::Insert), ChangeAction::Delete) => None,
+                        (Some(ChangeAction::Delete), ChangeAction::Insert) => {
+                            Some(ChangeAction::Update)
+                        }
+                        (_, action) => Some(action),
+                    };
+                    e.insert(collapsed);
+                }
+            }
+        }
+
+        order
+            .into_iter()
+            .filter_map(|key| {
+                let action = actions.remove(&key)??;
+                let (table, row_id) = key;
+                Some(TableChange {
+                    table,
+                    action,
+                    row_id,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_collapse_per_row() {
+        let mut pending = PendingChanges::default();
+        pending.push(TableName::Messages, ChangeAction::Insert, 1);
+        pending.push(TableName::Messages, ChangeAction::Update, 1);
+        pending.push(TableName::Messages, ChangeAction::Insert, 2);
+        pending.push(TableName::Messages, ChangeAction::Delete, 2);
+        pending.push(TableName::Configs, ChangeAction::Delete, 1);
+        pending.push(TableName::Configs, ChangeAction::Insert, 1);
+        pending.push(TableName::AppSettings, ChangeAction::Update, 3);
+        pending.push(TableName::AppSettings, ChangeAction::Delete, 3);
+
+        assert_eq!(
+            pending.take(),
+            vec![
+                TableChange {
+                    table: TableName::Messages,
+                    action: ChangeAction::Insert,
+                    row_id: 1
+                },
+                TableChange {
+                    table: TableName::Configs,
+                    action: ChangeAction::Update,
+                    row_id: 1
+                },
+                TableChange {
+                    table: TableName::AppSettings,
+                    action: ChangeAction::Delete,
+                    row_id: 3
+                },
+            ]
+        );
+        assert!(pending.take().is_empty());
+    }
+}
@@ -5,8 +5,27 @@ use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
 use rusqlite::{params, Connection, OptionalExtension};
 
+/// What gets persisted of a config, taken out of it so that it can be saved elsewhere.
+pub struct ConfigRecord {
+    pub config_type: &'static str,
+    pub value: String,
+    pub dump: Option<Vec<u8>>,
+}
+
+impl ConfigRecord {
+    pub fn take<C: Config>(config: &mut C) -> anyhow::Result<Self> {
+        let dump = config.dump().map(|d| d.as_ref().to_vec());
+        Ok(Self {
+            config_type: C::CONFIG_TYPE_NAME,
+            value: serde_json::to_string(&config.to_json()?)?,
+            dump,
+        })
+    }
+}
+
 pub trait ConfigRepositoryExt {
     fn save_config<C: Config>(&self, config: &mut C, id: Option<&str>) -> anyhow::Result<()>;
+    fn save_config_record(&self, record: &ConfigRecord, id: Option<&str>) -> anyhow::Result<()>;
     fn save_config_raw(
         &self,
         config_type: &str,
@@ -16,16 +35,22 @@ pub trait ConfigRepositoryExt {
     ) -> anyhow::Result<()>;
 
     fn get_config_dump(&self, config_type: &str, id: Option<&str>) -> anyhow::Result<Vec<u8>>;
+
+    /// Deletes every config saved under `id`, e.g. all the configs of a group.
+    fn delete_configs(&self, id: &str) -> anyhow::Result<usize>;
 }
 
 impl ConfigRepositoryExt for Connection {
     fn save_config<C: Config>(&self, config: &mut C, id: Option<&str>) -> anyhow::Result<()> {
-        let dump = config.dump();
+        self.save_config_record(&ConfigRecord::take(config)?, id)
+    }
+
+    fn save_config_record(&self, record: &ConfigRecord, id: Option<&str>) -> anyhow::Result<()> {
         self.save_config_raw(
-            C::CONFIG_TYPE_NAME,
+            record.config_type,
             id,
-            &serde_json::to_string(&config.to_json()?)?,
-            dump.as_ref().map(|d| d.as_ref()),
+            &record.value,
+            record.dump.as_deref(),
         )
     }
 
@@ -39,7 +64,8 @@ impl ConfigRepositoryExt for Connection {
         let dump = dump.map(|d| BASE64_STANDARD.encode(d));
 
         self.execute(
-            "INSERT OR REPLACE INTO configs(config_type, id, value, dump) VALUES (?, ?, ?, ?)",
+            "INSERT INTO configs(config_type, id, value, dump) VALUES (?, ?, ?, ?)
+             ON CONFLICT (config_type, id) DO UPDATE SET value = excluded.value, dump = excluded.dump",
             params![
                 config_type,
                 id.unwrap_or_default(),
@@ -63,4 +89,9 @@ impl ConfigRepositoryExt for Connection {
 
         Ok(dump.map(|d| d.0).unwrap_or_default())
     }
+
+    fn delete_configs(&self, id: &str) -> anyhow::Result<usize> {
+        self.execute("DELETE FROM configs WHERE id = ?", [id])
+            .context("Deleting configs")
+    }
 }
@@ -16,3 +16,275 @@ impl ConversationRepositoryExt for Connection {
         todo!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{self, save_my_identity, BOB, EVE, GROUP, ME};
+    use rusqlite::types::Value;
+    use rusqlite::Connection;
+
+    const MY_BLINDED: &str = "15cccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccc";
+    const STRANGER_BLINDED: &str =
+        "15ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    const COMMUNITY: &str = "https://open.example.org/room";
+
+    // With the `conversations` view exactly as 001-initial defined it as `legacy_conversations`
+    fn open_db() -> Connection {
+        let conn = test_utils::open_db();
+        let initial = include_str!("../../migrations/001-initial/up.sql");
+        let view = &initial[initial.find("CREATE VIEW conversations AS").unwrap()..];
+        conn.execute_batch(&view.replacen(
+            "CREATE VIEW conversations",
+            "CREATE TEMP VIEW legacy_conversations",
+            1,
+        ))
+        .expect("To create legacy view");
+        conn
+    }
+
+    fn save_configs(conn: &Connection, one_to_one_last_read: u64, group_last_read: u64) {
+        save_my_identity(conn);
+        conn.execute_batch(&format!(
+            r#"
+            INSERT OR REPLACE INTO app_settings (name, id, value) VALUES
+                ('blinded_id', '{COMMUNITY}', '{MY_BLINDED}');
+            INSERT OR REPLACE INTO configs (config_type, id, value) VALUES
+                ('ContactsConfig', '', json_array(
+                    json_object('session_id', '{BOB}', 'name', 'Bob', 'nickname', '', 'approved', 1,
+                                'approved_me', 1, 'blocked', 0, 'priority', 0, 'profile_picture', NULL))),
+                ('UserGroupsConfig', '', json_array(
+                    json_object('type', 'group', 'id', '{GROUP}', 'name', 'Group', 'priority', 0),
+                    json_object('type', 'community', 'url', '{COMMUNITY}', 'name', 'Room', 'priority', 0))),
+                ('GroupInfoConfig', '{GROUP}', json_object('name', 'Group info', 'profile_pic', NULL)),
+                ('GroupMemberConfig', '{GROUP}', json_array(
+                    json_object('session_id', '{ME}', 'name', 'Me', 'admin', 1, 'invite_status', 0),
+                    json_object('session_id', '{BOB}', 'name', 'Bob', 'admin', 0, 'invite_status', 0))),
+                ('ConvoInfoVolatileConfig', '', json_array(
+                    json_object('type', 'one_to_one', 'session_id', '{BOB}', 'last_read', {one_to_one_last_read}, 'unread', 0),
+                    json_object('type', 'one_to_one', 'session_id', '{EVE}', 'last_read', 0, 'unread', 0),
+                    json_object('type', 'group', 'id', '{GROUP}', 'last_read', {group_last_read}, 'unread', 0)));
+            "#
+        ))
+        .expect("To save configs");
+    }
+
+    fn save_messages(conn: &Connection) {
+        let messages = [
+            (ME, "h1", BOB, ME, 1000),
+            (ME, "h2", ME, BOB, 2000),
+            (ME, "h3", BOB, ME, 3000),
+            (ME, "h4", BOB, ME, 4000),
+            (GROUP, "g1", BOB, GROUP, 1100),
+            (GROUP, "g2", ME, GROUP, 1200),
+            (GROUP, "g3", BOB, GROUP, 1300),
+            (COMMUNITY, "c1", STRANGER_BLINDED, COMMUNITY, 1400),
+            (COMMUNITY, "c2", MY_BLINDED, COMMUNITY, 1600),
+        ];
+
+        for (source, hash, sender, receiver, created_at) in messages {
+            conn.execute(
+                "INSERT INTO messages (source, hash, content, kind, body, sender, receiver, created_at, expiration_at)
+                 VALUES (?1, ?2, json_object('dataMessage', json_object('body', ?2)), 'visible', ?2, ?3, ?4, ?5, 9000)",
+                (source, hash, sender, receiver, created_at),
+            )
+            .expect("To save message");
+        }
+
+        // Newer than the visible ones, but not shown as the last message nor counted as unread
+        const RECEIPT: &str = r#"{"receiptMessage":{}}"#;
+        const REACTION: &str = r#"{"dataMessage":{"reaction":{}}}"#;
+        let others = [
+            (ME, "n1", "receipt", RECEIPT, BOB, ME, 4500),
+            (GROUP, "n2", "reaction", REACTION, BOB, GROUP, 1400),
+        ];
+        for (source, hash, kind, content, sender, receiver, created_at) in others {
+            conn.execute(
+                "INSERT INTO messages (source, hash, content, kind, sender, receiver, created_at, expiration_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 9000)",
+                (source, hash, content, kind, sender, receiver, created_at),
+            )
+            .expect("To save message");
+        }
+    }
+
+    fn query_all(conn: &Connection, sql: &str) -> Vec<Vec<Value>> {
+        let mut stmt = conn.prepare(sql).expect("To prepare query");
+        let column_count = stmt.column_count();
+        stmt.query_map([], |row| {
+            (0..column_count)
+                .map(|i| row.get::<_, Value>(i))
+                .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .expect("To query")
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .expect("To read rows")
+    }
+
+    // The last message now carries the decoded body along with the content, and only visible
+    // messages are summarised, which the legacy view predates. The legacy view also counts each
+    // unread message once per message of the conversation, as it joins both at once.
+    fn assert_same_as_legacy(conn: &Connection) {
+        let conversations = query_all(
+            conn,
+            "SELECT id, name, last_message_created, \
+                    json_remove(last_message, '$.body', '$.attachment_count'), \
+                    avatar, approved, unread_count \
+             FROM conversations ORDER BY id",
+        );
+        assert!(!conversations.is_empty());
+
+        conn.execute_batch("SAVEPOINT legacy; DELETE FROM messages WHERE kind != 'visible';")
+            .expect("To hide invisible messages");
+        let legacy = query_all(
+            conn,
+            &format!(
+                "SELECT id, name, last_message_created, last_message, avatar, approved, \
+                        unread_count / max(1, ( \
+                            SELECT COUNT(*) FROM messages m \
+                            WHERE m.receiver = l.id OR (m.sender = l.id AND m.receiver = '{ME}') \
+                        )) \
+                 FROM legacy_conversations l ORDER BY id"
+            ),
+        );
+        conn.execute_batch("ROLLBACK TO legacy; RELEASE legacy;")
+            .expect("To restore invisible messages");
+        assert_eq!(conversations, legacy);
+    }
+
+    #[test]
+    fn summaries_match_legacy_view_when_messages_arrive_last() {
+        let conn = open_db();
+        save_configs(&conn, 1500, 0);
+        save_messages(&conn);
+        assert_same_as_legacy(&conn);
+    }
+
+    #[test]
+    fn summaries_match_legacy_view_when_configs_arrive_last() {
+        let conn = open_db();
+        save_messages(&conn);
+        save_configs(&conn, 1500, 0);
+        assert_same_as_legacy(&conn);
+    }
+
+    #[test]
+    fn summaries_match_legacy_view_after_changes() {
+        let conn = open_db();
+        save_configs(&conn, 1500, 0);
+        save_messages(&conn);
+
+        conn.execute("DELETE FROM messages WHERE hash IN ('h4', 'g3')", [])
+            .expect("To delete messages");
+        assert_same_as_legacy(&conn);
+
+        save_configs(&conn, 2500, 1150);
+        assert_same_as_legacy(&conn);
+
+        conn.execute("DELETE FROM app_settings WHERE name = 'blinded_id'", [])
+            .expect("To delete blinded id");
+        assert_same_as_legacy(&conn);
+    }
+
+    #[test]
+    fn summaries_match_legacy_view_after_message_updates() {
+        let conn = open_db();
+        save_configs(&conn, 1500, 0);
+        save_messages(&conn);
+
+        // Sending gives a pending message its hash
+        conn.execute(
+            "UPDATE messages SET hash = NULL, job_state = 'pending_send' WHERE hash = 'h3'",
+            [],
+        )
+        .expect("To unsend message");
+        assert_same_as_legacy(&conn);
+        conn.execute(
+            "UPDATE messages SET hash = 'h3', job_state = 'none' WHERE body = 'h3'",
+            [],
+        )
+        .expect("To send message");
+        assert_same_as_legacy(&conn);
+
+        conn.execute("UPDATE messages SET created_at = 10 WHERE hash = 'h4'", [])
+            .expect("To move message back in time");
+        assert_same_as_legacy(&conn);
+
+        // The legacy view doesn't know about kinds, but a rebuild would
+        conn.execute("UPDATE messages SET kind = 'control' WHERE hash = 'g3'", [])
+            .expect("To hide message");
+        assert_eq!(
+            query_all(&conn, "SELECT * FROM conversation_summaries ORDER BY 1"),
+            query_all(
+                &conn,
+                "SELECT * FROM conversation_summaries_computed ORDER BY 1"
+            ),
+        );
+    }
+
+    #[test]
+    fn summaries_match_legacy_view_after_bulk_delete() {
+        let conn = open_db();
+        save_configs(&conn, 1500, 0);
+        save_messages(&conn);
+
+        conn.execute("DELETE FROM messages WHERE created_at > 1150", [])
+            .expect("To delete messages");
+        assert_same_as_legacy(&conn);
+    }
+
+    #[test]
+    fn summaries_only_count_visible_messages() {
+        let conn = open_db();
+        save_configs(&conn, 1500, 0);
+        save_messages(&conn);
+
+        let summary = |conn: &Connection| {
+            query_all(
+                conn,
+                &format!(
+                    "SELECT last_message_created, unread_count FROM conversations WHERE id = '{BOB}'"
+                ),
+            )
+        };
+        let before = summary(&conn);
+
+        conn.execute(
+            "INSERT INTO messages (source, hash, content, kind, sender, receiver, created_at, expiration_at)
+             VALUES (?1, 'r1', json_object('receiptMessage', json_object()), 'receipt', ?2, ?1, 5000, 9000)",
+            (ME, BOB),
+        )
+        .expect("To save receipt");
+        assert_eq!(summary(&conn), before);
+
+        conn.execute("DELETE FROM messages WHERE hash = 'r1'", [])
+            .expect("To delete receipt");
+        assert_eq!(summary(&conn), before);
+    }
+
+    #[test]
+    fn groups_are_approved_once_the_invite_is_accepted() {
+        let conn = open_db();
+        save_my_identity(&conn);
+        let save_group = |invited: bool| {
+            conn.execute_batch(&format!(
+                r#"
+                INSERT OR REPLACE INTO configs (config_type, id, value) VALUES
+                    ('UserGroupsConfig', '', json_array(
+                        json_object('type', 'group', 'id', '{GROUP}', 'name', 'Group', 'priority', 0,
+                                    'invited', json('{invited}')))),
+                    ('ConvoInfoVolatileConfig', '', json_array(
+                        json_object('type', 'group', 'id', '{GROUP}', 'last_read', 0, 'unread', 0)));
+                "#
+            ))
+            .expect("To save configs");
+            query_all(
+                &conn,
+                &format!("SELECT approved FROM conversations WHERE id = '{GROUP}'"),
+            )
+        };
+
+        // We have no member config until we join
+        assert_eq!(save_group(true), vec![vec![Value::Integer(0)]]);
+        assert_eq!(save_group(false), vec![vec![Value::Integer(1)]]);
+    }
+}
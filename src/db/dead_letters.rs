@@ -0,0 +1,231 @@
+use crate::base64::Base64;
+use crate::clock::Timestamp;
+use crate::db::models::MessageSource;
+use crate::oxenss::retrieve::Message as ApiMessage;
+use anyhow::{anyhow, Context};
+use rusqlite::{named_params, params, Connection, Row};
+use serde::Serialize;
+
+/// A message that couldn't be decrypted, kept so it can be tried again later.
+#[derive(Serialize, Debug, Clone)]
+pub struct DeadLetter {
+    pub source: String,
+    pub namespace: isize,
+    pub hash: String,
+    #[serde(skip)]
+    pub data: Vec<u8>,
+    pub created_at: Timestamp,
+    pub expiration_at: Timestamp,
+    pub error: String,
+    pub attempts: u32,
+    pub last_attempt: Timestamp,
+}
+
+impl DeadLetter {
+    pub fn new(
+        source: &MessageSource<'_>,
+        namespace: isize,
+        message: &ApiMessage,
+        error: String,
+        now: Timestamp,
+    ) -> Self {
+        Self {
+            source: source.to_string(),
+            namespace,
+            hash: message.hash.clone(),
+            data: message.data.0.clone(),
+            created_at: message.created,
+            expiration_at: message.expiration,
+            error,
+            attempts: 1,
+            last_attempt: now,
+        }
+    }
+
+    pub fn to_message(&self) -> ApiMessage {
+        ApiMessage {
+            data: Base64(self.data.clone()),
+            hash: self.hash.clone(),
+            expiration: self.expiration_at,
+            created: self.created_at,
+        }
+    }
+
+    fn from_row(row: &Row) -> anyhow::Result<Self> {
+        Ok(Self {
+            source: row.get("source")?,
+            namespace: row.get("namespace")?,
+            hash: row.get("hash")?,
+            data: row.get("data")?,
+            created_at: to_timestamp(row.get("created_at")?)?,
+            expiration_at: to_timestamp(row.get("expiration_at")?)?,
+            error: row.get("error")?,
+            attempts: row.get("attempts")?,
+            last_attempt: to_timestamp(row.get("last_attempt")?)?,
+        })
+    }
+}
+
+fn to_timestamp(millis: i64) -> anyhow::Result<Timestamp> {
+    Timestamp::from_mills(millis).ok_or_else(|| anyhow!("Invalid timestamp {millis}"))
+}
+
+pub trait DeadLetterRepositoryExt {
+    /// Records the messages that failed to decrypt, or counts another attempt for the ones
+    /// already there.
+    fn save_dead_letters(&self, letters: &[DeadLetter]) -> anyhow::Result<()>;
+
+    /// The letters waiting on `source`, or on every source. Expired letters are left out.
+    fn get_dead_letters(
+        &self,
+        source: Option<&str>,
+        namespace: Option<isize>,
+        now: Timestamp,
+    ) -> anyhow::Result<Vec<DeadLetter>>;
+
+    fn remove_dead_letters(
+        &self,
+        source: &str,
+        namespace: isize,
+        hashes: &[&str],
+    ) -> anyhow::Result<usize>;
+
+    fn remove_expired_dead_letters(&self, now: Timestamp) -> anyhow::Result<usize>;
+}
+
+impl DeadLetterRepositoryExt for Connection {
+    fn save_dead_letters(&self, letters: &[DeadLetter]) -> anyhow::Result<()> {
+        let mut stmt = self.prepare_cached(
+            "INSERT INTO dead_letters
+                (source, namespace, hash, data, created_at, expiration_at, error, attempts, last_attempt)
+             VALUES (:source, :namespace, :hash, :data, :created_at, :expiration_at, :error, 1, :last_attempt)
+             ON CONFLICT (source, namespace, hash) DO UPDATE SET
+                error = excluded.error,
+                attempts = attempts + 1,
+                last_attempt = excluded.last_attempt",
+        )?;
+
+        for letter in letters {
+            stmt.execute(named_params! {
+                ":source": letter.source,
+                ":namespace": letter.namespace,
+                ":hash": letter.hash,
+                ":data": letter.data,
+                ":created_at": letter.created_at.as_millis() as i64,
+                ":expiration_at": letter.expiration_at.as_millis() as i64,
+                ":error": letter.error,
+                ":last_attempt": letter.last_attempt.as_millis() as i64,
+            })
+            .context("Saving dead letter")?;
+        }
+
+        Ok(())
+    }
+
+    fn get_dead_letters(
+        &self,
+        source: Option<&str>,
+        namespace: Option<isize>,
+        now: Timestamp,
+    ) -> anyhow::Result<Vec<DeadLetter>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT * FROM dead_letters
+             WHERE (?1 IS NULL OR source = ?1) AND (?2 IS NULL OR namespace = ?2)
+               AND expiration_at > ?3
+             ORDER BY source, namespace, created_at",
+        )?;
+
+        let mut rows = stmt.query(params![source, namespace, now.as_millis() as i64])?;
+        let mut letters = Vec::new();
+        while let Some(row) = rows.next()? {
+            letters.push(DeadLetter::from_row(row).context("Reading dead letter")?);
+        }
+        Ok(letters)
+    }
+
+    fn remove_dead_letters(
+        &self,
+        source: &str,
+        namespace: isize,
+        hashes: &[&str],
+    ) -> anyhow::Result<usize> {
+        let mut stmt = self.prepare_cached(
+            "DELETE FROM dead_letters WHERE source = ? AND namespace = ? AND hash = ?",
+        )?;
+
+        let mut removed = 0;
+        for hash in hashes {
+            removed += stmt
+                .execute(params![source, namespace, hash])
+                .context("Removing dead letter")?;
+        }
+        Ok(removed)
+    }
+
+    fn remove_expired_dead_letters(&self, now: Timestamp) -> anyhow::Result<usize> {
+        self.execute(
+            "DELETE FROM dead_letters WHERE expiration_at <= ?",
+            [now.as_millis() as i64],
+        )
+        .context("Removing expired dead letters")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{open_db, GROUP};
+
+    fn letter(hash: &str, expiration: u64, error: &str, now: u64) -> DeadLetter {
+        DeadLetter {
+            source: GROUP.to_string(),
+            namespace: 11,
+            hash: hash.to_string(),
+            data: vec![1, 2, 3],
+            created_at: Timestamp::from_mills(100).unwrap(),
+            expiration_at: Timestamp::from_mills(expiration).unwrap(),
+            error: error.to_string(),
+            attempts: 1,
+            last_attempt: Timestamp::from_mills(now).unwrap(),
+        }
+    }
+
+    #[test]
+    fn failing_again_counts_attempts() {
+        let conn = open_db();
+        conn.save_dead_letters(&[letter("a", 1000, "No keys", 200)])
+            .unwrap();
+        conn.save_dead_letters(&[letter("a", 1000, "Still no keys", 300)])
+            .unwrap();
+
+        let letters = conn
+            .get_dead_letters(Some(GROUP), Some(11), Timestamp::from_mills(400).unwrap())
+            .unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].attempts, 2);
+        assert_eq!(letters[0].error, "Still no keys");
+        assert_eq!(letters[0].last_attempt.as_millis(), 300);
+        assert_eq!(letters[0].data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn expired_letters_are_left_out_and_removed() {
+        let conn = open_db();
+        conn.save_dead_letters(&[
+            letter("a", 500, "No keys", 200),
+            letter("b", 1000, "No keys", 200),
+        ])
+        .unwrap();
+
+        let now = Timestamp::from_mills(600).unwrap();
+        let letters = conn.get_dead_letters(None, None, now).unwrap();
+        assert_eq!(
+            letters.iter().map(|l| l.hash.as_str()).collect::<Vec<_>>(),
+            vec!["b"]
+        );
+
+        assert_eq!(conn.remove_expired_dead_letters(now).unwrap(), 1);
+        assert_eq!(conn.remove_dead_letters(GROUP, 11, &["b"]).unwrap(), 1);
+        assert!(conn.get_dead_letters(None, None, now).unwrap().is_empty());
+    }
+}
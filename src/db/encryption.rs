@@ -0,0 +1,152 @@
+use anyhow::{bail, Context};
+use rusqlite::{Connection, OpenFlags};
+use std::path::Path;
+
+/// Unlocks a SQLCipher database. The actual key is derived from the passphrase by SQLCipher
+/// (PBKDF2-HMAC-SHA512), using the salt stored in the database file's header.
+pub fn unlock(conn: &Connection, passphrase: &str) -> rusqlite::Result<()> {
+    conn.pragma_update(None, "key", passphrase)
+}
+
+/// Whether the connection can actually read the database, i.e. it's plaintext or unlocked with
+/// the right passphrase.
+pub fn is_readable(conn: &Connection) -> bool {
+    conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+        .is_ok()
+}
+
+/// Encrypts an existing plaintext database in place.
+pub fn encrypt_database(path: &Path, passphrase: &str) -> anyhow::Result<()> {
+    if passphrase.is_empty() {
+        bail!("The passphrase must not be empty");
+    }
+
+    let encrypted_path = path.with_extension("encrypting");
+    let _ = std::fs::remove_file(&encrypted_path);
+
+    {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+            .context("Opening plaintext database")?;
+
+        if !is_readable(&conn) {
+            bail!("{} is not a plaintext database", path.display());
+        }
+
+        let user_version: i64 = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .context("Reading schema version")?;
+
+        conn.execute(
+            "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+            (encrypted_path.to_string_lossy(), passphrase),
+        )
+        .context("Creating encrypted database")?;
+
+        conn.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))
+            .context("Exporting into encrypted database")?;
+
+        // The migrations rely on this to know where the schema is at
+        conn.pragma_update(Some("encrypted"), "user_version", user_version)
+            .context("Copying schema version")?;
+
+        conn.execute("DETACH DATABASE encrypted", [])
+            .context("Detaching encrypted database")?;
+    }
+
+    std::fs::rename(&encrypted_path, path).context("Replacing plaintext database")?;
+    Ok(())
+}
+
+/// Changes the passphrase of an encrypted database in place.
+pub fn change_passphrase(path: &Path, old: &str, new: &str) -> anyhow::Result<()> {
+    if new.is_empty() {
+        bail!("The new passphrase must not be empty");
+    }
+
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .context("Opening database")?;
+    unlock(&conn, old).context("Unlocking database")?;
+
+    if !is_readable(&conn) {
+        bail!("Unable to unlock {}: wrong passphrase?", path.display());
+    }
+
+    conn.pragma_update(None, "rekey", new)
+        .context("Changing passphrase")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Repository;
+    use crate::test_utils::{open_db_at, temp_path};
+    use r2d2_sqlite::SqliteConnectionManager;
+
+    fn create_plaintext_db(path: &Path) -> i64 {
+        let conn = open_db_at(path);
+        conn.execute(
+            "INSERT INTO app_settings (name, id, value) VALUES ('test', '', 'value')",
+            [],
+        )
+        .expect("To write setting");
+        conn.pragma_query_value(None, "user_version", |row| row.get(0))
+            .expect("To read schema version")
+    }
+
+    fn read_setting(conn: &Connection) -> String {
+        conn.query_row(
+            "SELECT value FROM app_settings WHERE name = 'test'",
+            [],
+            |row| row.get(0),
+        )
+        .expect("To read setting")
+    }
+
+    #[test]
+    fn encrypted_database_reopens_with_its_passphrase() {
+        let path = temp_path("sqlite3");
+        let user_version = create_plaintext_db(&path);
+
+        encrypt_database(&path, "secret").expect("To encrypt");
+        assert!(encrypt_database(&path, "secret").is_err());
+        assert!(!path.with_extension("encrypting").exists());
+
+        let conn = Connection::open(&*path).expect("To open db");
+        assert!(!is_readable(&conn));
+
+        let conn = Connection::open(&*path).expect("To open db");
+        unlock(&conn, "secret").expect("To unlock");
+        assert!(is_readable(&conn));
+        assert_eq!(read_setting(&conn), "value");
+        assert_eq!(
+            conn.pragma_query_value(None, "user_version", |row| row.get::<_, i64>(0))
+                .expect("To read schema version"),
+            user_version
+        );
+
+        Repository::new(SqliteConnectionManager::file(&*path), Some("secret"))
+            .expect("To open the encrypted repo");
+    }
+
+    #[test]
+    fn wrong_passphrase_is_an_error() {
+        let path = temp_path("sqlite3");
+        create_plaintext_db(&path);
+        encrypt_database(&path, "secret").expect("To encrypt");
+
+        assert!(Repository::new(SqliteConnectionManager::file(&*path), Some("wrong")).is_err());
+        assert!(Repository::new(SqliteConnectionManager::file(&*path), None).is_err());
+        assert!(change_passphrase(&path, "wrong", "new").is_err());
+
+        change_passphrase(&path, "secret", "new").expect("To change passphrase");
+
+        let conn = Connection::open(&*path).expect("To open db");
+        unlock(&conn, "secret").expect("To unlock");
+        assert!(!is_readable(&conn));
+
+        let conn = Connection::open(&*path).expect("To open db");
+        unlock(&conn, "new").expect("To unlock");
+        assert_eq!(read_setting(&conn), "value");
+    }
+}
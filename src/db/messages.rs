@@ -1,9 +1,10 @@
 use crate::clock::Timestamp;
-use crate::db::models::MessageSource;
+use crate::db::models::{ExpiryMode, ExpirySetting, MessageSource};
 use crate::oxenss::namespace::MessageNamespace;
-use crate::session_id::{IndividualOrBlindedID, SessionID};
-use anyhow::Context;
-use rusqlite::{params, Connection, OptionalExtension};
+use crate::protos::Content;
+use crate::session_id::{GroupID, IndividualID, IndividualOrBlindedID, SessionID};
+use anyhow::{anyhow, Context};
+use rusqlite::{named_params, params, Connection, OptionalExtension, ToSql};
 use serde::{Deserialize, Serialize};
 use serde_rusqlite::to_params_named;
 use std::borrow::Cow;
@@ -18,11 +19,62 @@ pub enum MessageJobState {
     FailedSend,
 }
 
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    Visible,
+    Reaction,
+    Receipt,
+    Typing,
+    Control,
+    ConfigSync,
+    Unknown,
+}
+
+impl MessageKind {
+    // Must stay in sync with the backfill in the `003-decoded-message-columns` migration
+    pub fn of(content: &Content) -> Self {
+        if content.shared_config_message.is_some() {
+            return Self::ConfigSync;
+        }
+
+        if content.receipt_message.is_some() {
+            return Self::Receipt;
+        }
+
+        if content.typing_message.is_some() {
+            return Self::Typing;
+        }
+
+        if content.call_message.is_some()
+            || content.data_extraction_notification.is_some()
+            || content.unsend_request.is_some()
+            || content.message_request_response.is_some()
+        {
+            return Self::Control;
+        }
+
+        match &content.data_message {
+            Some(d) if d.reaction.is_some() => Self::Reaction,
+            Some(d) if d.group_update_message.is_some() || d.flags.unwrap_or(0) != 0 => {
+                Self::Control
+            }
+            Some(_) => Self::Visible,
+            None => Self::Unknown,
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct Message<'a> {
-    pub source: &'a MessageSource<'a>,
-    pub hash: Option<&'a str>,
+    pub source: MessageSource<'a>,
+    pub hash: Option<Cow<'a, str>>,
     pub content: Cow<'a, str>,
+    pub kind: MessageKind,
+    pub body: Option<Cow<'a, str>>,
+    pub attachment_count: usize,
+    pub expire_timer: Option<u32>,
+    pub expiry_mode: ExpiryMode,
     pub sender: Cow<'a, IndividualOrBlindedID>,
     pub receiver: Cow<'a, SessionID>,
     pub created_at: Timestamp,
@@ -31,8 +83,96 @@ pub struct Message<'a> {
     pub job_state: MessageJobState,
 }
 
+impl Message<'_> {
+    pub fn into_owned(self) -> Message<'static> {
+        Message {
+            source: self.source.into_owned(),
+            hash: self.hash.map(|h| Cow::Owned(h.into_owned())),
+            content: Cow::Owned(self.content.into_owned()),
+            kind: self.kind,
+            body: self.body.map(|b| Cow::Owned(b.into_owned())),
+            attachment_count: self.attachment_count,
+            expire_timer: self.expire_timer,
+            expiry_mode: self.expiry_mode,
+            sender: Cow::Owned(self.sender.into_owned()),
+            receiver: Cow::Owned(self.receiver.into_owned()),
+            created_at: self.created_at,
+            expiration_at: self.expiration_at,
+            quoting_timestamp: self.quoting_timestamp,
+            job_state: self.job_state,
+        }
+    }
+}
+
+impl Message<'static> {
+    /// A message written by us, waiting to be sent to `receiver`. Messages to a group belong
+    /// to the group swarm, where the poller finds them again once sent.
+    pub fn new_outgoing(
+        sender: IndividualID,
+        receiver: SessionID,
+        content: &Content,
+        created_at: Timestamp,
+        expiration_at: Timestamp,
+    ) -> anyhow::Result<Self> {
+        let source: MessageSource = match &receiver {
+            SessionID::Group(_) => receiver.clone().into(),
+            _ => SessionID::from(sender.clone()).into(),
+        };
+        Ok(Message {
+            source,
+            hash: None,
+            content: Cow::Owned(
+                serde_json::to_string(content).context("Serialising message content")?,
+            ),
+            kind: MessageKind::of(content),
+            body: content
+                .data_message
+                .as_ref()
+                .and_then(|d| d.body.clone())
+                .filter(|b| !b.is_empty())
+                .map(Cow::Owned),
+            attachment_count: content
+                .data_message
+                .as_ref()
+                .map_or(0, |d| d.attachments.len()),
+            expire_timer: None,
+            expiry_mode: ExpiryMode::None,
+            sender: Cow::Owned(sender.into()),
+            receiver: Cow::Owned(receiver),
+            created_at,
+            expiration_at,
+            quoting_timestamp: content
+                .data_message
+                .as_ref()
+                .and_then(|s| s.quote.as_ref())
+                .map(|q| q.id),
+            job_state: MessageJobState::PendingSend,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingMessage {
+    pub id: i64,
+    pub content: Content,
+    pub receiver: SessionID,
+    pub created_at: Timestamp,
+}
+
+/// A message stored on `source`'s swarm that should now expire at `expiration_at`.
+#[derive(Debug, Clone)]
+pub struct ExpiringMessage {
+    pub source: String,
+    pub hash: String,
+    pub expiration_at: Timestamp,
+}
+
 pub trait MessageRepositoryExt {
-    fn save_messages<'a>(&self, messages: impl Iterator<Item = Message<'a>>) -> anyhow::Result<()>;
+    /// Returns the messages that were actually inserted, i.e. not seen before.
+    fn save_messages<'a>(
+        &self,
+        messages: impl Iterator<Item = Message<'a>>,
+    ) -> anyhow::Result<Vec<Message<'a>>>;
 
     fn save_last_message_hash<NS: MessageNamespace>(
         &self,
@@ -43,25 +183,113 @@ pub trait MessageRepositoryExt {
         &self,
         source: &MessageSource<'_>,
     ) -> anyhow::Result<Option<String>>;
+
+    fn get_pending_sends(&self, source: &MessageSource<'_>) -> anyhow::Result<Vec<PendingMessage>>;
+
+    /// Records the hash the message got on our swarm, along with the content that was
+    /// actually sent.
+    fn mark_message_sent(
+        &self,
+        id: i64,
+        hash: &str,
+        content: &Content,
+        expiry: ExpirySetting,
+        sent_at: Timestamp,
+        expiration_at: Timestamp,
+    ) -> anyhow::Result<()>;
+
+    fn mark_message_failed(&self, id: i64, error: &str, at: Timestamp) -> anyhow::Result<()>;
+
+    /// The timestamps of the messages `sender` sent us in `(after, up_to]`, oldest first.
+    fn get_received_timestamps(
+        &self,
+        me: &IndividualID,
+        sender: &IndividualID,
+        after: u64,
+        up_to: u64,
+    ) -> anyhow::Result<Vec<u64>>;
+
+    /// Starts the countdown of the after-read messages that have now been read, shortening
+    /// their expiry. Returns the messages whose expiry changed.
+    fn start_after_read_timers(&self, now: Timestamp) -> anyhow::Result<Vec<ExpiringMessage>>;
+
+    /// The latest invite we received to the group.
+    fn get_group_invite(
+        &self,
+        me: &IndividualID,
+        group_id: &GroupID,
+    ) -> anyhow::Result<Option<Content>>;
+
+    /// The promotions to admin we received, latest first. They can't be looked up by group as
+    /// they only carry the group's seed.
+    fn get_group_promotions(&self, me: &IndividualID) -> anyhow::Result<Vec<Content>>;
+
+    /// Deletes what `sender` posted to `source`, returning the hashes of the deleted messages.
+    fn delete_messages_from(
+        &self,
+        source: &MessageSource<'_>,
+        sender: &IndividualID,
+    ) -> anyhow::Result<Vec<String>>;
+
+    /// Deletes everything we have of the group's messages, including where polling was at.
+    fn delete_group_messages(&self, group_id: &GroupID) -> anyhow::Result<usize>;
+
+    /// Enforces the group's `delete_before` and `delete_attach_before`. Returns how many
+    /// messages and attachments were removed.
+    fn purge_group_messages(
+        &self,
+        group_id: &GroupID,
+        delete_before: Option<Timestamp>,
+        delete_attach_before: Option<Timestamp>,
+    ) -> anyhow::Result<(usize, usize)>;
+}
+
+// Foreign keys aren't enforced, so nothing cascades
+fn delete_message_children(
+    conn: &Connection,
+    matching: &str,
+    params: &[(&str, &dyn ToSql)],
+) -> anyhow::Result<()> {
+    for table in ["message_reactions", "message_attachments"] {
+        conn.execute(
+            &format!("DELETE FROM {table} WHERE message_id IN ({matching})"),
+            params,
+        )
+        .with_context(|| format!("Deleting from {table}"))?;
+    }
+    Ok(())
+}
+
+fn to_timestamp(millis: i64) -> anyhow::Result<Timestamp> {
+    Timestamp::from_mills(millis).ok_or_else(|| anyhow!("Invalid timestamp {millis}"))
 }
 
 impl MessageRepositoryExt for Connection {
-    fn save_messages<'a>(&self, messages: impl Iterator<Item = Message<'a>>) -> anyhow::Result<()> {
+    fn save_messages<'a>(
+        &self,
+        messages: impl Iterator<Item = Message<'a>>,
+    ) -> anyhow::Result<Vec<Message<'a>>> {
         let mut stmt = self.prepare_cached("INSERT OR IGNORE INTO \
-            messages(source, hash, content, sender, receiver, created_at, expiration_at, quoting_timestamp, job_state) \
-            VALUES (:source, :hash, :content, :sender, :receiver, :created_at, :expiration_at, :quoting_timestamp, :job_state)").context("Prepare insert statement")?;
+            messages(source, hash, content, kind, body, attachment_count, expire_timer, expiry_mode, sender, receiver, created_at, expiration_at, quoting_timestamp, job_state) \
+            VALUES (:source, :hash, :content, :kind, :body, :attachment_count, :expire_timer, :expiry_mode, :sender, :receiver, :created_at, :expiration_at, :quoting_timestamp, :job_state)").context("Prepare insert statement")?;
 
+        let mut inserted = Vec::new();
         for msg in messages {
-            stmt.execute(
-                to_params_named(msg)
-                    .context("Serialising save message")?
-                    .to_slice()
-                    .as_slice(),
-            )
-            .context("Saving message")?;
+            let num_rows = stmt
+                .execute(
+                    to_params_named(&msg)
+                        .context("Serialising save message")?
+                        .to_slice()
+                        .as_slice(),
+                )
+                .context("Saving message")?;
+
+            if num_rows > 0 {
+                inserted.push(msg);
+            }
         }
 
-        Ok(())
+        Ok(inserted)
     }
 
     fn save_last_message_hash<NS: MessageNamespace>(
@@ -88,4 +316,366 @@ impl MessageRepositoryExt for Connection {
             |row| row.get(0),
         ).optional().context("Getting last message hash")
     }
+
+    fn get_pending_sends(&self, source: &MessageSource<'_>) -> anyhow::Result<Vec<PendingMessage>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT id, content, receiver, created_at FROM messages
+             WHERE source = ? AND job_state = 'pending_send'
+             ORDER BY created_at",
+        )?;
+
+        let rows: Vec<(i64, String, String, i64)> = stmt
+            .query_map([source], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .collect::<Result<_, _>>()
+            .context("Querying pending messages")?;
+
+        rows.into_iter()
+            .map(|(id, content, receiver, created_at)| {
+                Ok(PendingMessage {
+                    id,
+                    content: serde_json::from_str(&content)
+                        .with_context(|| format!("Parsing content of message {id}"))?,
+                    receiver: receiver.parse().context("Parsing receiver")?,
+                    created_at: to_timestamp(created_at)?,
+                })
+            })
+            .collect()
+    }
+
+    fn mark_message_sent(
+        &self,
+        id: i64,
+        hash: &str,
+        content: &Content,
+        expiry: ExpirySetting,
+        sent_at: Timestamp,
+        expiration_at: Timestamp,
+    ) -> anyhow::Result<()> {
+        // Our own after-read messages count as read as soon as they're sent
+        let expiry_started_at =
+            (expiry.mode == ExpiryMode::AfterRead).then(|| sent_at.as_millis() as i64);
+
+        self.execute(
+            "UPDATE messages
+             SET hash = ?2, content = ?3, expiry_mode = ?4, expire_timer = ?5,
+                 expiry_started_at = ?6, expiration_at = ?7,
+                 job_state = 'none', last_job_attempt = NULL, last_job_error = NULL
+             WHERE id = ?1",
+            params![
+                id,
+                hash,
+                serde_json::to_string(content).context("Serialising message content")?,
+                serde_json::to_value(expiry.mode)?.as_str(),
+                expiry.is_enabled().then_some(expiry.timer_seconds),
+                expiry_started_at,
+                expiration_at.as_millis() as i64,
+            ],
+        )
+        .context("Marking message as sent")?;
+        Ok(())
+    }
+
+    fn mark_message_failed(&self, id: i64, error: &str, at: Timestamp) -> anyhow::Result<()> {
+        self.execute(
+            "UPDATE messages
+             SET job_state = 'failed_send', last_job_attempt = ?2, last_job_error = ?3
+             WHERE id = ?1",
+            params![id, at.as_millis() as i64, error],
+        )
+        .context("Marking message as failed")?;
+        Ok(())
+    }
+
+    fn get_received_timestamps(
+        &self,
+        me: &IndividualID,
+        sender: &IndividualID,
+        after: u64,
+        up_to: u64,
+    ) -> anyhow::Result<Vec<u64>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT created_at FROM messages
+             WHERE source = :me AND receiver = :me AND sender = :sender AND kind = 'visible'
+               AND created_at > :after AND created_at <= :up_to
+             ORDER BY created_at",
+        )?;
+
+        let timestamps = stmt
+            .query_map(
+                named_params! {
+                    ":me": me.as_str(),
+                    ":sender": sender.as_str(),
+                    ":after": after as i64,
+                    ":up_to": up_to as i64,
+                },
+                |row| row.get(0),
+            )?
+            .collect::<Result<_, _>>()
+            .context("Querying received messages")?;
+
+        Ok(timestamps)
+    }
+
+    fn get_group_invite(
+        &self,
+        me: &IndividualID,
+        group_id: &GroupID,
+    ) -> anyhow::Result<Option<Content>> {
+        let content: Option<String> = self
+            .query_row(
+                "SELECT content FROM messages
+                 WHERE source = :me AND receiver = :me AND kind = 'control'
+                   AND content ->> '$.dataMessage.groupUpdateMessage.inviteMessage.groupSessionId' = :group_id
+                 ORDER BY created_at DESC
+                 LIMIT 1",
+                named_params! {
+                    ":me": me.as_str(),
+                    ":group_id": group_id.as_str(),
+                },
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Querying group invite")?;
+
+        content
+            .map(|c| serde_json::from_str(&c).context("Parsing group invite"))
+            .transpose()
+    }
+
+    fn get_group_promotions(&self, me: &IndividualID) -> anyhow::Result<Vec<Content>> {
+        let contents: Vec<String> = self
+            .prepare_cached(
+                "SELECT content FROM messages
+                 WHERE source = :me AND receiver = :me AND kind = 'control'
+                   AND content -> '$.dataMessage.groupUpdateMessage.promoteMessage' IS NOT NULL
+                 ORDER BY created_at DESC",
+            )?
+            .query_map(named_params! { ":me": me.as_str() }, |row| row.get(0))?
+            .collect::<Result<_, _>>()
+            .context("Querying group promotions")?;
+
+        contents
+            .iter()
+            .map(|c| serde_json::from_str(c).context("Parsing group promotion"))
+            .collect()
+    }
+
+    fn delete_messages_from(
+        &self,
+        source: &MessageSource<'_>,
+        sender: &IndividualID,
+    ) -> anyhow::Result<Vec<String>> {
+        let matching = "SELECT id FROM messages WHERE source = :source AND sender = :sender";
+        let params = named_params! { ":source": source, ":sender": sender.as_str() };
+        delete_message_children(self, matching, params)?;
+
+        let hashes: Vec<Option<String>> = self
+            .prepare_cached(&format!(
+                "DELETE FROM messages WHERE id IN ({matching}) RETURNING hash"
+            ))?
+            .query_map(params, |row| row.get(0))?
+            .collect::<Result<_, _>>()
+            .context("Deleting messages")?;
+
+        Ok(hashes.into_iter().flatten().collect())
+    }
+
+    fn delete_group_messages(&self, group_id: &GroupID) -> anyhow::Result<usize> {
+        let in_group = "SELECT id FROM messages WHERE source = :group_id OR receiver = :group_id";
+        let params = named_params! { ":group_id": group_id.as_str() };
+        delete_message_children(self, in_group, params)?;
+        let removed = self
+            .execute(
+                &format!("DELETE FROM messages WHERE id IN ({in_group})"),
+                params,
+            )
+            .context("Deleting group messages")?;
+
+        self.execute(
+            "DELETE FROM message_retrieve_state WHERE source = ?",
+            [group_id.as_str()],
+        )
+        .context("Deleting group retrieve state")?;
+        Ok(removed)
+    }
+
+    fn purge_group_messages(
+        &self,
+        group_id: &GroupID,
+        delete_before: Option<Timestamp>,
+        delete_attach_before: Option<Timestamp>,
+    ) -> anyhow::Result<(usize, usize)> {
+        // Covers what we sent to the group as well as what we received from it
+        let in_group = "SELECT id FROM messages
+                        WHERE (source = :group_id OR receiver = :group_id) AND created_at < :before";
+
+        let mut removed_messages = 0;
+        if let Some(before) = delete_before {
+            let before = before.as_millis() as i64;
+            let params = named_params! { ":group_id": group_id.as_str(), ":before": before };
+            delete_message_children(self, in_group, params)?;
+            removed_messages = self
+                .execute(
+                    &format!("DELETE FROM messages WHERE id IN ({in_group})"),
+                    params,
+                )
+                .context("Purging group messages")?;
+        }
+
+        let mut removed_attachments = 0;
+        if let Some(before) = delete_attach_before {
+            let before = before.as_millis() as i64;
+            let params = named_params! { ":group_id": group_id.as_str(), ":before": before };
+            removed_attachments = self
+                .execute(
+                    &format!("DELETE FROM message_attachments WHERE message_id IN ({in_group})"),
+                    params,
+                )
+                .context("Purging group attachments")?;
+        }
+
+        Ok((removed_messages, removed_attachments))
+    }
+
+    fn start_after_read_timers(&self, now: Timestamp) -> anyhow::Result<Vec<ExpiringMessage>> {
+        let mut stmt = self.prepare_cached(
+            "UPDATE messages
+             SET expiry_started_at = :now,
+                 expiration_at = min(expiration_at, :now + expire_timer * 1000)
+             WHERE expiry_mode = 'after_read' AND expiry_started_at IS NULL
+               AND hash IS NOT NULL
+               AND EXISTS (
+                   SELECT 1 FROM message_conversations mc
+                   JOIN conversation_keys k ON k.id = mc.conversation_id
+                   WHERE mc.message_id = messages.id AND messages.created_at <= k.last_read
+               )
+             RETURNING source, hash, expiration_at",
+        )?;
+
+        let rows: Vec<(String, String, i64)> = stmt
+            .query_map(named_params! { ":now": now.as_millis() as i64 }, |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<Result<_, _>>()
+            .context("Starting after-read timers")?;
+
+        rows.into_iter()
+            .map(|(source, hash, expiration_at)| {
+                Ok(ExpiringMessage {
+                    source,
+                    hash,
+                    expiration_at: to_timestamp(expiration_at)?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{self, save_my_identity, BOB, GROUP, ME};
+
+    fn open_db() -> Connection {
+        let conn = test_utils::open_db();
+        save_my_identity(&conn);
+        conn.execute_batch(&format!(
+            r#"
+            INSERT INTO configs (config_type, id, value) VALUES
+                ('ConvoInfoVolatileConfig', '', json_array(
+                    json_object('type', 'one_to_one', 'session_id', '{BOB}', 'last_read', 20, 'unread', 0)
+                ));
+            "#
+        ))
+        .expect("To save configs");
+        conn
+    }
+
+    fn insert_message(
+        conn: &Connection,
+        hash: Option<&str>,
+        (sender, receiver): (&str, &str),
+        expiry_mode: &str,
+        created: i64,
+    ) {
+        conn.execute(
+            "INSERT INTO messages (source, hash, content, kind, sender, receiver, created_at, expiration_at, expire_timer, expiry_mode, job_state)
+             VALUES (?1, ?2, '{}', 'visible', ?3, ?4, ?5, 1000000, 60, ?6, iif(?2 IS NULL, 'pending_send', 'none'))",
+            params![ME, hash, sender, receiver, created, expiry_mode],
+        )
+        .expect("To insert message");
+    }
+
+    #[test]
+    fn after_read_timers_start_once_read() {
+        let conn = open_db();
+        insert_message(&conn, Some("read"), (BOB, ME), "after_read", 10);
+        insert_message(&conn, Some("unread"), (BOB, ME), "after_read", 30);
+        insert_message(&conn, Some("after-send"), (BOB, ME), "after_send", 12);
+        insert_message(&conn, None, (ME, BOB), "after_read", 11);
+
+        let expiring = conn
+            .start_after_read_timers(Timestamp::from_mills(100).unwrap())
+            .unwrap();
+        assert_eq!(expiring.len(), 1);
+        assert_eq!(expiring[0].source, ME);
+        assert_eq!(expiring[0].hash, "read");
+        assert_eq!(expiring[0].expiration_at.as_millis(), 100 + 60_000);
+
+        // Timers that already started are left alone
+        assert!(conn
+            .start_after_read_timers(Timestamp::from_mills(200).unwrap())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn group_sends_seen_by_the_poller_first_are_not_duplicated() {
+        let conn = open_db();
+        let outgoing = || {
+            Message::new_outgoing(
+                ME.parse().unwrap(),
+                GROUP.parse().unwrap(),
+                &Content::default(),
+                Timestamp::from_mills(1000).unwrap(),
+                Timestamp::from_mills(1_000_000).unwrap(),
+            )
+            .unwrap()
+        };
+        conn.save_messages(std::iter::once(outgoing())).unwrap();
+        let source: MessageSource = GROUP.parse::<SessionID>().unwrap().into();
+        let [pending] = conn.get_pending_sends(&source).unwrap().try_into().unwrap();
+
+        // The poller saves our message from the group swarm before it's marked as sent
+        let polled = Message {
+            hash: Some(Cow::Borrowed("sent")),
+            job_state: MessageJobState::None,
+            ..outgoing()
+        };
+        assert!(conn
+            .save_messages(std::iter::once(polled))
+            .unwrap()
+            .is_empty());
+
+        conn.mark_message_sent(
+            pending.id,
+            "sent",
+            &pending.content,
+            ExpirySetting::default(),
+            Timestamp::from_mills(2000).unwrap(),
+            Timestamp::from_mills(1_000_000).unwrap(),
+        )
+        .unwrap();
+
+        let rows: Vec<(Option<String>, String)> = conn
+            .prepare("SELECT hash, job_state FROM messages WHERE source = ?")
+            .unwrap()
+            .query_map([GROUP], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rows, vec![(Some("sent".to_string()), "none".to_string())]);
+    }
 }
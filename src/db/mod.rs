@@ -1,10 +1,17 @@
 pub mod app_setting;
+pub mod backup;
+pub mod changes;
 pub mod config;
 pub mod conversations;
+pub mod dead_letters;
+pub mod encryption;
 pub mod messages;
-mod migrations;
+pub(crate) mod migrations;
 pub mod models;
 mod repo;
+pub mod retention;
+pub mod transcript;
 pub mod watch;
+mod writer;
 
-pub use repo::{Repository, TableName};
+pub use repo::{RepoConnection, Repository, TableName};
@@ -1,3 +1,4 @@
+use crate::protos::content::ExpirationType;
 use crate::session_id::{BlindedID, GroupID, IndividualID, SessionID};
 use derive_more::From;
 use reqwest::Url;
@@ -7,6 +8,7 @@ use serde_with::{DeserializeFromStr, SerializeDisplay};
 use std::borrow::Cow;
 use std::fmt::Display;
 use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -17,15 +19,63 @@ pub enum NotifyMode {
     MentionsOnly,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum ExpiryMode {
+    #[default]
     None,
     AfterSend,
     AfterRead,
 }
 
-#[derive(SerializeDisplay, DeserializeFromStr, Debug, From)]
+impl ExpiryMode {
+    pub fn from_proto(expiration_type: ExpirationType) -> Self {
+        match expiration_type {
+            ExpirationType::DeleteAfterSend => Self::AfterSend,
+            ExpirationType::DeleteAfterRead => Self::AfterRead,
+            ExpirationType::Unknown => Self::None,
+        }
+    }
+
+    pub fn to_proto(self) -> ExpirationType {
+        match self {
+            Self::None => ExpirationType::Unknown,
+            Self::AfterSend => ExpirationType::DeleteAfterSend,
+            Self::AfterRead => ExpirationType::DeleteAfterRead,
+        }
+    }
+}
+
+/// The disappearing messages setting of a conversation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExpirySetting {
+    pub mode: ExpiryMode,
+    pub timer_seconds: u32,
+}
+
+impl ExpirySetting {
+    pub fn new(mode: ExpiryMode, timer_seconds: u32) -> Self {
+        if mode == ExpiryMode::None || timer_seconds == 0 {
+            Self::default()
+        } else {
+            Self {
+                mode,
+                timer_seconds,
+            }
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.mode != ExpiryMode::None
+    }
+
+    pub fn timer(&self) -> Option<Duration> {
+        self.is_enabled()
+            .then(|| Duration::from_secs(self.timer_seconds.into()))
+    }
+}
+
+#[derive(SerializeDisplay, DeserializeFromStr, Debug, Clone, From)]
 pub enum MessageSource<'a> {
     IndividualSwarm(Cow<'a, IndividualID>),
     Blinded(Cow<'a, BlindedID>),
@@ -76,6 +126,17 @@ impl FromStr for MessageSource<'static> {
 }
 
 impl<'a> MessageSource<'a> {
+    pub fn into_owned(self) -> MessageSource<'static> {
+        match self {
+            MessageSource::IndividualSwarm(id) => {
+                MessageSource::IndividualSwarm(Cow::Owned(id.into_owned()))
+            }
+            MessageSource::Blinded(id) => MessageSource::Blinded(Cow::Owned(id.into_owned())),
+            MessageSource::GroupSwarm(id) => MessageSource::GroupSwarm(Cow::Owned(id.into_owned())),
+            MessageSource::Community(url) => MessageSource::Community(Cow::Owned(url.into_owned())),
+        }
+    }
+
     pub fn as_str(&self) -> &str {
         match self {
             MessageSource::IndividualSwarm(id) => id.as_str(),
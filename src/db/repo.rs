@@ -1,22 +1,27 @@
-use anyhow::Context;
+use super::changes::{ChangeNotifier, TableChanges};
+use super::writer::{run_writer, Job, WriteJob};
+use anyhow::{anyhow, Context};
 use derive_more::Deref;
-use r2d2::Pool;
+use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::hooks::Action;
+use rusqlite::Connection;
 use std::ops::DerefMut;
 use std::sync::Arc;
 use strum::EnumString;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc};
+use zeroize::Zeroizing;
 
 #[derive(Deref)]
 pub struct Repository {
     #[deref]
     pub(super) db: Pool<SqliteConnectionManager>,
 
-    table_change_broadcast: broadcast::Receiver<TableName>,
+    table_change_broadcast: broadcast::Sender<TableChanges>,
+    changes: ChangeNotifier,
+    writer: mpsc::UnboundedSender<Box<dyn WriteJob>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, EnumString)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, EnumString)]
 #[strum(serialize_all = "snake_case")]
 pub enum TableName {
     Messages,
@@ -26,43 +31,265 @@ pub enum TableName {
 }
 
 impl Repository {
-    pub fn new(manager: SqliteConnectionManager) -> anyhow::Result<Self> {
-        let db = Pool::new(manager)?;
-        let mut conn = db.get()?;
+    /// Opens the database, unlocking it with `passphrase` if it's encrypted.
+    pub fn new(manager: SqliteConnectionManager, passphrase: Option<&str>) -> anyhow::Result<Self> {
+        let manager = match passphrase {
+            Some(passphrase) => {
+                let passphrase = Zeroizing::new(passphrase.to_string());
+                manager.with_init(move |c| super::encryption::unlock(c, &passphrase))
+            }
+            None => manager,
+        };
+
+        let (table_change_broadcast, _) = broadcast::channel(64);
+        let changes = ChangeNotifier::new(table_change_broadcast.clone());
+        let db = Pool::builder()
+            .connection_customizer(Box::new(changes.clone()))
+            .build(manager)?;
+        let mut conn = db.get().context("Opening database")?;
+
+        if !super::encryption::is_readable(&conn) {
+            anyhow::bail!(
+                "Unable to read the database: it's encrypted and the passphrase is missing or wrong"
+            );
+        }
 
         super::migrations::create_migrations()
             .to_latest(conn.deref_mut())
             .context("Error running db migrations")?;
+        // Nothing is subscribed yet, and the migrations aren't changes to report anyway
+        changes.discard_committed(&conn);
 
-        let (table_change_broadcast_tx, table_change_broadcast) = broadcast::channel(10);
-        conn.update_hook(Some(move |_: Action, _: &str, table: &str, _: i64| {
-            let _ = table_change_broadcast_tx
-                .send(table.parse().unwrap_or(TableName::Other(table.into())));
-        }));
+        let (writer, jobs) = mpsc::unbounded_channel();
+        std::thread::Builder::new()
+            .name("db-writer".into())
+            .spawn({
+                let changes = changes.clone();
+                move || run_writer(conn, jobs, changes)
+            })
+            .context("Spawning db writer thread")?;
 
         Ok(Self {
             db,
             table_change_broadcast,
+            changes,
+            writer,
         })
     }
 
-    pub fn subscribe_table_changes(&self) -> broadcast::Receiver<TableName> {
-        self.table_change_broadcast.resubscribe()
+    /// Runs `f` with a pooled connection on the blocking thread pool.
+    pub async fn read<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let (db, changes) = (self.db.clone(), self.changes.clone());
+        tokio::task::spawn_blocking(move || {
+            let conn = RepoConnection::get(&db, changes)?;
+            f(&conn)
+        })
+        .await
+        .context("Running db read")?
     }
 
-    pub fn obtain_connection(
+    /// Runs `f` in a transaction of its own on a pooled connection, for writes too big to hold up
+    /// the writer's batches.
+    pub async fn transaction<T: Send + 'static>(
         &self,
-    ) -> anyhow::Result<r2d2::PooledConnection<SqliteConnectionManager>> {
-        self.db.get().context("Getting pool connection")
+        f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        self.read(move |conn| {
+            let tx = conn.unchecked_transaction()?;
+            let result = f(&tx)?;
+            tx.commit()?;
+            Ok(result)
+        })
+        .await
+    }
+
+    /// Runs `f` on the writer thread, batched together with other pending writes into one
+    /// transaction. `f` must not start a transaction itself: it already runs in a savepoint, and
+    /// its changes are rolled back on error without affecting the rest of the batch.
+    pub async fn write<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let (job, result) = Job::new(f);
+        self.writer
+            .send(Box::new(job))
+            .map_err(|_| anyhow!("Db writer has stopped"))?;
+        result.await.context("Waiting for db write")?
+    }
+
+    pub fn subscribe_table_changes(&self) -> broadcast::Receiver<TableChanges> {
+        self.table_change_broadcast.subscribe()
+    }
+
+    pub fn obtain_connection(&self) -> anyhow::Result<RepoConnection> {
+        RepoConnection::get(&self.db, self.changes.clone())
+    }
+}
+
+/// A pooled connection that publishes whatever was committed on it when it's dropped.
+#[derive(Deref, derive_more::DerefMut)]
+pub struct RepoConnection {
+    #[deref]
+    #[deref_mut]
+    conn: PooledConnection<SqliteConnectionManager>,
+    changes: ChangeNotifier,
+}
+
+impl RepoConnection {
+    fn get(db: &Pool<SqliteConnectionManager>, changes: ChangeNotifier) -> anyhow::Result<Self> {
+        Ok(Self {
+            conn: db.get().context("Getting pool connection")?,
+            changes,
+        })
+    }
+}
+
+impl Drop for RepoConnection {
+    fn drop(&mut self) {
+        self.changes.publish(&self.conn);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db::changes::ChangeAction;
+    use crate::test_utils::temp_path;
 
     #[test]
     fn db_works() {
-        let repo = Repository::new(SqliteConnectionManager::memory()).expect("To create repo");
+        let repo =
+            Repository::new(SqliteConnectionManager::memory(), None).expect("To create repo");
+    }
+
+    #[tokio::test]
+    async fn changes_from_any_connection_are_reported_on_commit() {
+        let path = temp_path("sqlite3");
+        let repo =
+            Repository::new(SqliteConnectionManager::file(&*path), None).expect("To create repo");
+        let mut changes = repo.subscribe_table_changes();
+
+        // Hold on to the first connection so that the second one is a different one
+        let _first = repo.obtain_connection().expect("To get connection");
+        let mut second = repo.obtain_connection().expect("To get connection");
+
+        let tx = second.transaction().expect("To start transaction");
+        tx.execute_batch(
+            "INSERT INTO app_settings (name, id, value) VALUES ('test', '1', 'a');
+             INSERT INTO app_settings (name, id, value) VALUES ('test', '2', 'b');
+             UPDATE app_settings SET value = 'c' WHERE name = 'test' AND id = '1';",
+        )
+        .expect("To write settings");
+        assert!(changes.try_recv().is_err());
+        tx.commit().expect("To commit");
+        assert!(changes.try_recv().is_err());
+        drop(second);
+
+        let committed = changes.try_recv().expect("To receive changes");
+        assert_eq!(committed.len(), 2);
+        assert!(committed
+            .iter()
+            .all(|c| c.table == TableName::AppSettings && c.action == ChangeAction::Insert));
+        assert!(changes.try_recv().is_err());
+
+        repo.read(|conn| {
+            let tx = conn.unchecked_transaction()?;
+            tx.execute("DELETE FROM app_settings WHERE name = 'test'", [])?;
+            tx.rollback()?;
+
+            let tx = conn.unchecked_transaction()?;
+            tx.execute(
+                "DELETE FROM app_settings WHERE name = 'test' AND id = '2'",
+                [],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+        .expect("To delete setting");
+
+        let committed = changes.try_recv().expect("To receive changes");
+        assert_eq!(committed.len(), 1);
+        assert_eq!(committed[0].action, ChangeAction::Delete);
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn writes_are_reported_once_committed() {
+        let path = temp_path("sqlite3");
+        let repo =
+            Repository::new(SqliteConnectionManager::file(&*path), None).expect("To create repo");
+        let mut changes = repo.subscribe_table_changes();
+
+        let failing = repo.write(|conn| {
+            conn.execute(
+                "INSERT INTO app_settings (name, id, value) VALUES ('test', 'failing', 'value')",
+                [],
+            )?;
+            anyhow::bail!("Failing on purpose")
+        });
+        assert!(failing.await.is_err());
+
+        repo.write(|conn| {
+            conn.execute(
+                "INSERT INTO app_settings (name, id, value) VALUES ('test', '1', 'value')",
+                [],
+            )?;
+            Ok(())
+        })
+        .await
+        .expect("To write setting");
+
+        let committed = changes.try_recv().expect("To receive changes");
+        assert_eq!(committed.len(), 1);
+        assert_eq!(committed[0].table, TableName::AppSettings);
+        assert_eq!(committed[0].action, ChangeAction::Insert);
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn batched_writes_fail_independently() {
+        let path = temp_path("sqlite3");
+        let repo =
+            Repository::new(SqliteConnectionManager::file(&*path), None).expect("To create repo");
+
+        let save = |id: &'static str| {
+            repo.write(move |conn| {
+                conn.execute(
+                    "INSERT INTO app_settings (name, id, value) VALUES ('test', ?, 'value')",
+                    [id],
+                )?;
+                Ok(())
+            })
+        };
+
+        let failing = repo.write(|conn| {
+            conn.execute(
+                "INSERT INTO app_settings (name, id, value) VALUES ('test', 'failing', 'value')",
+                [],
+            )?;
+            anyhow::bail!("Failing on purpose")
+        });
+
+        let (first, failed, second) = tokio::join!(save("1"), failing, save("2"));
+        first.expect("To save first");
+        assert!(failed.is_err());
+        second.expect("To save second");
+
+        let ids = repo
+            .read(|conn| {
+                let mut stmt =
+                    conn.prepare("SELECT id FROM app_settings WHERE name = 'test' ORDER BY id")?;
+                let ids = stmt
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(ids)
+            })
+            .await
+            .expect("To read settings");
+        assert_eq!(ids, vec!["1".to_string(), "2".to_string()]);
     }
 }
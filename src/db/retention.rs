@@ -0,0 +1,335 @@
+use crate::clock::Timestamp;
+use anyhow::Context;
+use rusqlite::{named_params, params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_rows;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Empty for the global policy
+    pub conversation_id: String,
+    pub max_messages: Option<u32>,
+    pub max_age: Option<u64>,
+    pub attachment_max_age: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct PruneReport {
+    pub expired_messages: usize,
+    pub aged_out_messages: usize,
+    pub over_limit_messages: usize,
+    pub evicted_attachments: usize,
+    pub evicted_attachment_bytes: u64,
+    /// Messages removed, per conversation
+    pub conversations: BTreeMap<String, usize>,
+}
+
+impl PruneReport {
+    pub fn removed_messages(&self) -> usize {
+        self.expired_messages + self.aged_out_messages + self.over_limit_messages
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.removed_messages() == 0 && self.evicted_attachments == 0
+    }
+}
+
+pub trait RetentionRepositoryExt {
+    fn get_retention_policies(&self) -> anyhow::Result<Vec<RetentionPolicy>>;
+    fn save_retention_policy(&self, policy: &RetentionPolicy) -> anyhow::Result<()>;
+    fn remove_retention_policy(&self, conversation_id: &str) -> anyhow::Result<bool>;
+
+    /// Removes expired messages and applies the retention policies. Must run in a transaction.
+    fn prune_messages(&self, now: Timestamp) -> anyhow::Result<PruneReport>;
+}
+
+// Every message that may be pruned, along with the policy that applies to it. Messages that
+// don't belong to a known conversation are grouped by their source.
+const COLLECT_CANDIDATES: &str = r#"
+CREATE TEMP TABLE IF NOT EXISTS prune_messages (
+    id INTEGER PRIMARY KEY,
+    conversation_id TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    expiration_at INTEGER NOT NULL,
+    disappearing INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    max_messages INTEGER,
+    max_age INTEGER,
+    attachment_max_age INTEGER,
+    reason TEXT
+);
+
+DELETE FROM temp.prune_messages;
+
+INSERT OR IGNORE INTO temp.prune_messages
+    (id, conversation_id, created_at, expiration_at, disappearing, kind, max_messages, max_age, attachment_max_age)
+SELECT
+    m.id,
+    coalesce(mc.conversation_id, m.source),
+    m.created_at,
+    m.expiration_at,
+    m.expire_timer IS NOT NULL AND (
+        m.expiry_mode = 'after_send' OR (m.expiry_mode = 'after_read' AND m.expiry_started_at IS NOT NULL)
+    ),
+    m.kind,
+    iif(p.conversation_id IS NULL, g.max_messages, p.max_messages),
+    iif(p.conversation_id IS NULL, g.max_age, p.max_age),
+    iif(p.conversation_id IS NULL, g.attachment_max_age, p.attachment_max_age)
+FROM messages m
+LEFT JOIN message_conversations mc ON mc.message_id = m.id
+LEFT JOIN retention_policies p ON p.conversation_id = coalesce(mc.conversation_id, m.source)
+LEFT JOIN retention_policies g ON g.conversation_id = ''
+WHERE m.job_state != 'pending_send';
+"#;
+
+// A message is only marked for the first reason that applies to it, in this order. Only
+// disappearing messages expire, the swarm TTL alone doesn't remove anything from our history.
+const MARK_EXPIRED: &str = r#"
+UPDATE temp.prune_messages SET reason = 'expired'
+WHERE disappearing AND expiration_at <= :now
+"#;
+
+const MARK_AGED_OUT: &str = r#"
+UPDATE temp.prune_messages SET reason = 'age'
+WHERE reason IS NULL AND max_age IS NOT NULL AND created_at <= :now - max_age
+"#;
+
+const MARK_OVER_LIMIT: &str = r#"
+UPDATE temp.prune_messages SET reason = 'count'
+WHERE reason IS NULL AND id IN (
+    SELECT id FROM (
+        SELECT id, max_messages, row_number() OVER (
+            PARTITION BY conversation_id ORDER BY created_at DESC, id DESC
+        ) AS position
+        FROM temp.prune_messages
+        WHERE kind = 'visible' AND max_messages IS NOT NULL
+    )
+    WHERE position > max_messages
+)
+"#;
+
+const REMOVE_MESSAGES: &str = r#"
+DELETE FROM message_reactions WHERE message_id IN (SELECT id FROM temp.prune_messages WHERE reason IS NOT NULL);
+DELETE FROM message_attachments WHERE message_id IN (SELECT id FROM temp.prune_messages WHERE reason IS NOT NULL);
+DELETE FROM messages WHERE id IN (SELECT id FROM temp.prune_messages WHERE reason IS NOT NULL);
+"#;
+
+const ATTACHMENTS_TO_EVICT: &str = r#"
+SELECT a.id FROM message_attachments a
+JOIN temp.prune_messages pm ON pm.id = a.message_id
+WHERE a.evicted_at IS NULL
+  AND pm.reason IS NULL
+  AND pm.attachment_max_age IS NOT NULL
+  AND pm.created_at <= :now - pm.attachment_max_age
+"#;
+
+impl RetentionRepositoryExt for Connection {
+    fn get_retention_policies(&self) -> anyhow::Result<Vec<RetentionPolicy>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT conversation_id, max_messages, max_age, attachment_max_age
+             FROM retention_policies ORDER BY conversation_id",
+        )?;
+        let rows = stmt.query([])?;
+        from_rows(rows)
+            .collect::<Result<_, _>>()
+            .context("Querying retention policies")
+    }
+
+    fn save_retention_policy(&self, policy: &RetentionPolicy) -> anyhow::Result<()> {
+        self.execute(
+            "INSERT OR REPLACE INTO retention_policies (conversation_id, max_messages, max_age, attachment_max_age)
+             VALUES (?, ?, ?, ?)",
+            params![
+                policy.conversation_id,
+                policy.max_messages,
+                policy.max_age,
+                policy.attachment_max_age
+            ],
+        )
+        .context("Saving retention policy")?;
+        Ok(())
+    }
+
+    fn remove_retention_policy(&self, conversation_id: &str) -> anyhow::Result<bool> {
+        let removed = self
+            .execute(
+                "DELETE FROM retention_policies WHERE conversation_id = ?",
+                [conversation_id],
+            )
+            .context("Removing retention policy")?;
+        Ok(removed > 0)
+    }
+
+    fn prune_messages(&self, now: Timestamp) -> anyhow::Result<PruneReport> {
+        let now = now.as_millis() as i64;
+        let mut report = PruneReport::default();
+
+        self.execute_batch(COLLECT_CANDIDATES)
+            .context("Collecting messages to prune")?;
+
+        self.execute(MARK_EXPIRED, named_params! { ":now": now })
+            .context("Finding expired messages")?;
+        self.execute(MARK_AGED_OUT, named_params! { ":now": now })
+            .context("Applying age limits")?;
+        self.execute(MARK_OVER_LIMIT, [])
+            .context("Applying count limits")?;
+
+        {
+            let mut stmt = self.prepare(
+                "SELECT conversation_id, reason, count(*) FROM temp.prune_messages
+                 WHERE reason IS NOT NULL GROUP BY conversation_id, reason",
+            )?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                let conversation_id: String = row.get(0)?;
+                let reason: String = row.get(1)?;
+                let count: usize = row.get(2)?;
+
+                match reason.as_str() {
+                    "expired" => report.expired_messages += count,
+                    "age" => report.aged_out_messages += count,
+                    _ => report.over_limit_messages += count,
+                }
+                *report.conversations.entry(conversation_id).or_default() += count;
+            }
+        }
+
+        self.execute_batch(REMOVE_MESSAGES)
+            .context("Removing pruned messages")?;
+
+        (report.evicted_attachments, report.evicted_attachment_bytes) = self
+            .query_row(
+                &format!(
+                    "SELECT count(*), coalesce(sum(length(content)), 0) FROM message_attachments
+                     WHERE id IN ({ATTACHMENTS_TO_EVICT})"
+                ),
+                named_params! { ":now": now },
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .context("Measuring attachments to evict")?;
+
+        self.execute(
+            &format!(
+                "UPDATE message_attachments SET content = x'', evicted_at = :now
+                 WHERE id IN ({ATTACHMENTS_TO_EVICT})"
+            ),
+            named_params! { ":now": now },
+        )
+        .context("Evicting attachments")?;
+
+        self.execute("DELETE FROM temp.prune_messages", [])?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{self, save_my_identity, BOB, GROUP, ME};
+
+    fn open_db() -> Connection {
+        let conn = test_utils::open_db();
+        save_my_identity(&conn);
+        conn.execute_batch(&format!(
+            r#"
+            INSERT INTO configs (config_type, id, value) VALUES
+                ('ConvoInfoVolatileConfig', '', json_array(
+                    json_object('type', 'one_to_one', 'session_id', '{BOB}', 'last_read', 0, 'unread', 0),
+                    json_object('type', 'group', 'id', '{GROUP}', 'last_read', 0, 'unread', 0)
+                ));
+            "#
+        ))
+        .expect("To save configs");
+        conn
+    }
+
+    fn insert_message(conn: &Connection, source: &str, sender: &str, receiver: &str, created: i64) {
+        conn.execute(
+            "INSERT INTO messages (source, hash, content, kind, sender, receiver, created_at, expiration_at)
+             VALUES (?1, ?2, '{}', 'visible', ?3, ?4, ?5, ?5 + 1000)",
+            params![source, format!("hash-{created}"), sender, receiver, created],
+        )
+        .expect("To insert message");
+    }
+
+    fn remaining(conn: &Connection) -> Vec<i64> {
+        conn.prepare("SELECT created_at FROM messages ORDER BY created_at")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn conversation_policy_replaces_global_one() {
+        let conn = open_db();
+        for created in 1..=5 {
+            insert_message(&conn, ME, BOB, ME, created);
+            insert_message(&conn, GROUP, BOB, GROUP, 10 + created);
+        }
+        conn.execute(
+            "INSERT INTO message_attachments (message_id, id, url, content_type, content)
+             SELECT id, 'a' || id, 'http://example.com', 'image/png', x'0102' FROM messages WHERE created_at = 15",
+            [],
+        )
+        .unwrap();
+
+        conn.save_retention_policy(&RetentionPolicy {
+            conversation_id: String::new(),
+            max_messages: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+        conn.save_retention_policy(&RetentionPolicy {
+            conversation_id: GROUP.to_string(),
+            max_messages: Some(4),
+            attachment_max_age: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let report = conn
+            .prune_messages(Timestamp::from_mills(100).unwrap())
+            .expect("To prune");
+        assert_eq!(report.over_limit_messages, 4);
+        assert_eq!(report.conversations.get(BOB), Some(&3));
+        assert_eq!(report.conversations.get(GROUP), Some(&1));
+        assert_eq!(report.evicted_attachments, 1);
+        assert_eq!(report.evicted_attachment_bytes, 2);
+        assert_eq!(remaining(&conn), vec![4, 5, 12, 13, 14, 15]);
+
+        // Disappearing messages expire
+        conn.execute(
+            "UPDATE messages SET expiry_mode = 'after_send', expire_timer = 1",
+            [],
+        )
+        .unwrap();
+        let report = conn
+            .prune_messages(Timestamp::from_mills(2000).unwrap())
+            .expect("To prune");
+        assert_eq!(report.expired_messages, 6);
+        assert!(remaining(&conn).is_empty());
+    }
+
+    #[test]
+    fn swarm_expiry_keeps_ordinary_history() {
+        let conn = open_db();
+        insert_message(&conn, ME, BOB, ME, 1);
+        insert_message(&conn, ME, BOB, ME, 2);
+        insert_message(&conn, ME, BOB, ME, 3);
+        conn.execute_batch(
+            "UPDATE messages SET expiry_mode = 'after_send', expire_timer = 1 WHERE created_at = 2;
+             UPDATE messages SET expiry_mode = 'after_read', expire_timer = 1 WHERE created_at = 3;",
+        )
+        .unwrap();
+
+        // Past the swarm expiry of all of them, but only the after-send one has a running timer
+        let report = conn
+            .prune_messages(Timestamp::from_mills(2000).unwrap())
+            .expect("To prune");
+        assert_eq!(report.expired_messages, 1);
+        assert_eq!(remaining(&conn), vec![1, 3]);
+    }
+}
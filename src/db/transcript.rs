@@ -0,0 +1,208 @@
+use anyhow::Context;
+use rusqlite::{named_params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_rows;
+use std::collections::HashMap;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TranscriptConversation {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub conversation_type: String,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub my_id: Option<String>,
+    #[serde(skip_serializing)]
+    pub my_blinded_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TranscriptMessage {
+    pub id: i64,
+    pub sender: String,
+    pub from_me: bool,
+    pub created_at: i64,
+    pub body: Option<String>,
+    pub quote_author: Option<String>,
+    pub quote_text: Option<String>,
+    pub quote_timestamp: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TranscriptReaction {
+    pub message_id: i64,
+    pub sender: String,
+    pub emoji: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscriptAttachment {
+    pub message_id: i64,
+    pub id: String,
+    pub url: String,
+    pub content_type: String,
+    pub content: Vec<u8>,
+}
+
+/// Creation time bounds in millis, `from` inclusive and `to` exclusive.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeRange {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+pub trait TranscriptRepositoryExt {
+    fn get_transcript_conversations(
+        &self,
+        id: Option<&str>,
+    ) -> anyhow::Result<Vec<TranscriptConversation>>;
+
+    fn get_transcript_messages(
+        &self,
+        conversation: &TranscriptConversation,
+        range: TimeRange,
+    ) -> anyhow::Result<Vec<TranscriptMessage>>;
+
+    fn get_transcript_reactions(
+        &self,
+        message_ids: &[i64],
+    ) -> anyhow::Result<Vec<TranscriptReaction>>;
+
+    fn get_transcript_attachments(
+        &self,
+        message_ids: &[i64],
+    ) -> anyhow::Result<Vec<TranscriptAttachment>>;
+
+    /// Display names of everyone known in the conversation, keyed by their session or blinded ID.
+    fn get_display_names(
+        &self,
+        conversation: &TranscriptConversation,
+    ) -> anyhow::Result<HashMap<String, String>>;
+}
+
+impl TranscriptRepositoryExt for Connection {
+    fn get_transcript_conversations(
+        &self,
+        id: Option<&str>,
+    ) -> anyhow::Result<Vec<TranscriptConversation>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT k.id, k.type AS conversation_type, coalesce(c.name, '') AS name, k.my_id, k.my_blinded_id
+             FROM conversation_keys k
+             LEFT JOIN conversations c ON c.id = k.id
+             WHERE ?1 IS NULL OR k.id = ?1
+             ORDER BY name, k.id",
+        )?;
+
+        let rows = stmt.query([id])?;
+        from_rows(rows)
+            .collect::<Result<_, _>>()
+            .context("Querying conversations")
+    }
+
+    fn get_transcript_messages(
+        &self,
+        conversation: &TranscriptConversation,
+        range: TimeRange,
+    ) -> anyhow::Result<Vec<TranscriptMessage>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT
+                m.id, m.sender, m.created_at, m.body,
+                coalesce(m.sender IN (:my_id, :my_blinded_id), 0) AS from_me,
+                m.content ->> '$.dataMessage.quote.author' AS quote_author,
+                m.content ->> '$.dataMessage.quote.text' AS quote_text,
+                CAST(m.content ->> '$.dataMessage.quote.id' AS INTEGER) AS quote_timestamp
+             FROM messages m
+             WHERE m.kind = 'visible'
+               AND (m.receiver = :id OR (:type = 'one_to_one' AND m.sender = :id AND m.receiver = :my_id))
+               AND (:from IS NULL OR m.created_at >= :from)
+               AND (:to IS NULL OR m.created_at < :to)
+             ORDER BY m.created_at, m.id",
+        )?;
+
+        let rows = stmt.query(named_params! {
+            ":id": conversation.id,
+            ":type": conversation.conversation_type,
+            ":my_id": conversation.my_id,
+            ":my_blinded_id": conversation.my_blinded_id,
+            ":from": range.from,
+            ":to": range.to,
+        })?;
+
+        from_rows(rows)
+            .collect::<Result<_, _>>()
+            .context("Querying messages")
+    }
+
+    fn get_transcript_reactions(
+        &self,
+        message_ids: &[i64],
+    ) -> anyhow::Result<Vec<TranscriptReaction>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT message_id, sender, emoji FROM message_reactions
+             WHERE message_id IN (SELECT value FROM json_each(?1))
+             ORDER BY message_id, created_at",
+        )?;
+
+        let rows = stmt.query([serde_json::to_string(message_ids)?])?;
+        from_rows(rows)
+            .collect::<Result<_, _>>()
+            .context("Querying reactions")
+    }
+
+    fn get_transcript_attachments(
+        &self,
+        message_ids: &[i64],
+    ) -> anyhow::Result<Vec<TranscriptAttachment>> {
+        let mut stmt = self.prepare_cached(
+            "SELECT message_id, id, url, content_type, content FROM message_attachments
+             WHERE message_id IN (SELECT value FROM json_each(?1))
+             ORDER BY message_id, rowid",
+        )?;
+
+        let attachments = stmt
+            .query_map([serde_json::to_string(message_ids)?], |row| {
+                Ok(TranscriptAttachment {
+                    message_id: row.get(0)?,
+                    id: row.get(1)?,
+                    url: row.get(2)?,
+                    content_type: row.get(3)?,
+                    content: row.get(4)?,
+                })
+            })?
+            .collect::<Result<_, _>>()
+            .context("Querying attachments")?;
+
+        Ok(attachments)
+    }
+
+    fn get_display_names(
+        &self,
+        conversation: &TranscriptConversation,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        // Later rows win: contacts' own names override what the group says about them
+        let mut stmt = self.prepare_cached(
+            "SELECT session_id, name FROM config_group_members
+             WHERE group_id = :id AND nullif(name, '') IS NOT NULL
+             UNION ALL
+             SELECT session_id, coalesce(nullif(nickname, ''), nullif(name, '')) FROM config_contacts
+             WHERE coalesce(nullif(nickname, ''), nullif(name, '')) IS NOT NULL
+             UNION ALL
+             SELECT my.id, p.name FROM config_user_profile p, (SELECT :my_id AS id UNION ALL SELECT :my_blinded_id) my
+             WHERE my.id IS NOT NULL AND nullif(p.name, '') IS NOT NULL",
+        )?;
+
+        let names = stmt
+            .query_map(
+                named_params! {
+                    ":id": conversation.id,
+                    ":my_id": conversation.my_id,
+                    ":my_blinded_id": conversation.my_blinded_id,
+                },
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            )?
+            .collect::<Result<_, _>>()
+            .context("Querying display names")?;
+
+        Ok(names)
+    }
+}
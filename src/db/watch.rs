@@ -1,3 +1,4 @@
+use crate::db::changes::TableChanges;
 use crate::db::TableName;
 use async_stream::stream;
 use futures_core::Stream;
@@ -7,7 +8,7 @@ use tokio::sync::broadcast;
 use tokio::time::sleep;
 
 pub fn with_changes<'a, F>(
-    mut receiver: broadcast::Receiver<TableName>,
+    mut receiver: broadcast::Receiver<TableChanges>,
     tables_to_watch: &'a [TableName],
     min_interval: Duration,
     mut produce: impl FnMut() -> F + 'a,
@@ -28,14 +29,20 @@ where
 }
 
 async fn wait_for(
-    receiver: &mut broadcast::Receiver<TableName>,
+    receiver: &mut broadcast::Receiver<TableChanges>,
     min_interval: Duration,
     tables_to_watch: &[TableName],
 ) -> Option<()> {
     let started = Instant::now();
     loop {
-        let change = receiver.recv().await.ok()?;
-        if tables_to_watch.contains(&change) {
+        let changed = match receiver.recv().await {
+            Ok(changes) => changes.iter().any(|c| tables_to_watch.contains(&c.table)),
+            // Some changes were missed, assume they were relevant
+            Err(broadcast::error::RecvError::Lagged(_)) => true,
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+
+        if changed {
             let elapsed = started.elapsed();
 
             if elapsed < min_interval {
@@ -0,0 +1,129 @@
+use super::changes::ChangeNotifier;
+use anyhow::anyhow;
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use tokio::sync::{mpsc, oneshot};
+
+/// The most write jobs committed together in one transaction.
+const MAX_BATCH_SIZE: usize = 128;
+
+pub(super) trait WriteJob: Send {
+    /// Runs the job inside the batch's transaction.
+    fn run(&mut self, conn: &Connection, changes: &ChangeNotifier);
+
+    /// Reports the outcome once the batch's transaction is settled.
+    fn finish(self: Box<Self>, committed: Result<(), &anyhow::Error>);
+}
+
+pub(super) struct Job<F, T> {
+    f: Option<F>,
+    result: Option<anyhow::Result<T>>,
+    reply: oneshot::Sender<anyhow::Result<T>>,
+}
+
+impl<F, T> Job<F, T> {
+    pub fn new(f: F) -> (Self, oneshot::Receiver<anyhow::Result<T>>) {
+        let (reply, rx) = oneshot::channel();
+        (
+            Self {
+                f: Some(f),
+                result: None,
+                reply,
+            },
+            rx,
+        )
+    }
+}
+
+impl<F, T> WriteJob for Job<F, T>
+where
+    F: FnOnce(&Connection) -> anyhow::Result<T> + Send,
+    T: Send,
+{
+    fn run(&mut self, conn: &Connection, changes: &ChangeNotifier) {
+        if let Some(f) = self.f.take() {
+            self.result = Some(run_in_savepoint(conn, changes, f));
+        }
+    }
+
+    fn finish(self: Box<Self>, committed: Result<(), &anyhow::Error>) {
+        let result = match (committed, self.result) {
+            (Err(e), _) => Err(anyhow!("Committing batched writes: {e:?}")),
+            (Ok(()), Some(result)) => result,
+            (Ok(()), None) => Err(anyhow!("Write job was never run")),
+        };
+
+        let _ = self.reply.send(result);
+    }
+}
+
+// A failing job only rolls back its own changes, not the rest of the batch
+fn run_in_savepoint<T>(
+    conn: &Connection,
+    changes: &ChangeNotifier,
+    f: impl FnOnce(&Connection) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    conn.execute_batch("SAVEPOINT write_job")?;
+    let mark = changes.pending_mark(conn);
+
+    let result = catch_unwind(AssertUnwindSafe(|| f(conn)))
+        .unwrap_or_else(|_| Err(anyhow!("Write job panicked")));
+
+    match result {
+        Ok(v) => {
+            conn.execute_batch("RELEASE write_job")?;
+            Ok(v)
+        }
+        Err(e) => {
+            conn.execute_batch("ROLLBACK TO write_job; RELEASE write_job")?;
+            changes.forget_pending_since(conn, mark);
+            Err(e)
+        }
+    }
+}
+
+pub(super) fn run_writer(
+    conn: PooledConnection<SqliteConnectionManager>,
+    mut jobs: mpsc::UnboundedReceiver<Box<dyn WriteJob>>,
+    changes: ChangeNotifier,
+) {
+    while let Some(first) = jobs.blocking_recv() {
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH_SIZE {
+            match jobs.try_recv() {
+                Ok(job) => batch.push(job),
+                Err(_) => break,
+            }
+        }
+
+        let committed = write_batch(&conn, &changes, &mut batch);
+        match &committed {
+            Ok(()) => changes.publish(&conn),
+            Err(e) => {
+                changes.discard_committed(&conn);
+                log::error!("Error writing {} batched jobs: {e:?}", batch.len());
+            }
+        }
+
+        for job in batch {
+            job.finish(committed.as_ref().map(|_| ()));
+        }
+    }
+
+    log::info!("Database writer stopped");
+}
+
+fn write_batch(
+    conn: &Connection,
+    changes: &ChangeNotifier,
+    batch: &mut [Box<dyn WriteJob>],
+) -> anyhow::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for job in batch.iter_mut() {
+        job.run(&tx, changes);
+    }
+    tx.commit()?;
+    Ok(())
+}
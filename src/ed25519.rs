@@ -4,7 +4,7 @@ use crate::{
 };
 
 define_key_type!(ED25519PubKey, 32);
-define_key_type!(ED25519SecKey, 64);
+define_key_type!(ED25519SecKey, 64, secret);
 
 impl ED25519PubKey {
     pub fn to_curve25519(&self) -> Curve25519PubKey {
@@ -23,6 +23,18 @@ impl ED25519PubKey {
 
         curve25519_pubkey.into()
     }
+
+    pub fn verify(&self, msg: &[u8], signature: &[u8]) -> bool {
+        signature.len() == 64
+            && unsafe {
+                crate::bindings::session_ed25519_verify(
+                    signature.as_ptr(),
+                    self.as_ptr(),
+                    msg.as_ptr(),
+                    msg.len(),
+                )
+            }
+    }
 }
 
 impl ED25519SecKey {
@@ -51,7 +63,7 @@ impl ED25519SecKey {
                 self.as_ptr(),
                 msg.as_ptr(),
                 msg.len(),
-                signature.as_mut_ptr()
+                signature.as_mut_ptr(),
             )
         };
 
@@ -0,0 +1,132 @@
+use crate::clock::Timestamp;
+use crate::db::messages::MessageKind;
+use crate::db::retention::PruneReport;
+use crate::network::NetworkState;
+use crate::session_id::{GroupID, IndividualID, IndividualOrBlindedID, SessionID};
+use serde::Serialize;
+use std::io::Write;
+use tokio::sync::broadcast;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    MessageReceived {
+        source: String,
+        hash: String,
+        sender: IndividualOrBlindedID,
+        receiver: SessionID,
+        kind: MessageKind,
+        created_at: Timestamp,
+    },
+    MessageSent {
+        source: String,
+        hash: String,
+        created_at: Timestamp,
+    },
+    MessageFailed {
+        source: String,
+        created_at: Timestamp,
+        error: String,
+    },
+    ConfigMerged {
+        kind: &'static str,
+        id: Option<String>,
+    },
+    GroupInvited {
+        group_id: GroupID,
+        inviter: IndividualID,
+        name: String,
+    },
+    GroupJoined {
+        group_id: GroupID,
+    },
+    GroupPromoted {
+        group_id: GroupID,
+        promoter: IndividualID,
+    },
+    GroupKicked {
+        group_id: GroupID,
+    },
+    GroupInviteAccepted {
+        group_id: GroupID,
+        member: IndividualID,
+    },
+    GroupMembersRemoved {
+        group_id: GroupID,
+        members: Vec<IndividualID>,
+    },
+    NetworkStateChanged {
+        state: NetworkStatus,
+    },
+    MessagesPruned {
+        report: PruneReport,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum NetworkStatus {
+    Idle,
+    Connecting,
+    Connected { public_ips: Vec<String> },
+    Error { error: String },
+}
+
+impl From<&NetworkState> for NetworkStatus {
+    fn from(state: &NetworkState) -> Self {
+        match state {
+            NetworkState::Idle => Self::Idle,
+            NetworkState::Connecting => Self::Connecting,
+            NetworkState::Connected(ips) => Self::Connected {
+                public_ips: ips.iter().map(|ip| ip.to_string()).collect(),
+            },
+            NetworkState::Error(e) => Self::Error {
+                error: format!("{e:?}"),
+            },
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(256).0,
+        }
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, event: Event) {
+        log::debug!("Publishing event: {event:?}");
+        // Nobody listening is not an error
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+
+    /// Writes every event as one line of JSON to stdout, until the bus is gone.
+    pub async fn print_json_lines(&self) -> anyhow::Result<()> {
+        let mut rx = self.subscribe();
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    let line = serde_json::to_string(&event)?;
+                    let mut stdout = std::io::stdout().lock();
+                    writeln!(stdout, "{line}")?;
+                    stdout.flush()?;
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    log::warn!("Dropped {n} events while printing");
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+}
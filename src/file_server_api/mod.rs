@@ -0,0 +1,18 @@
+mod upload;
+
+pub use upload::*;
+
+use crate::curve25519::Curve25519PubKey;
+use crate::utils::HttpBaseUrl;
+
+pub const FILE_SERVER_URL: &str = "http://filev2.getsession.org";
+pub const FILE_SERVER_PUB_KEY: &str =
+    "da21e1d886c6fbaea313f75298bd64aab03a97ce985b46bb2dad9f2089c8ee59";
+
+pub fn file_server_url() -> HttpBaseUrl {
+    HttpBaseUrl::new(FILE_SERVER_URL).expect("To have a valid file server URL")
+}
+
+pub fn file_server_pub_key() -> Curve25519PubKey {
+    Curve25519PubKey::from_hex(FILE_SERVER_PUB_KEY).expect("To have a valid file server key")
+}
@@ -0,0 +1,76 @@
+use crate::http_api::{HttpApi, HttpJsonApiError};
+use crate::utils::HttpBaseUrl;
+use bytes::Bytes;
+use http::{Method, StatusCode};
+use serde::Deserialize;
+use std::borrow::Cow;
+use url::Url;
+
+/// Uploads an already encrypted file, which the file server keeps for a limited time.
+pub struct UploadFile {
+    pub data: Bytes,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UploadedFile {
+    pub id: serde_json::Value,
+}
+
+impl UploadedFile {
+    /// Where others can download the file from.
+    pub fn download_url(&self, base: &HttpBaseUrl) -> Url {
+        let id = match &self.id {
+            serde_json::Value::String(id) => id.clone(),
+            id => id.to_string(),
+        };
+
+        base.build_upon()
+            .append_path("file")
+            .append_path(&id)
+            .build()
+    }
+}
+
+impl HttpApi for UploadFile {
+    type Response = Result<UploadedFile, HttpJsonApiError>;
+
+    fn method(&self) -> Method {
+        Method::POST
+    }
+
+    fn path_segments(&self) -> impl Iterator<Item = Cow<str>> {
+        std::iter::once(Cow::Borrowed("file"))
+    }
+
+    fn queries(&self) -> impl Iterator<Item = (Cow<str>, Cow<str>)> {
+        std::iter::empty()
+    }
+
+    fn request_content_type(&self) -> Option<Cow<str>> {
+        Some(Cow::Borrowed("application/octet-stream"))
+    }
+
+    fn request_body(&self) -> Option<Bytes> {
+        Some(self.data.clone())
+    }
+
+    fn expected_response_type(&self) -> Option<Cow<str>> {
+        Some(Cow::Borrowed("application/json"))
+    }
+
+    fn deserialize_response(
+        &self,
+        status_code: StatusCode,
+        _content_type: Option<&str>,
+        buf: &[u8],
+    ) -> Self::Response {
+        if !status_code.is_success() {
+            return Err(HttpJsonApiError::UnsuccessfulResponse {
+                status_code,
+                message: std::str::from_utf8(buf).ok().map(ToString::to_string),
+            });
+        }
+
+        Ok(serde_json::from_slice(buf)?)
+    }
+}
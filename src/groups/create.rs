@@ -0,0 +1,63 @@
+use crate::config::{Group, GroupInfo, UserGroupsConfig};
+use crate::db::Repository;
+use crate::ed25519;
+use crate::identity::Identity;
+use crate::session_id::GroupID;
+use crate::worker::GroupConfigState;
+use anyhow::Context;
+use tokio::sync::watch;
+
+/// Creates a new group with us as its only member and admin, and adds it to our user groups
+/// along with its secret key. The group configs still have to be saved and pushed.
+pub async fn create_group(
+    repo: &Repository,
+    identity: &Identity,
+    user_groups: &watch::Sender<UserGroupsConfig>,
+    name: &str,
+    description: Option<&str>,
+    my_name: &str,
+) -> anyhow::Result<GroupConfigState> {
+    let (pub_key, sec_key) = ed25519::gen_pair();
+    let group_id = GroupID::new(pub_key);
+
+    let mut state = GroupConfigState::new(
+        repo,
+        identity.ed25519_sec_key(),
+        group_id.clone(),
+        Some(&sec_key),
+    )
+    .await
+    .context("Creating group configs")?;
+
+    state.group_info.set_name(name)?;
+    if let Some(description) = description {
+        state.group_info.set_description(description)?;
+    }
+
+    let mut me = state
+        .group_members
+        .get_or_construct_member(&identity.session_id())
+        .context("Adding ourselves to the group")?;
+    me.set_name(my_name)?;
+    me.set_admin(true);
+    state.group_members.set_member(&me);
+
+    // The initial key has to be readable by the members we just added
+    state
+        .group_keys
+        .rekey(&mut state.group_info, &mut state.group_members)?;
+
+    let mut result = Ok(());
+    user_groups.send_if_modified(|c| {
+        result = c.get_or_create_group(group_id.as_str()).and_then(|info| {
+            let mut info = GroupInfo::from(info);
+            info.set_name(name);
+            info.set_sec_key(&sec_key);
+            c.set_group(&Group::Group(info))
+        });
+        result.is_ok()
+    });
+    result.context("Adding the group to user groups")?;
+
+    Ok(state)
+}
@@ -0,0 +1,269 @@
+use super::push_group_configs;
+use crate::clock::ClockSource;
+use crate::config::{GroupAuthData, MemberStatus, UserGroupsConfig};
+use crate::identity::Identity;
+use crate::network::swarm::SwarmState;
+use crate::oxenss::store::StoreMessageRequest;
+use crate::oxenss::{JsonRpcCallSource, JsonRpcCallSourceExt};
+use crate::protos::{Content, DataMessage, GroupUpdateInviteMessage, GroupUpdateMessage};
+use crate::session_id::{GroupID, IndividualID};
+use crate::worker::{GroupConfigState, DEFAULT_MESSAGE_TTL};
+use anyhow::{ensure, Context};
+use tokio::sync::watch;
+
+/// An invitation to a group, as sent by one of its admins.
+pub struct GroupInvite {
+    pub group_id: GroupID,
+    pub name: String,
+    pub auth_data: GroupAuthData,
+}
+
+impl GroupInvite {
+    /// Finds the invite in a message sent to `me`, if there is one, and checks it was signed by
+    /// the group.
+    pub fn from_content(content: &Content, me: &IndividualID) -> Option<anyhow::Result<Self>> {
+        let data_message = content.data_message.as_ref()?;
+        let invite = data_message
+            .group_update_message
+            .as_ref()?
+            .invite_message
+            .as_ref()?;
+
+        Some((|| {
+            let group_id: GroupID = invite.group_session_id.parse()?;
+            let timestamp = data_message.timestamp.context("Invite without timestamp")?;
+            ensure!(
+                group_id.pub_key().verify(
+                    format!("INVITE{me}{timestamp}").as_bytes(),
+                    &invite.admin_signature,
+                ),
+                "Invalid invite signature for {group_id}"
+            );
+
+            Ok(Self {
+                group_id,
+                name: invite.name.clone(),
+                auth_data: invite
+                    .member_auth_data
+                    .as_slice()
+                    .try_into()
+                    .context("Invalid auth data")?,
+            })
+        })())
+    }
+}
+
+/// Whether a message to the group is a member letting the admins know they joined.
+pub fn accepts_invite(content: &Content) -> bool {
+    content
+        .data_message
+        .as_ref()
+        .and_then(|d| d.group_update_message.as_ref())
+        .and_then(|u| u.invite_response.as_ref())
+        .is_some_and(|r| r.is_approved)
+}
+
+/// Marks the member's invite as accepted. Only admins can, and the change is left for the
+/// group config push to send out. Returns whether anything changed.
+pub fn accept_member_invite(
+    configs: &watch::Sender<GroupConfigState>,
+    member_id: &IndividualID,
+) -> bool {
+    configs.send_if_modified(|s| {
+        if !s.group_keys.is_admin() {
+            return false;
+        }
+
+        match s.group_members.get(member_id) {
+            Some(mut member) if member.invite_status() != MemberStatus::Accepted => {
+                member.set_invite_status(MemberStatus::Accepted);
+                s.group_members.set_member(&member);
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Adds the members to the group and sends each of them an invite. Returns the members whose
+/// invite couldn't be sent; they stay in the group with a failed invite status.
+pub async fn invite_members<CS>(
+    call_source: &CS,
+    identity: &Identity,
+    user_groups: &watch::Sender<UserGroupsConfig>,
+    configs: &watch::Sender<GroupConfigState>,
+    members: &[IndividualID],
+    clock: &ClockSource,
+) -> anyhow::Result<Vec<(IndividualID, anyhow::Error)>>
+where
+    CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
+{
+    let group_id = configs.borrow().group_id.clone();
+    let admin_key = user_groups
+        .borrow()
+        .get_group(&group_id)
+        .and_then(|g| g.sec_key())
+        .context("Only admins can invite members")?;
+
+    let mut auth_data = Vec::with_capacity(members.len());
+    let mut result = Ok(());
+    configs.send_if_modified(|s| {
+        result = (|| {
+            for member_id in members {
+                let mut member = s
+                    .group_members
+                    .get_or_construct_member(member_id)
+                    .with_context(|| format!("Adding {member_id}"))?;
+                member.set_invite_status(MemberStatus::Sent);
+                s.group_members.set_member(&member);
+                auth_data.push(s.group_keys.make_subaccount(member_id)?);
+            }
+
+            // New members get a key they can read
+            s.group_keys
+                .rekey(&mut s.group_info, &mut s.group_members)?;
+            anyhow::Ok(())
+        })();
+        true
+    });
+    result?;
+
+    let swarm = SwarmState::new(group_id.clone().into());
+    let auth = (configs.subscribe(), user_groups.subscribe());
+    push_group_configs(call_source, &swarm, configs, &auth, clock)
+        .await
+        .context("Pushing new members")?;
+
+    let name = configs.borrow().group_info.name().to_string();
+    let mut failed = Vec::new();
+    for (member_id, auth_data) in members.iter().zip(auth_data) {
+        let now = clock.now_or_uncalibrated();
+        let signature = admin_key.sign(format!("INVITE{member_id}{}", now.as_millis()).as_bytes());
+        let content = Content {
+            data_message: Some(DataMessage {
+                group_update_message: Some(GroupUpdateMessage {
+                    invite_message: Some(GroupUpdateInviteMessage {
+                        group_session_id: group_id.to_string(),
+                        name: name.clone(),
+                        member_auth_data: auth_data.to_vec(),
+                        admin_signature: signature.to_vec(),
+                    }),
+                    ..Default::default()
+                }),
+                timestamp: Some(now.as_millis()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let sent = async {
+            let req = StoreMessageRequest::new_unauthenticated(
+                identity.ed25519_sec_key(),
+                member_id,
+                &content,
+                DEFAULT_MESSAGE_TTL,
+                now,
+                now,
+            )?;
+            call_source
+                .perform_json_rpc(SwarmState::new(member_id.clone().into()), &req)
+                .await
+                .context("Storing invite")?;
+            anyhow::Ok(())
+        }
+        .await;
+
+        let status = match sent {
+            Ok(()) => MemberStatus::Sent,
+            Err(e) => {
+                log::error!("Error inviting {member_id} to {group_id}: {e:?}");
+                failed.push((member_id.clone(), e));
+                MemberStatus::Failed
+            }
+        };
+
+        configs.send_if_modified(|s| match s.group_members.get(member_id) {
+            Some(mut member) if member.invite_status() != status => {
+                member.set_invite_status(status);
+                s.group_members.set_member(&member);
+                true
+            }
+            _ => false,
+        });
+    }
+
+    if !failed.is_empty() {
+        push_group_configs(call_source, &swarm, configs, &auth, clock)
+            .await
+            .context("Pushing invite status")?;
+    }
+
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IndividualConfig;
+    use crate::db::Repository;
+    use crate::groups::create_group;
+    use crate::protos::GroupUpdateInviteResponseMessage;
+    use crate::test_utils::{temp_path, BOB, EVE};
+    use r2d2_sqlite::SqliteConnectionManager;
+
+    fn invite_response(is_approved: bool) -> Content {
+        Content {
+            data_message: Some(DataMessage {
+                group_update_message: Some(GroupUpdateMessage {
+                    invite_response: Some(GroupUpdateInviteResponseMessage { is_approved }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn admins_mark_accepted_invites() {
+        assert!(accepts_invite(&invite_response(true)));
+        assert!(!accepts_invite(&invite_response(false)));
+        assert!(!accepts_invite(&Content::default()));
+
+        let path = temp_path("sqlite3");
+        let repo =
+            Repository::new(SqliteConnectionManager::file(&*path), None).expect("To create repo");
+        let identity = Identity::gen();
+        let user_groups =
+            watch::channel(UserGroupsConfig::new(identity.ed25519_sec_key(), None).unwrap()).0;
+        let state = create_group(&repo, &identity, &user_groups, "Group", None, "Me")
+            .await
+            .expect("To create group");
+        let configs = watch::channel(state).0;
+
+        let bob: IndividualID = BOB.parse().unwrap();
+        configs.send_modify(|s| {
+            let mut member = s.group_members.get_or_construct_member(&bob).unwrap();
+            member.set_invite_status(MemberStatus::Sent);
+            s.group_members.set_member(&member);
+        });
+
+        assert!(accept_member_invite(&configs, &bob));
+        let status = configs
+            .borrow()
+            .group_members
+            .get(&bob)
+            .unwrap()
+            .invite_status();
+        assert_eq!(status, MemberStatus::Accepted);
+
+        // Nothing to push for repeated responses or strangers
+        assert!(!accept_member_invite(&configs, &bob));
+        assert!(!accept_member_invite(&configs, &EVE.parse().unwrap()));
+        assert!(configs
+            .borrow()
+            .group_members
+            .get(&EVE.parse().unwrap())
+            .is_none());
+    }
+}
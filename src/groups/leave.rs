@@ -0,0 +1,76 @@
+use super::send_group_message;
+use crate::clock::ClockSource;
+use crate::config_state::ConfigState;
+use crate::db::config::ConfigRepositoryExt;
+use crate::db::messages::MessageRepositoryExt;
+use crate::db::Repository;
+use crate::identity::Identity;
+use crate::network::swarm::SwarmState;
+use crate::oxenss::JsonRpcCallSource;
+use crate::protos::{Content, DataMessage, GroupUpdateMemberLeftMessage, GroupUpdateMessage};
+use crate::worker::{GroupConfigState, DEFAULT_MESSAGE_TTL};
+use anyhow::Context;
+use tokio::sync::watch;
+
+/// Tells the group we're leaving, then forgets it along with everything we have of it. The
+/// user groups config still has to be saved and pushed.
+pub async fn leave_group<CS>(
+    call_source: &CS,
+    repo: &Repository,
+    identity: &Identity,
+    config_state: &ConfigState,
+    configs: &watch::Sender<GroupConfigState>,
+    clock: &ClockSource,
+) -> anyhow::Result<()>
+where
+    CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
+{
+    let group_id = configs.borrow().group_id.clone();
+    let now = clock.now_or_uncalibrated();
+    let content = Content {
+        data_message: Some(DataMessage {
+            group_update_message: Some(GroupUpdateMessage {
+                member_left_message: Some(GroupUpdateMemberLeftMessage {}),
+                ..Default::default()
+            }),
+            timestamp: Some(now.as_millis()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    // Leaving goes ahead regardless, the admins just won't know until they notice
+    let auth = (
+        configs.subscribe(),
+        config_state.user_groups_config.subscribe(),
+    );
+    if let Err(e) = send_group_message(
+        call_source,
+        &configs.subscribe(),
+        &auth,
+        &identity.session_id(),
+        &content,
+        DEFAULT_MESSAGE_TTL,
+        now,
+        now,
+    )
+    .await
+    {
+        log::warn!("Unable to tell {group_id} we're leaving: {e:?}");
+    }
+
+    config_state.remove_group(&group_id);
+
+    let removed = repo
+        .write({
+            let group_id = group_id.clone();
+            move |conn| {
+                conn.delete_configs(group_id.as_str())?;
+                conn.delete_group_messages(&group_id)
+            }
+        })
+        .await
+        .context("Deleting group data")?;
+    log::info!("Left {group_id}, deleted {removed} messages");
+    Ok(())
+}
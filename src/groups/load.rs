@@ -0,0 +1,55 @@
+use crate::config::UserGroupsConfig;
+use crate::db::config::ConfigRepositoryExt;
+use crate::db::Repository;
+use crate::identity::Identity;
+use crate::session_id::GroupID;
+use crate::worker::GroupConfigState;
+use anyhow::Context;
+use tokio::sync::watch;
+
+/// Loads the saved configs of a group we're in, with its admin key if we have one.
+pub async fn load_group(
+    repo: &Repository,
+    identity: &Identity,
+    user_groups: &watch::Sender<UserGroupsConfig>,
+    group_id: &GroupID,
+) -> anyhow::Result<watch::Sender<GroupConfigState>> {
+    let sec_key = user_groups
+        .borrow()
+        .get_group(group_id)
+        .with_context(|| format!("Not in group {group_id}"))?
+        .sec_key();
+
+    let state = GroupConfigState::new(
+        repo,
+        identity.ed25519_sec_key(),
+        group_id.clone(),
+        sec_key.as_ref(),
+    )
+    .await?;
+
+    Ok(watch::channel(state).0)
+}
+
+/// Saves the group configs, normally done by the group sync.
+pub async fn save_group(
+    repo: &Repository,
+    configs: &watch::Sender<GroupConfigState>,
+) -> anyhow::Result<()> {
+    let mut records = Ok(Vec::new());
+    configs.send_if_modified(|s| {
+        records = s.take_records();
+        false
+    });
+
+    let records = records?;
+    let group_id = configs.borrow().group_id.to_string();
+    repo.write(move |conn| {
+        for record in &records {
+            conn.save_config_record(record, Some(&group_id))?;
+        }
+        Ok(())
+    })
+    .await
+    .context("Saving group configs")
+}
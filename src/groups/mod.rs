@@ -0,0 +1,19 @@
+mod create;
+mod invite;
+mod leave;
+mod load;
+mod promote;
+mod push;
+mod remove;
+mod send;
+mod update;
+
+pub use create::*;
+pub use invite::*;
+pub use leave::*;
+pub use load::*;
+pub use promote::*;
+pub use push::*;
+pub use remove::*;
+pub use send::*;
+pub use update::*;
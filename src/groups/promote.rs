@@ -0,0 +1,179 @@
+use super::{push_group_configs, queue_group_update};
+use crate::clock::ClockSource;
+use crate::config::{MemberStatus, UserGroupsConfig};
+use crate::db::Repository;
+use crate::ed25519::{self, ED25519SecKey};
+use crate::identity::Identity;
+use crate::network::swarm::SwarmState;
+use crate::oxenss::store::StoreMessageRequest;
+use crate::oxenss::{JsonRpcCallSource, JsonRpcCallSourceExt};
+use crate::protos::{
+    group_update_member_change_message, Content, DataMessage, GroupUpdateMemberChangeMessage,
+    GroupUpdateMessage, GroupUpdatePromoteMessage,
+};
+use crate::session_id::{GroupID, IndividualID};
+use crate::worker::{GroupConfigState, DEFAULT_MESSAGE_TTL};
+use anyhow::Context;
+use tokio::sync::watch;
+
+/// A promotion to admin of a group, carrying the group's secret key.
+pub struct GroupPromotion {
+    pub group_id: GroupID,
+    pub name: String,
+    pub sec_key: ED25519SecKey,
+}
+
+impl GroupPromotion {
+    /// Finds the promotion in a message, if there is one. The seed is the proof: it has to
+    /// derive the group's key pair.
+    pub fn from_content(content: &Content) -> Option<anyhow::Result<Self>> {
+        let promotion = content
+            .data_message
+            .as_ref()?
+            .group_update_message
+            .as_ref()?
+            .promote_message
+            .as_ref()?;
+
+        Some((|| {
+            let seed: [u8; 32] = promotion
+                .group_identity_seed
+                .as_slice()
+                .try_into()
+                .context("Invalid group seed")?;
+            let (pub_key, sec_key) = ed25519::gen_pair_from_seed(seed);
+
+            Ok(Self {
+                group_id: GroupID::new(pub_key),
+                name: promotion.name.clone(),
+                sec_key,
+            })
+        })())
+    }
+}
+
+/// Makes the members admins and sends each of them the group's secret key. Returns the members
+/// the promotion couldn't be sent to; their promotion status is marked as failed.
+pub async fn promote_members<CS>(
+    call_source: &CS,
+    repo: &Repository,
+    identity: &Identity,
+    user_groups: &watch::Sender<UserGroupsConfig>,
+    configs: &watch::Sender<GroupConfigState>,
+    members: &[IndividualID],
+    clock: &ClockSource,
+) -> anyhow::Result<Vec<(IndividualID, anyhow::Error)>>
+where
+    CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
+{
+    let group_id = configs.borrow().group_id.clone();
+    let admin_key = user_groups
+        .borrow()
+        .get_group(&group_id)
+        .and_then(|g| g.sec_key())
+        .context("Only admins can promote members")?;
+
+    let mut result = Ok(());
+    configs.send_if_modified(|s| {
+        result = (|| {
+            for member_id in members {
+                let mut member = s
+                    .group_members
+                    .get(member_id)
+                    .with_context(|| format!("{member_id} is not a member"))?;
+                member.set_admin(true);
+                member.set_promotion_status(MemberStatus::Sent);
+                s.group_members.set_member(&member);
+            }
+            anyhow::Ok(())
+        })();
+        true
+    });
+    result?;
+
+    let swarm = SwarmState::new(group_id.clone().into());
+    let auth = (configs.subscribe(), user_groups.subscribe());
+    push_group_configs(call_source, &swarm, configs, &auth, clock)
+        .await
+        .context("Pushing promoted members")?;
+
+    let name = configs.borrow().group_info.name().to_string();
+    let mut failed = Vec::new();
+    for member_id in members {
+        let now = clock.now_or_uncalibrated();
+        let content = Content {
+            data_message: Some(DataMessage {
+                group_update_message: Some(GroupUpdateMessage {
+                    promote_message: Some(GroupUpdatePromoteMessage {
+                        group_identity_seed: admin_key.seed().to_vec(),
+                        name: name.clone(),
+                    }),
+                    ..Default::default()
+                }),
+                timestamp: Some(now.as_millis()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let sent = async {
+            let req = StoreMessageRequest::new_unauthenticated(
+                identity.ed25519_sec_key(),
+                member_id,
+                &content,
+                DEFAULT_MESSAGE_TTL,
+                now,
+                now,
+            )?;
+            call_source
+                .perform_json_rpc(SwarmState::new(member_id.clone().into()), &req)
+                .await
+                .context("Storing promotion")?;
+            anyhow::Ok(())
+        }
+        .await;
+
+        if let Err(e) = sent {
+            log::error!("Error promoting {member_id} in {group_id}: {e:?}");
+            configs.send_if_modified(|s| match s.group_members.get(member_id) {
+                Some(mut member) => {
+                    member.set_promotion_status(MemberStatus::Failed);
+                    s.group_members.set_member(&member);
+                    true
+                }
+                None => false,
+            });
+            failed.push((member_id.clone(), e));
+        }
+    }
+
+    if !failed.is_empty() {
+        push_group_configs(call_source, &swarm, configs, &auth, clock)
+            .await
+            .context("Pushing promotion status")?;
+    }
+
+    let promoted: Vec<String> = members
+        .iter()
+        .filter(|m| !failed.iter().any(|(f, _)| f == *m))
+        .map(ToString::to_string)
+        .collect();
+    if !promoted.is_empty() {
+        let now = clock.now_or_uncalibrated();
+        let change_type = group_update_member_change_message::Type::Promoted as i32;
+        let signature =
+            admin_key.sign(format!("MEMBER_CHANGE{change_type}{}", now.as_millis()).as_bytes());
+        let update = GroupUpdateMessage {
+            member_change_message: Some(GroupUpdateMemberChangeMessage {
+                r#type: change_type,
+                member_session_ids: promoted,
+                admin_signature: signature.to_vec(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        queue_group_update(repo, &identity.session_id(), &group_id, update, now).await?;
+    }
+
+    Ok(failed)
+}
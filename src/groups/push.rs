@@ -0,0 +1,128 @@
+use crate::clock::ClockSource;
+use crate::config::Config;
+use crate::network::swarm::{SwarmAuth, SwarmState};
+use crate::oxenss::delete::DeleteMessagesRequest;
+use crate::oxenss::namespace::{
+    GroupInfoConfigNamespace, GroupKeysNamespace, GroupMemberConfigNamespace, MessageNamespace,
+};
+use crate::oxenss::store::StoreMessageRequest;
+use crate::oxenss::{JsonRpcCallSource, JsonRpcCallSourceExt};
+use crate::worker::{GroupConfigState, CONFIG_TTL};
+use anyhow::Context;
+use tokio::sync::watch;
+
+/// Pushes whatever group configs need pushing, signed with the group's admin key. Keys go first
+/// so that members can decrypt the info and members configs as soon as they see them.
+pub async fn push_group_configs<CS>(
+    call_source: &CS,
+    swarm: &SwarmState,
+    configs: &watch::Sender<GroupConfigState>,
+    auth: &impl SwarmAuth,
+    clock: &ClockSource,
+) -> anyhow::Result<()>
+where
+    CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
+{
+    let mut push_data = None;
+    configs.send_if_modified(|s| {
+        push_data = Some((
+            s.group_keys.needs_push().then(|| s.group_keys.push()),
+            s.group_info.needs_push().then(|| s.group_info.push()),
+            s.group_members.needs_push().then(|| s.group_members.push()),
+        ));
+        false
+    });
+
+    let (keys, info, members) = push_data.context("Empty push data")?;
+    if let Some(keys) = keys.transpose()?.flatten() {
+        let hash = store::<GroupKeysNamespace, _>(call_source, swarm, auth, clock, &keys)
+            .await
+            .context("Storing group keys")?;
+        // Keys have no seqno, only the pushed data is remembered until it comes back merged
+        configs.send_modify(|s| s.group_keys.confirm_pushed(0, &hash));
+    }
+
+    let mut obsolete = Vec::new();
+    for (push, is_info) in [(info, true), (members, false)] {
+        let Some(push) = push.transpose()? else {
+            continue;
+        };
+
+        let hash = if is_info {
+            store::<GroupInfoConfigNamespace, _>(
+                call_source,
+                swarm,
+                auth,
+                clock,
+                push.config_data(),
+            )
+            .await
+            .context("Storing group info")?
+        } else {
+            store::<GroupMemberConfigNamespace, _>(
+                call_source,
+                swarm,
+                auth,
+                clock,
+                push.config_data(),
+            )
+            .await
+            .context("Storing group members")?
+        };
+
+        let seqno = push.seqno;
+        configs.send_modify(|s| {
+            if is_info {
+                s.group_info.confirm_pushed(seqno, &hash)
+            } else {
+                s.group_members.confirm_pushed(seqno, &hash)
+            }
+        });
+
+        obsolete.extend(
+            push.obsolete_message_hashes()
+                .filter_map(|h| h.to_str().ok())
+                .map(ToString::to_string),
+        );
+    }
+
+    if !obsolete.is_empty() {
+        let req = DeleteMessagesRequest::new(auth, &obsolete)?;
+        if let Err(e) = call_source.perform_json_rpc(swarm.clone(), &req).await {
+            log::warn!(
+                "Failed to delete {} obsolete group configs: {e:?}",
+                obsolete.len()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn store<NS, CS>(
+    call_source: &CS,
+    swarm: &SwarmState,
+    auth: &impl SwarmAuth,
+    clock: &ClockSource,
+    data: &[u8],
+) -> anyhow::Result<String>
+where
+    NS: MessageNamespace,
+    CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
+{
+    let req = StoreMessageRequest::new_authenticated::<NS>(
+        auth,
+        data,
+        CONFIG_TTL,
+        clock.now_or_uncalibrated(),
+    )?;
+    let hash = call_source
+        .perform_json_rpc(swarm.clone(), &req)
+        .await?
+        .hash;
+    log::info!(
+        "Pushed group config to namespace {}, hash = {hash}",
+        NS::INT_VALUE
+    );
+    Ok(hash)
+}
@@ -0,0 +1,241 @@
+use super::{push_group_configs, queue_group_update};
+use crate::clock::ClockSource;
+use crate::config::UserGroupsConfig;
+use crate::db::messages::MessageRepositoryExt;
+use crate::db::models::MessageSource;
+use crate::db::Repository;
+use crate::identity::Identity;
+use crate::network::swarm::SwarmState;
+use crate::oxenss::delete::DeleteMessagesRequest;
+use crate::oxenss::revoke::RevokeSubaccountRequest;
+use crate::oxenss::{JsonRpcCallSource, JsonRpcCallSourceExt};
+use crate::protos::{
+    group_update_member_change_message, Content, GroupUpdateDeleteMemberContentMessage,
+    GroupUpdateMemberChangeMessage, GroupUpdateMessage,
+};
+use crate::session_id::{GroupID, IndividualID};
+use crate::worker::GroupConfigState;
+use anyhow::{anyhow, ensure, Context};
+use std::borrow::Cow;
+use tokio::sync::watch;
+
+/// Removes the members from the group. The keys are rotated so they can't read anything sent
+/// from now on, and their sub-accounts are revoked so the swarm stops serving them. With
+/// `delete_content`, whatever they posted is deleted as well.
+pub async fn remove_members<CS>(
+    call_source: &CS,
+    repo: &Repository,
+    identity: &Identity,
+    user_groups: &watch::Sender<UserGroupsConfig>,
+    configs: &watch::Sender<GroupConfigState>,
+    members: &[IndividualID],
+    delete_content: bool,
+    clock: &ClockSource,
+) -> anyhow::Result<()>
+where
+    CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
+{
+    let group_id = configs.borrow().group_id.clone();
+    let admin_key = user_groups
+        .borrow()
+        .get_group(&group_id)
+        .and_then(|g| g.sec_key())
+        .context("Only admins can remove members")?;
+
+    let mut tokens = Vec::with_capacity(members.len());
+    let mut result = Ok(());
+    configs.send_if_modified(|s| {
+        result = (|| {
+            // Nothing changes unless every member can be removed
+            let mut removed = Vec::with_capacity(members.len());
+            for member_id in members {
+                let member = s
+                    .group_members
+                    .get(member_id)
+                    .with_context(|| format!("{member_id} is not a member"))?;
+                tokens.push(s.group_keys.subaccount_token(member_id)?);
+                removed.push(member);
+            }
+
+            for member_id in members {
+                s.group_members.remove_member(member_id);
+            }
+
+            // Removed members must not get the new key
+            let rekeyed = s
+                .group_keys
+                .rekey(&mut s.group_info, &mut s.group_members)
+                .map(|_| ());
+            if rekeyed.is_err() {
+                for member in &removed {
+                    s.group_members.set_member(member);
+                }
+            }
+            rekeyed
+        })();
+        result.is_ok()
+    });
+    result?;
+
+    let swarm = SwarmState::new(group_id.clone().into());
+    let auth = (configs.subscribe(), user_groups.subscribe());
+    push_group_configs(call_source, &swarm, configs, &auth, clock)
+        .await
+        .context("Pushing group without the removed members")?;
+
+    let req = RevokeSubaccountRequest::new(&auth, &tokens, clock.now_or_uncalibrated())?;
+    call_source
+        .perform_json_rpc(swarm.clone(), &req)
+        .await
+        .map_err(|e| anyhow!("Revoking sub-accounts: {e}"))?;
+
+    let member_ids: Vec<String> = members.iter().map(ToString::to_string).collect();
+    let me = identity.session_id();
+
+    if delete_content {
+        let mut hashes = Vec::new();
+        for member_id in members {
+            let (group_id, member_id) = (group_id.clone(), member_id.clone());
+            hashes.extend(
+                repo.write(move |conn| {
+                    conn.delete_messages_from(
+                        &MessageSource::GroupSwarm(Cow::Owned(group_id)),
+                        &member_id,
+                    )
+                })
+                .await
+                .context("Deleting member content")?,
+            );
+        }
+
+        if !hashes.is_empty() {
+            let req = DeleteMessagesRequest::new(&auth, &hashes)?;
+            if let Err(e) = call_source.perform_json_rpc(swarm.clone(), &req).await {
+                log::warn!(
+                    "Failed to delete {} messages of removed members: {e:?}",
+                    hashes.len()
+                );
+            }
+        }
+
+        // Lets the remaining members drop the content too
+        let now = clock.now_or_uncalibrated();
+        let signature = admin_key.sign(
+            format!(
+                "DELETE_CONTENT{}{}{}",
+                now.as_millis(),
+                member_ids.concat(),
+                hashes.concat()
+            )
+            .as_bytes(),
+        );
+        let update = GroupUpdateMessage {
+            delete_member_content: Some(GroupUpdateDeleteMemberContentMessage {
+                member_session_ids: member_ids.clone(),
+                message_hashes: hashes,
+                admin_signature: Some(signature.to_vec()),
+            }),
+            ..Default::default()
+        };
+        queue_group_update(repo, &me, &group_id, update, now).await?;
+    }
+
+    let now = clock.now_or_uncalibrated();
+    let change_type = group_update_member_change_message::Type::Removed as i32;
+    let signature =
+        admin_key.sign(format!("MEMBER_CHANGE{change_type}{}", now.as_millis()).as_bytes());
+    let update = GroupUpdateMessage {
+        member_change_message: Some(GroupUpdateMemberChangeMessage {
+            r#type: change_type,
+            member_session_ids: member_ids,
+            admin_signature: signature.to_vec(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    queue_group_update(repo, &me, &group_id, update, now).await
+}
+
+/// The members an admin announced as removed in a message to the group, if it is one.
+pub fn removed_members(
+    content: &Content,
+    group_id: &GroupID,
+) -> Option<anyhow::Result<Vec<IndividualID>>> {
+    let data_message = content.data_message.as_ref()?;
+    let change = data_message
+        .group_update_message
+        .as_ref()?
+        .member_change_message
+        .as_ref()?;
+    if change.r#type != group_update_member_change_message::Type::Removed as i32 {
+        return None;
+    }
+
+    Some((|| {
+        let timestamp = data_message
+            .timestamp
+            .context("Member change without timestamp")?;
+        ensure!(
+            group_id.pub_key().verify(
+                format!("MEMBER_CHANGE{}{timestamp}", change.r#type).as_bytes(),
+                &change.admin_signature,
+            ),
+            "Invalid member change signature in {group_id}"
+        );
+
+        change
+            .member_session_ids
+            .iter()
+            .map(|id| id.parse().context("Invalid member session ID"))
+            .collect()
+    })())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ed25519::gen_pair;
+    use crate::protos::group_update_member_change_message::Type;
+    use crate::protos::DataMessage;
+    use crate::test_utils::BOB;
+
+    fn member_change(change_type: Type, signature: &[u8]) -> Content {
+        Content {
+            data_message: Some(DataMessage {
+                group_update_message: Some(GroupUpdateMessage {
+                    member_change_message: Some(GroupUpdateMemberChangeMessage {
+                        r#type: change_type as i32,
+                        member_session_ids: vec![BOB.to_string()],
+                        admin_signature: signature.to_vec(),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                timestamp: Some(1000),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn removals_need_the_admin_signature() {
+        let (group_key, admin_key) = gen_pair();
+        let group_id = GroupID::from(group_key);
+        let message = format!("MEMBER_CHANGE{}1000", Type::Removed as i32);
+
+        let signed = member_change(Type::Removed, &admin_key.sign(message.as_bytes()));
+        assert_eq!(
+            removed_members(&signed, &group_id).unwrap().unwrap(),
+            vec![BOB.parse::<IndividualID>().unwrap()]
+        );
+
+        let (_, other_key) = gen_pair();
+        let forged = member_change(Type::Removed, &other_key.sign(message.as_bytes()));
+        assert!(removed_members(&forged, &group_id).unwrap().is_err());
+
+        // Other member changes are none of its business
+        let promoted = member_change(Type::Promoted, &admin_key.sign(message.as_bytes()));
+        assert!(removed_members(&promoted, &group_id).is_none());
+    }
+}
@@ -0,0 +1,55 @@
+use crate::clock::Timestamp;
+use crate::network::swarm::{SwarmAuth, SwarmState};
+use crate::oxenss::namespace::GroupNamespace;
+use crate::oxenss::store::StoreMessageRequest;
+use crate::oxenss::{JsonRpcCallSource, JsonRpcCallSourceExt};
+use crate::protos::{envelope, Content, Envelope};
+use crate::session_id::IndividualID;
+use crate::worker::GroupConfigState;
+use anyhow::{anyhow, Context};
+use prost::Message;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Encrypts `content` with the group's current key and stores it on the group swarm at
+/// `timestamp`, with the envelope saying it was `sent_at`. Returns the hash the swarm gave it.
+pub async fn send_group_message<CS>(
+    call_source: &CS,
+    configs: &watch::Receiver<GroupConfigState>,
+    auth: &impl SwarmAuth,
+    sender: &IndividualID,
+    content: &Content,
+    ttl: Duration,
+    sent_at: Timestamp,
+    timestamp: Timestamp,
+) -> anyhow::Result<String>
+where
+    CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
+{
+    let envelope = Envelope {
+        r#type: envelope::Type::ClosedGroupMessage as i32,
+        source: Some(sender.to_string()),
+        timestamp: sent_at.as_millis(),
+        content: Some(content.encode_to_vec()),
+        ..Default::default()
+    };
+
+    // Signing borrows the configs again, so the keys must be let go of first
+    let (group_id, encrypted) = {
+        let configs = configs.borrow();
+        let encrypted = configs
+            .group_keys
+            .encrypt_message(&envelope.encode_to_vec())
+            .context("Encrypting group message")?;
+        (configs.group_id.clone(), encrypted.as_ref().to_vec())
+    };
+
+    let req =
+        StoreMessageRequest::new_authenticated::<GroupNamespace>(auth, &encrypted, ttl, timestamp)?;
+    let hash = call_source
+        .perform_json_rpc(SwarmState::new(group_id.into()), &req)
+        .await
+        .map_err(|e| anyhow!("Storing group message: {e}"))?
+        .hash;
+    Ok(hash)
+}
@@ -0,0 +1,38 @@
+use crate::clock::Timestamp;
+use crate::db::messages::{Message, MessageRepositoryExt};
+use crate::db::Repository;
+use crate::protos::{Content, DataMessage, GroupUpdateMessage};
+use crate::session_id::{GroupID, IndividualID};
+use crate::worker::DEFAULT_MESSAGE_TTL;
+use anyhow::Context;
+
+/// Queues a group update message, which the send worker posts to the group swarm.
+pub async fn queue_group_update(
+    repo: &Repository,
+    sender: &IndividualID,
+    group_id: &GroupID,
+    update: GroupUpdateMessage,
+    now: Timestamp,
+) -> anyhow::Result<()> {
+    let content = Content {
+        data_message: Some(DataMessage {
+            group_update_message: Some(update),
+            timestamp: Some(now.as_millis()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let message = Message::new_outgoing(
+        sender.clone(),
+        group_id.clone().into(),
+        &content,
+        now,
+        Timestamp::from_mills(now.as_millis() + DEFAULT_MESSAGE_TTL.as_millis() as u64)
+            .context("Invalid expiration")?,
+    )?;
+
+    repo.write(move |conn| conn.save_messages(std::iter::once(message)).map(|_| ()))
+        .await
+        .context("Queueing group update")
+}
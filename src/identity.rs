@@ -6,10 +6,11 @@ use crate::ed25519::{self, ED25519SecKey};
 use crate::mnemonic::ENGLISH;
 use crate::network::swarm::SwarmAuth;
 use crate::session_id::IndividualID;
-use anyhow::anyhow;
+use anyhow::{anyhow, bail, Context};
 use serde::Serialize;
 use std::borrow::Cow;
 use std::fmt::{Debug, Formatter};
+use zeroize::Zeroizing;
 
 #[derive(Clone, Eq, PartialEq)]
 pub struct Identity {
@@ -36,21 +37,34 @@ impl Identity {
     }
 
     pub fn from_mnemonic(mnemonic: &str) -> anyhow::Result<Self> {
-        let seed =
-            hex::decode(crate::mnemonic::decode(&mnemonic, &ENGLISH).expect("To decode mnemonic"))
-                .expect("To decode mnemonic hex");
+        let seed_hex = Zeroizing::new(
+            crate::mnemonic::decode(mnemonic, &ENGLISH).context("Decoding mnemonic")?,
+        );
+        Self::from_hex_seed(&seed_hex)
+    }
 
-        let ed25519_key_pair = ed25519::gen_pair_from_seed(ed25519::pad_ed25519_seed(&seed));
+    pub fn from_hex_seed(seed_hex: &str) -> anyhow::Result<Self> {
+        let seed = Zeroizing::new(hex::decode(seed_hex.trim()).context("Decoding seed hex")?);
+        if seed.is_empty() || seed.len() > 32 {
+            bail!("Invalid seed length: {}", seed.len());
+        }
 
-        Ok(Self {
-            sec_key: ed25519_key_pair.1.to_curve25519(),
-            session_id: IndividualID::new(ed25519_key_pair.0.to_curve25519()),
-            ed25519_key_pair,
-        })
+        Ok(Self::from_seed(&Zeroizing::new(ed25519::pad_ed25519_seed(
+            &seed,
+        ))))
+    }
+
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        Self::new(ed25519::gen_pair_from_seed(*seed))
+    }
+
+    pub fn seed(&self) -> Zeroizing<[u8; 32]> {
+        Zeroizing::new(self.ed25519_sec_key().seed())
     }
 
-    pub fn mnemonic(&self) -> String {
-        crate::mnemonic::encode(&hex::encode(self.ed25519_sec_key().seed()), &ENGLISH)
+    pub fn mnemonic(&self) -> Zeroizing<String> {
+        let seed_hex = Zeroizing::new(hex::encode(*self.seed()));
+        Zeroizing::new(crate::mnemonic::encode(&seed_hex, &ENGLISH))
     }
 
     pub fn sec_key(&self) -> &Curve25519SecKey {
@@ -1,5 +1,20 @@
 #[macro_export]
 macro_rules! define_key_type {
+    // Secret keys are wiped from memory when dropped
+    ($type_name:ident, $len:literal, secret) => {
+        $crate::define_key_type!($type_name, $len);
+
+        impl Drop for $type_name {
+            fn drop(&mut self) {
+                use zeroize::Zeroize;
+                self.binary.zeroize();
+                if let Some(hex) = self.hex.take() {
+                    hex.into_bytes().zeroize();
+                }
+            }
+        }
+    };
+
     ($type_name:ident, $len:literal) => {
         #[derive(Clone)]
         pub struct $type_name {
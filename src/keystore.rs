@@ -0,0 +1,193 @@
+use crate::identity::Identity;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, bail, ensure, Context};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::RngCore;
+use std::io::Write;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+// File layout:
+//   magic (4) | version (1) | m_cost KiB (4, LE) | t_cost (4, LE) | p_cost (4, LE)
+//   | salt (16) | nonce (12) | encrypted seed (32 + 16 tag)
+//
+// Everything before the ciphertext is authenticated as associated data.
+const MAGIC: &[u8; 4] = b"SCKS";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = 4 + 1 + 4 * 3 + SALT_LEN + NONCE_LEN;
+const SEED_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+
+/// Argon2id parameters used to derive the key that encrypts the seed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub m_cost_kib: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            m_cost_kib: 64 * 1024,
+            t_cost: 3,
+            p_cost: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    fn derive_key(&self, passphrase: &str, salt: &[u8]) -> anyhow::Result<Zeroizing<[u8; 32]>> {
+        let params = Params::new(self.m_cost_kib, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| anyhow!("Invalid KDF parameters: {e}"))?;
+
+        let mut key = Zeroizing::new([0u8; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt, key.as_mut_slice())
+            .map_err(|e| anyhow!("Deriving key: {e}"))?;
+        Ok(key)
+    }
+}
+
+/// Encrypts the identity's seed with `passphrase` and writes it to `path`, replacing any
+/// existing keystore.
+pub fn save(
+    path: &Path,
+    identity: &Identity,
+    passphrase: &str,
+    kdf: KdfParams,
+) -> anyhow::Result<()> {
+    ensure!(!passphrase.is_empty(), "The passphrase must not be empty");
+
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+    let mut file = Vec::with_capacity(HEADER_LEN + SEED_LEN + TAG_LEN);
+    file.extend_from_slice(MAGIC);
+    file.push(VERSION);
+    file.extend_from_slice(&kdf.m_cost_kib.to_le_bytes());
+    file.extend_from_slice(&kdf.t_cost.to_le_bytes());
+    file.extend_from_slice(&kdf.p_cost.to_le_bytes());
+    file.extend_from_slice(&salt);
+    file.extend_from_slice(&nonce);
+
+    let key = kdf.derive_key(passphrase, &salt)?;
+    let seed = identity.seed();
+    let encrypted = Aes256Gcm::new_from_slice(key.as_slice())
+        .context("Creating cipher")?
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: seed.as_slice(),
+                aad: &file,
+            },
+        )
+        .map_err(|_| anyhow!("Encrypting seed"))?;
+    file.extend_from_slice(&encrypted);
+
+    write_private_file(path, &file).with_context(|| format!("Writing {}", path.display()))
+}
+
+/// Decrypts the keystore at `path` and restores the identity in it.
+pub fn unlock(path: &Path, passphrase: &str) -> anyhow::Result<Identity> {
+    let file = std::fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+    ensure!(
+        file.len() == HEADER_LEN + SEED_LEN + TAG_LEN && file.starts_with(MAGIC),
+        "{} is not a keystore file",
+        path.display()
+    );
+
+    if file[4] != VERSION {
+        bail!("Unsupported keystore version {}", file[4]);
+    }
+
+    let (header, encrypted) = file.split_at(HEADER_LEN);
+    let u32_at = |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+    let kdf = KdfParams {
+        m_cost_kib: u32_at(5),
+        t_cost: u32_at(9),
+        p_cost: u32_at(13),
+    };
+    let salt = &header[17..17 + SALT_LEN];
+    let nonce = &header[17 + SALT_LEN..];
+
+    let key = kdf.derive_key(passphrase, salt)?;
+    let seed = Zeroizing::new(
+        Aes256Gcm::new_from_slice(key.as_slice())
+            .context("Creating cipher")?
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: encrypted,
+                    aad: header,
+                },
+            )
+            .map_err(|_| anyhow!("Unable to unlock keystore: wrong passphrase?"))?,
+    );
+
+    let seed: Zeroizing<[u8; SEED_LEN]> = Zeroizing::new(
+        seed.as_slice()
+            .try_into()
+            .context("Invalid seed in keystore")?,
+    );
+    Ok(Identity::from_seed(&seed))
+}
+
+fn write_private_file(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::rename(tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::swarm::SwarmAuth;
+
+    const TEST_KDF: KdfParams = KdfParams {
+        m_cost_kib: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn keystore_round_trips() {
+        let path = std::env::temp_dir().join(format!("{}.keystore", uuid::Uuid::new_v4()));
+        let _cleanup = scopeguard::guard(path.clone(), |path| {
+            let _ = std::fs::remove_file(path);
+        });
+
+        let identity = Identity::gen();
+        save(&path, &identity, "correct horse", TEST_KDF).expect("To save keystore");
+
+        let unlocked = unlock(&path, "correct horse").expect("To unlock keystore");
+        assert_eq!(unlocked.session_id(), identity.session_id());
+        assert_eq!(unlocked.ed25519_sec_key(), identity.ed25519_sec_key());
+
+        assert!(unlock(&path, "wrong horse").is_err());
+
+        // Tampering with the header is detected
+        let mut file = std::fs::read(&path).unwrap();
+        file[5] ^= 1;
+        std::fs::write(&path, file).unwrap();
+        assert!(unlock(&path, "correct horse").is_err());
+    }
+}
@@ -2,24 +2,41 @@
 
 extern crate link_cplusplus;
 
-use crate::clock::ClockSource;
+use crate::app_setting::identity::PublicIdentity;
+use crate::clock::{local_timestamp, ClockSource, Timestamp};
+use crate::config::{Config, Contact};
 use crate::config_state::ConfigState;
+use crate::cwrapper::CWrapper;
 use crate::db::app_setting::AppSettingRepositoryExt;
+use crate::db::dead_letters::DeadLetterRepositoryExt;
+use crate::db::messages::{Message as DbMessage, MessageRepositoryExt};
+use crate::db::retention::{RetentionPolicy, RetentionRepositoryExt};
+use crate::events::EventBus;
+use crate::groups::GroupInvite;
 use crate::identity::Identity;
 use crate::network::batch::BatchManager;
 use crate::network::legacy::LegacyNetwork;
 use crate::network::swarm::{SwarmAuth, SwarmManager, SwarmState};
 use crate::oxenss::namespace::{
-    ContactsNamespace, ConvoInfoVolatileConfigNamespace, DefaultNamespace,
+    ContactsNamespace, ConvoInfoVolatileConfigNamespace, DefaultNamespace, MessageNamespace,
     UserGroupsConfigNamespace, UserProfileConfigNamespace,
 };
-use crate::worker::{sync_config, sync_groups, sync_messages};
-use clap::{Parser, Subcommand};
+use crate::session_id::{GroupID, IndividualID, SessionID};
+use crate::transcript::{AttachmentMode, ExportFormat, ExportOptions};
+use crate::worker::{
+    expire_read_messages, prune_messages, publish_network_state, push_config, send_messages,
+    sync_config, sync_groups, sync_messages, track_group_updates, DEFAULT_MESSAGE_TTL,
+};
+use anyhow::Context;
+use clap::{ArgGroup, Parser, Subcommand};
 use r2d2_sqlite::SqliteConnectionManager;
 use reqwest::Client;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, watch};
 use tokio::try_join;
+use zeroize::Zeroizing;
 
 mod oxenss;
 
@@ -39,6 +56,7 @@ mod ed25519;
 mod hex_encode;
 mod identity;
 mod ip;
+mod keystore;
 
 #[macro_use]
 mod key;
@@ -47,6 +65,9 @@ mod config_state;
 mod crypto;
 mod cwrapper;
 mod db;
+mod events;
+mod file_server_api;
+mod groups;
 mod io;
 mod logging;
 mod message_crypto;
@@ -59,6 +80,9 @@ mod network;
 mod service;
 mod session_id;
 mod sogs_api;
+#[cfg(test)]
+mod test_utils;
+mod transcript;
 mod utils;
 mod worker;
 
@@ -73,6 +97,22 @@ mod protos {
 
 #[derive(Parser, Debug)]
 struct Cli {
+    /// Path to the database file
+    #[clap(long, env = "SESSION_DB")]
+    db: Option<PathBuf>,
+
+    /// Passphrase of the encrypted database
+    #[clap(long, env = "SESSION_DB_PASSPHRASE", hide_env_values = true)]
+    db_passphrase: Option<String>,
+
+    /// Path to the identity keystore file
+    #[clap(long, env = "SESSION_KEYSTORE")]
+    keystore: Option<PathBuf>,
+
+    /// Passphrase of the identity keystore
+    #[clap(long, env = "SESSION_KEYSTORE_PASSPHRASE", hide_env_values = true)]
+    keystore_passphrase: Option<String>,
+
     #[command(subcommand)]
     commands: Commands,
 }
@@ -81,15 +121,207 @@ struct Cli {
 enum Commands {
     GenKey,
     RetrieveConfigMessages {
+        /// Use this identity instead of the one in the keystore
         #[clap(short, long, env)]
-        mnemonic: String,
+        mnemonic: Option<String>,
+        /// Print every event to stdout as a line of JSON
+        #[clap(long)]
+        print_events: bool,
+        /// Stream the events as server-sent events from /events on this address
+        #[clap(long)]
+        listen: Option<SocketAddr>,
+    },
+    /// Saves an identity into the keystore
+    #[clap(group(ArgGroup::new("source").required(true).args(["mnemonic", "seed"])))]
+    ImportIdentity {
+        #[clap(long)]
+        mnemonic: Option<String>,
+        /// Hex encoded ed25519 seed
+        #[clap(long)]
+        seed: Option<String>,
+    },
+    /// Prints the mnemonic of the identity in the keystore
+    ExportIdentity {
+        /// Don't ask for confirmation
+        #[clap(long)]
+        yes: bool,
+    },
+    /// Encrypts an existing plaintext database with a passphrase
+    EncryptDb {
+        #[clap(long, env = "SESSION_DB_NEW_PASSPHRASE", hide_env_values = true)]
+        new_passphrase: String,
+    },
+    /// Changes the passphrase of an encrypted database
+    ChangeDbPassphrase {
+        #[clap(long, env = "SESSION_DB_NEW_PASSPHRASE", hide_env_values = true)]
+        new_passphrase: String,
+    },
+    /// Writes the account and its local data into an encrypted archive
+    Backup {
+        archive: PathBuf,
+        #[clap(long, env = "SESSION_BACKUP_PASSPHRASE", hide_env_values = true)]
+        backup_passphrase: String,
+    },
+    /// Replaces the local data with the content of a backup archive
+    Restore {
+        archive: PathBuf,
+        #[clap(long, env = "SESSION_BACKUP_PASSPHRASE", hide_env_values = true)]
+        backup_passphrase: String,
+        /// Restore even if the archive belongs to a different account
+        #[clap(long)]
+        force: bool,
+    },
+    /// Exports conversation transcripts, one file per conversation
+    ExportTranscript {
+        /// Directory to write the transcripts into
+        out_dir: PathBuf,
+        /// Only export this conversation
+        #[clap(long)]
+        conversation: Option<String>,
+        #[clap(long, value_enum, default_value = "markdown")]
+        format: ExportFormat,
+        #[clap(long, value_enum, default_value = "inline")]
+        attachments: AttachmentMode,
+        /// Timezone of the timestamps, e.g. Europe/London
+        #[clap(long, default_value = "UTC")]
+        timezone: chrono_tz::Tz,
+        /// First day to export (YYYY-MM-DD)
+        #[clap(long)]
+        from: Option<chrono::NaiveDate>,
+        /// Last day to export (YYYY-MM-DD)
+        #[clap(long)]
+        to: Option<chrono::NaiveDate>,
+    },
+    /// Sets the retention policy of a conversation, or the global one
+    SetRetention {
+        /// Conversation the policy is for. The global policy applies to every conversation
+        /// without its own
+        #[clap(long)]
+        conversation: Option<String>,
+        /// Keep at most this many messages per conversation
+        #[clap(long)]
+        max_messages: Option<u32>,
+        /// Remove messages older than this many days
+        #[clap(long)]
+        max_age_days: Option<u64>,
+        /// Remove attachment content older than this many days, keeping the messages
+        #[clap(long)]
+        attachment_max_age_days: Option<u64>,
+    },
+    /// Removes the retention policy of a conversation, or the global one
+    ClearRetention {
+        #[clap(long)]
+        conversation: Option<String>,
+    },
+    /// Lists the retention policies
+    ListRetention,
+    /// Prunes messages now and prints what was removed
+    Prune,
+    /// Lists the messages that couldn't be decrypted yet
+    ListDeadLetters {
+        /// Only the ones polled from this swarm or community
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Marks a conversation as read on all devices
+    MarkRead {
+        conversation: String,
+        /// Read marker in millis since epoch, defaults to now
+        #[clap(long)]
+        up_to: Option<u64>,
+        /// Also let the sender know, for one-to-one conversations
+        #[clap(long)]
+        send_receipt: bool,
+    },
+    /// Adds or updates an approved contact
+    AddContact {
+        session_id: String,
+        #[clap(long)]
+        name: Option<String>,
+        #[clap(long)]
+        nickname: Option<String>,
+    },
+    /// Sets the nickname of a contact, empty to clear it
+    SetContactNickname {
+        session_id: String,
+        nickname: String,
+    },
+    /// Approves message requests from a contact
+    ApproveContact {
+        session_id: String,
+    },
+    /// Blocks a contact
+    BlockContact {
+        session_id: String,
+        /// Unblocks the contact instead
+        #[clap(long)]
+        unblock: bool,
+    },
+    /// Updates our display name and avatar and pushes them right away, or the next time the
+    /// client runs if that fails
+    #[clap(group(ArgGroup::new("avatar_change").args(["avatar", "remove_avatar"])))]
+    SetProfile {
+        #[clap(long)]
+        name: Option<String>,
+        /// Image file to use as the avatar
+        #[clap(long)]
+        avatar: Option<PathBuf>,
+        #[clap(long)]
+        remove_avatar: bool,
+    },
+    /// Creates a group with us as its admin and prints its ID
+    CreateGroup {
+        name: String,
+        #[clap(long)]
+        description: Option<String>,
+    },
+    /// Invites members to a group we're an admin of
+    InviteGroupMembers {
+        group_id: String,
+        #[clap(required = true)]
+        members: Vec<String>,
+    },
+    /// Removes members from a group we're an admin of
+    RemoveGroupMembers {
+        group_id: String,
+        #[clap(required = true)]
+        members: Vec<String>,
+        /// Also deletes the messages they posted
+        #[clap(long)]
+        delete_content: bool,
+    },
+    /// Makes members admins of a group we're an admin of
+    PromoteGroupMembers {
+        group_id: String,
+        #[clap(required = true)]
+        members: Vec<String>,
+    },
+    /// Joins a group we were invited to
+    AcceptGroupInvite {
+        group_id: String,
+    },
+    /// Turns down a group invite
+    DeclineGroupInvite {
+        group_id: String,
+    },
+    /// Leaves a group and deletes its messages
+    LeaveGroup {
+        group_id: String,
+    },
+    /// Queues a text message, sent the next time the client runs
+    SendMessage {
+        /// Session ID of the recipient
+        to: String,
+        body: String,
     },
 }
 
 const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(10);
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 10);
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> anyhow::Result<()> {
     let _ = dotenvy::dotenv();
     env_logger::init();
 
@@ -102,13 +334,834 @@ async fn main() {
     //     }
     // }
 
-    let db_file = dirs::home_dir().unwrap().join("Temp/session.sqlite3db");
-    let _ = std::fs::remove_file(&db_file);
+    let cli = Cli::parse();
+    // Not wiped on start anymore: it holds the message history and may be encrypted, so it has
+    // to outlive a run. Point --db at a fresh path to start over.
+    let db_file = cli
+        .db
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join("Temp/session.sqlite3db"));
+    let keystore_file = cli
+        .keystore
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join("Temp/session.keystore"));
+    let db_passphrase = cli.db_passphrase.map(Zeroizing::new);
+    let db_passphrase = db_passphrase.as_ref().map(|p| p.as_str());
+    let keystore_passphrase = cli.keystore_passphrase.map(Zeroizing::new);
+    let unlock_keystore = || -> anyhow::Result<Identity> {
+        keystore::unlock(
+            &keystore_file,
+            keystore_passphrase
+                .as_deref()
+                .context("The keystore passphrase is required")?,
+        )
+        .context("Unlocking the keystore")
+    };
+
+    match cli.commands {
+        Commands::GenKey => {
+            let identity = Identity::gen();
+            println!("Session ID: {}", identity.session_id());
+            println!("Mnemonic: {}", identity.mnemonic().as_str());
+        }
+
+        Commands::ImportIdentity { mnemonic, seed } => {
+            let identity = match (mnemonic, seed) {
+                (Some(mnemonic), _) => Identity::from_mnemonic(&mnemonic),
+                (None, Some(seed)) => Identity::from_hex_seed(&seed),
+                (None, None) => unreachable!("One of mnemonic or seed is required"),
+            }
+            .context("Invalid identity")?;
+
+            keystore::save(
+                &keystore_file,
+                &identity,
+                keystore_passphrase
+                    .as_deref()
+                    .context("The keystore passphrase is required")?,
+                Default::default(),
+            )
+            .context("Saving the keystore")?;
+            println!("Imported {}", identity.session_id());
+        }
+
+        Commands::ExportIdentity { yes } => {
+            let identity = unlock_keystore()?;
+            if !yes {
+                eprint!(
+                    "Anyone with the mnemonic has full control of {}. Type 'yes' to show it: ",
+                    identity.session_id()
+                );
+                let mut answer = String::new();
+                std::io::stdin()
+                    .read_line(&mut answer)
+                    .expect("To read confirmation");
+                if answer.trim() != "yes" {
+                    eprintln!("Aborted");
+                    return Ok(());
+                }
+            }
+
+            println!("{}", identity.mnemonic().as_str());
+        }
+
+        Commands::EncryptDb { new_passphrase } => {
+            let new_passphrase = Zeroizing::new(new_passphrase);
+            db::encryption::encrypt_database(&db_file, &new_passphrase)
+                .expect("To encrypt the database");
+        }
+
+        Commands::ChangeDbPassphrase { new_passphrase } => {
+            let new_passphrase = Zeroizing::new(new_passphrase);
+            db::encryption::change_passphrase(
+                &db_file,
+                db_passphrase.expect("To have the current passphrase"),
+                &new_passphrase,
+            )
+            .expect("To change the database passphrase");
+        }
+
+        Commands::Backup {
+            archive,
+            backup_passphrase,
+        } => {
+            let backup_passphrase = Zeroizing::new(backup_passphrase);
+            let identity = unlock_keystore()?;
+            let repo = open_repo(&db_file, db_passphrase);
+            let info = {
+                let archive = archive.clone();
+                repo.read(move |conn| {
+                    db::backup::backup(conn, &identity, &archive, &backup_passphrase)
+                })
+                .await
+                .expect("To back up")
+            };
+            println!(
+                "Backed up {} at schema version {} to {}",
+                info.identity.session_id(),
+                info.schema_version,
+                archive.display()
+            );
+        }
+
+        Commands::Restore {
+            archive,
+            backup_passphrase,
+            force,
+        } => {
+            let backup_passphrase = Zeroizing::new(backup_passphrase);
+            let repo = open_repo(&db_file, db_passphrase);
+            let info = repo
+                .transaction(move |conn| {
+                    db::backup::restore(conn, &archive, &backup_passphrase, force)
+                })
+                .await
+                .expect("To restore");
+            println!("Restored {}", info.identity.session_id());
+
+            match keystore_passphrase.as_deref() {
+                Some(passphrase) => {
+                    keystore::save(&keystore_file, &info.identity, passphrase, Default::default())
+                        .expect("To save the keystore");
+                }
+                None => eprintln!(
+                    "No keystore passphrase given, import the identity with import-identity before running"
+                ),
+            }
+        }
+
+        Commands::ExportTranscript {
+            out_dir,
+            conversation,
+            format,
+            attachments,
+            timezone,
+            from,
+            to,
+        } => {
+            let repo = open_repo(&db_file, db_passphrase);
+            let options = ExportOptions {
+                format,
+                attachments,
+                range: ExportOptions::days_to_range(&timezone, from, to)
+                    .expect("To have a valid date range"),
+                timezone,
+            };
+
+            let files = repo
+                .read(move |conn| {
+                    transcript::export(conn, conversation.as_deref(), &out_dir, &options)
+                })
+                .await
+                .expect("To export transcripts");
+            for file in files {
+                println!("{}", file.display());
+            }
+        }
+
+        Commands::SetRetention {
+            conversation,
+            max_messages,
+            max_age_days,
+            attachment_max_age_days,
+        } => {
+            let policy = RetentionPolicy {
+                conversation_id: conversation.unwrap_or_default(),
+                max_messages,
+                max_age: max_age_days.map(|d| d * MILLIS_PER_DAY),
+                attachment_max_age: attachment_max_age_days.map(|d| d * MILLIS_PER_DAY),
+            };
+            open_repo(&db_file, db_passphrase)
+                .write(move |conn| conn.save_retention_policy(&policy))
+                .await
+                .expect("To save retention policy");
+        }
+
+        Commands::ClearRetention { conversation } => {
+            let removed = open_repo(&db_file, db_passphrase)
+                .write(move |conn| {
+                    conn.remove_retention_policy(conversation.as_deref().unwrap_or_default())
+                })
+                .await
+                .expect("To remove retention policy");
+            if !removed {
+                eprintln!("There was no such policy");
+            }
+        }
+
+        Commands::ListRetention => {
+            let policies = open_repo(&db_file, db_passphrase)
+                .read(|conn| conn.get_retention_policies())
+                .await
+                .expect("To get retention policies");
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&policies).expect("To serialize policies")
+            );
+        }
+
+        Commands::Prune => {
+            let report = open_repo(&db_file, db_passphrase)
+                .write(|conn| conn.prune_messages(local_timestamp()))
+                .await
+                .expect("To prune messages");
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).expect("To serialize report")
+            );
+        }
+
+        Commands::ListDeadLetters { source } => {
+            let letters = open_repo(&db_file, db_passphrase)
+                .read(move |conn| conn.get_dead_letters(source.as_deref(), None, local_timestamp()))
+                .await
+                .expect("To get dead letters");
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&letters).expect("To serialize dead letters")
+            );
+        }
+
+        Commands::MarkRead {
+            conversation,
+            up_to,
+            send_receipt,
+        } => {
+            let identity = unlock_keystore()?;
+            let repo = open_repo(&db_file, db_passphrase);
+            let config_state = ConfigState::new(&repo, identity.ed25519_sec_key())
+                .await
+                .expect("To create a new config state");
+            let up_to = up_to
+                .map(|t| Timestamp::from_mills(t).context("Invalid --up-to timestamp"))
+                .transpose()?
+                .unwrap_or_else(local_timestamp);
+
+            let Some(previous) = config_state
+                .mark_read(&conversation, up_to)
+                .with_context(|| format!("Marking {conversation} as read"))?
+            else {
+                eprintln!("Already read");
+                return Ok(());
+            };
+
+            push_and_save::<ConvoInfoVolatileConfigNamespace, _>(
+                &repo,
+                &identity,
+                &config_state.convo_info_volatile_config,
+            )
+            .await;
+
+            match conversation.parse::<SessionID>() {
+                Ok(SessionID::Individual(contact))
+                    if send_receipt && contact != *identity.session_id() =>
+                {
+                    queue_read_receipt(&repo, &identity, contact, previous, up_to).await
+                }
+                _ if send_receipt => {
+                    eprintln!("Receipts are only sent in one-to-one conversations")
+                }
+                _ => {}
+            }
+        }
+
+        Commands::AddContact {
+            session_id,
+            name,
+            nickname,
+        } => {
+            update_contact(
+                &db_file,
+                db_passphrase,
+                unlock_keystore()?,
+                &session_id,
+                |c| {
+                    if let Some(name) = &name {
+                        c.set_name(name)?;
+                    }
+                    if let Some(nickname) = &nickname {
+                        c.set_nickname(nickname)?;
+                    }
+                    c.set_approved(true);
+                    Ok(())
+                },
+            )
+            .await?
+        }
+
+        Commands::SetContactNickname {
+            session_id,
+            nickname,
+        } => {
+            update_contact(
+                &db_file,
+                db_passphrase,
+                unlock_keystore()?,
+                &session_id,
+                |c| c.set_nickname(&nickname),
+            )
+            .await?
+        }
+
+        Commands::ApproveContact { session_id } => {
+            update_contact(
+                &db_file,
+                db_passphrase,
+                unlock_keystore()?,
+                &session_id,
+                |c| {
+                    c.set_approved(true);
+                    Ok(())
+                },
+            )
+            .await?
+        }
+
+        Commands::BlockContact {
+            session_id,
+            unblock,
+        } => {
+            update_contact(
+                &db_file,
+                db_passphrase,
+                unlock_keystore()?,
+                &session_id,
+                |c| {
+                    c.set_blocked(!unblock);
+                    Ok(())
+                },
+            )
+            .await?
+        }
+
+        Commands::SetProfile {
+            name,
+            avatar,
+            remove_avatar,
+        } => {
+            let identity = unlock_keystore()?;
+            let repo = open_repo(&db_file, db_passphrase);
+            let config_state = ConfigState::new(&repo, identity.ed25519_sec_key())
+                .await
+                .expect("To create a new config state");
+            let avatar = avatar
+                .map(|path| {
+                    std::fs::read(&path).with_context(|| format!("Reading {}", path.display()))
+                })
+                .transpose()?;
+
+            let profile = service::profile::update(
+                &service::State::new(&repo, &config_state),
+                &Client::new(),
+                (),
+                protos::UpdateProfileRequest {
+                    name,
+                    avatar,
+                    remove_avatar: Some(remove_avatar),
+                },
+            )
+            .await
+            .context("Updating profile")?;
+
+            push_and_save::<UserProfileConfigNamespace, _>(
+                &repo,
+                &identity,
+                &config_state.user_profile_config,
+            )
+            .await;
+
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&profile).expect("To serialize profile")
+            );
+        }
+
+        Commands::CreateGroup { name, description } => {
+            let identity = unlock_keystore()?;
+            let repo = open_repo(&db_file, db_passphrase);
+            let config_state = ConfigState::new(&repo, identity.ed25519_sec_key())
+                .await
+                .expect("To create a new config state");
+            let my_name = config_state.user_profile_config.borrow().name().to_string();
+
+            let group = groups::create_group(
+                &repo,
+                &identity,
+                &config_state.user_groups_config,
+                &name,
+                description.as_deref(),
+                &my_name,
+            )
+            .await
+            .expect("To create group");
+            let group_id = group.group_id.clone();
+            let group = watch::channel(group).0;
+
+            let network = new_network();
+            let swarm_manager = SwarmManager::new(&network);
+            if let Err(e) = groups::push_group_configs(
+                &swarm_manager,
+                &SwarmState::new(group_id.clone().into()),
+                &group,
+                &(
+                    group.subscribe(),
+                    config_state.user_groups_config.subscribe(),
+                ),
+                &ClockSource::default(),
+            )
+            .await
+            {
+                eprintln!("Unable to push the group configs: {e:?}");
+            }
+
+            groups::save_group(&repo, &group)
+                .await
+                .expect("To save group configs");
+            push_and_save::<UserGroupsConfigNamespace, _>(
+                &repo,
+                &identity,
+                &config_state.user_groups_config,
+            )
+            .await;
+
+            println!("{group_id}");
+        }
+
+        Commands::InviteGroupMembers { group_id, members } => {
+            let identity = unlock_keystore()?;
+            let repo = open_repo(&db_file, db_passphrase);
+            let config_state = ConfigState::new(&repo, identity.ed25519_sec_key())
+                .await
+                .expect("To create a new config state");
+            let group_id: GroupID = group_id.parse().expect("To have a valid group ID");
+            let members: Vec<IndividualID> = members
+                .iter()
+                .map(|m| m.parse().expect("To have valid member session IDs"))
+                .collect();
+
+            let group = groups::load_group(
+                &repo,
+                &identity,
+                &config_state.user_groups_config,
+                &group_id,
+            )
+            .await
+            .expect("To load group");
+
+            let network = new_network();
+            let result = groups::invite_members(
+                &SwarmManager::new(&network),
+                &identity,
+                &config_state.user_groups_config,
+                &group,
+                &members,
+                &ClockSource::default(),
+            )
+            .await;
+
+            // Members are added even if some invites fail
+            groups::save_group(&repo, &group)
+                .await
+                .expect("To save group configs");
+
+            for (member, e) in result.expect("To invite members") {
+                eprintln!("Unable to invite {member}: {e:?}");
+            }
+        }
+
+        Commands::RemoveGroupMembers {
+            group_id,
+            members,
+            delete_content,
+        } => {
+            let identity = unlock_keystore()?;
+            let repo = open_repo(&db_file, db_passphrase);
+            let config_state = ConfigState::new(&repo, identity.ed25519_sec_key())
+                .await
+                .expect("To create a new config state");
+            let group_id: GroupID = group_id.parse().expect("To have a valid group ID");
+            let members: Vec<IndividualID> = members
+                .iter()
+                .map(|m| m.parse().expect("To have valid member session IDs"))
+                .collect();
 
-    let repo = db::Repository::new(SqliteConnectionManager::file(db_file))
-        .expect("To create a new repository");
+            let group = groups::load_group(
+                &repo,
+                &identity,
+                &config_state.user_groups_config,
+                &group_id,
+            )
+            .await
+            .expect("To load group");
 
-    let network = LegacyNetwork::new(
+            let network = new_network();
+            let result = groups::remove_members(
+                &SwarmManager::new(&network),
+                &repo,
+                &identity,
+                &config_state.user_groups_config,
+                &group,
+                &members,
+                delete_content,
+                &ClockSource::default(),
+            )
+            .await;
+
+            groups::save_group(&repo, &group)
+                .await
+                .expect("To save group configs");
+            result.expect("To remove members");
+        }
+
+        Commands::PromoteGroupMembers { group_id, members } => {
+            let identity = unlock_keystore()?;
+            let repo = open_repo(&db_file, db_passphrase);
+            let config_state = ConfigState::new(&repo, identity.ed25519_sec_key())
+                .await
+                .expect("To create a new config state");
+            let group_id: GroupID = group_id.parse().expect("To have a valid group ID");
+            let members: Vec<IndividualID> = members
+                .iter()
+                .map(|m| m.parse().expect("To have valid member session IDs"))
+                .collect();
+
+            let group = groups::load_group(
+                &repo,
+                &identity,
+                &config_state.user_groups_config,
+                &group_id,
+            )
+            .await
+            .expect("To load group");
+
+            let network = new_network();
+            let result = groups::promote_members(
+                &SwarmManager::new(&network),
+                &repo,
+                &identity,
+                &config_state.user_groups_config,
+                &group,
+                &members,
+                &ClockSource::default(),
+            )
+            .await;
+
+            groups::save_group(&repo, &group)
+                .await
+                .expect("To save group configs");
+
+            for (member, e) in result.expect("To promote members") {
+                eprintln!("Unable to promote {member}: {e:?}");
+            }
+        }
+
+        Commands::AcceptGroupInvite { group_id } => {
+            let identity = unlock_keystore()?;
+            let repo = open_repo(&db_file, db_passphrase);
+            let config_state = ConfigState::new(&repo, identity.ed25519_sec_key())
+                .await
+                .expect("To create a new config state");
+            let group_id: GroupID = group_id.parse().expect("To have a valid group ID");
+            let me = identity.session_id().into_owned();
+
+            let content = {
+                let (me, group_id) = (me.clone(), group_id.clone());
+                repo.read(move |conn| conn.get_group_invite(&me, &group_id))
+                    .await
+                    .expect("To look up the invite")
+                    .expect("To have been invited to the group")
+            };
+            let invite = GroupInvite::from_content(&content, &me)
+                .expect("To have an invite")
+                .expect("To have a valid invite");
+
+            config_state
+                .accept_group_invite(&invite)
+                .expect("To accept the invite");
+            push_and_save::<UserGroupsConfigNamespace, _>(
+                &repo,
+                &identity,
+                &config_state.user_groups_config,
+            )
+            .await;
+            push_and_save::<ConvoInfoVolatileConfigNamespace, _>(
+                &repo,
+                &identity,
+                &config_state.convo_info_volatile_config,
+            )
+            .await;
+
+            // Lets the admins know we joined, sent once the group swarm is reachable
+            let update = protos::GroupUpdateMessage {
+                invite_response: Some(protos::GroupUpdateInviteResponseMessage {
+                    is_approved: true,
+                }),
+                ..Default::default()
+            };
+            groups::queue_group_update(&repo, &me, &group_id, update, local_timestamp())
+                .await
+                .expect("To queue invite response");
+        }
+
+        Commands::DeclineGroupInvite { group_id } => {
+            let identity = unlock_keystore()?;
+            let repo = open_repo(&db_file, db_passphrase);
+            let config_state = ConfigState::new(&repo, identity.ed25519_sec_key())
+                .await
+                .expect("To create a new config state");
+            let group_id: GroupID = group_id.parse().expect("To have a valid group ID");
+
+            config_state.remove_group(&group_id);
+            push_and_save::<UserGroupsConfigNamespace, _>(
+                &repo,
+                &identity,
+                &config_state.user_groups_config,
+            )
+            .await;
+            push_and_save::<ConvoInfoVolatileConfigNamespace, _>(
+                &repo,
+                &identity,
+                &config_state.convo_info_volatile_config,
+            )
+            .await;
+        }
+
+        Commands::LeaveGroup { group_id } => {
+            let identity = unlock_keystore()?;
+            let repo = open_repo(&db_file, db_passphrase);
+            let config_state = ConfigState::new(&repo, identity.ed25519_sec_key())
+                .await
+                .expect("To create a new config state");
+            let group_id: GroupID = group_id.parse().expect("To have a valid group ID");
+
+            let group = groups::load_group(
+                &repo,
+                &identity,
+                &config_state.user_groups_config,
+                &group_id,
+            )
+            .await
+            .expect("To load group");
+
+            let network = new_network();
+            groups::leave_group(
+                &SwarmManager::new(&network),
+                &repo,
+                &identity,
+                &config_state,
+                &group,
+                &ClockSource::default(),
+            )
+            .await
+            .expect("To leave group");
+            push_and_save::<UserGroupsConfigNamespace, _>(
+                &repo,
+                &identity,
+                &config_state.user_groups_config,
+            )
+            .await;
+            push_and_save::<ConvoInfoVolatileConfigNamespace, _>(
+                &repo,
+                &identity,
+                &config_state.convo_info_volatile_config,
+            )
+            .await;
+        }
+
+        Commands::SendMessage { to, body } => {
+            let identity = unlock_keystore()?;
+            let receiver: SessionID = to.parse().expect("To have a valid session ID");
+            let now = local_timestamp();
+            let content = protos::Content {
+                data_message: Some(protos::DataMessage {
+                    body: Some(body),
+                    timestamp: Some(now.as_millis()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+
+            let message = DbMessage::new_outgoing(
+                identity.session_id().into_owned(),
+                receiver,
+                &content,
+                now,
+                Timestamp::from_mills(now.as_millis() + DEFAULT_MESSAGE_TTL.as_millis() as u64)
+                    .expect("To have a valid expiration"),
+            )
+            .expect("To create message");
+
+            open_repo(&db_file, db_passphrase)
+                .write(move |conn| conn.save_messages(std::iter::once(message)).map(|_| ()))
+                .await
+                .expect("To queue message");
+        }
+
+        Commands::RetrieveConfigMessages {
+            mnemonic,
+            print_events,
+            listen,
+        } => {
+            let identity = match mnemonic {
+                Some(mnemonic) => Identity::from_mnemonic(&mnemonic).context("Invalid mnemonic")?,
+                None => unlock_keystore()?,
+            };
+            run(&db_file, db_passphrase, identity, print_events, listen).await
+        }
+    }
+
+    Ok(())
+}
+
+async fn update_contact(
+    db_file: &Path,
+    db_passphrase: Option<&str>,
+    identity: Identity,
+    session_id: &str,
+    update: impl FnOnce(&mut Contact) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let session_id: IndividualID = session_id
+        .parse()
+        .with_context(|| format!("Invalid session ID {session_id}"))?;
+    let repo = open_repo(db_file, db_passphrase);
+    let config_state = ConfigState::new(&repo, identity.ed25519_sec_key())
+        .await
+        .expect("To create a new config state");
+
+    let contact = config_state
+        .update_contact(&session_id, update)
+        .context("Updating contact")?;
+
+    push_and_save::<ContactsNamespace, _>(&repo, &identity, &config_state.contacts_config).await;
+
+    println!("{contact:?}");
+    Ok(())
+}
+
+async fn queue_read_receipt(
+    repo: &db::Repository,
+    identity: &Identity,
+    contact: IndividualID,
+    after: u64,
+    up_to: Timestamp,
+) {
+    let me = identity.session_id().into_owned();
+    let timestamps = {
+        let (me, contact) = (me.clone(), contact.clone());
+        repo.read(move |conn| conn.get_received_timestamps(&me, &contact, after, up_to.as_millis()))
+            .await
+            .expect("To get received messages")
+    };
+
+    if timestamps.is_empty() {
+        return;
+    }
+
+    let now = local_timestamp();
+    let content = protos::Content {
+        receipt_message: Some(protos::ReceiptMessage {
+            r#type: protos::receipt_message::Type::Read as i32,
+            timestamp: timestamps,
+        }),
+        ..Default::default()
+    };
+
+    let message = DbMessage::new_outgoing(
+        me,
+        contact.into(),
+        &content,
+        now,
+        Timestamp::from_mills(now.as_millis() + DEFAULT_MESSAGE_TTL.as_millis() as u64)
+            .expect("To have a valid expiration"),
+    )
+    .expect("To create receipt");
+
+    repo.write(move |conn| conn.save_messages(std::iter::once(message)).map(|_| ()))
+        .await
+        .expect("To queue receipt");
+}
+
+/// Pushes a config changed from the command line, then saves it. A running client gets the change
+/// from the swarm like it would from another device, instead of having its dump overwritten.
+async fn push_and_save<NS, C>(repo: &db::Repository, identity: &Identity, config: &watch::Sender<C>)
+where
+    NS: MessageNamespace,
+    C: Config<PushData = CWrapper<bindings::config_push_data>>,
+{
+    let network = new_network();
+    let swarm_state = SwarmState::new(
+        identity
+            .session_id()
+            .into_owned()
+            .try_into()
+            .expect("To convert"),
+    );
+
+    if let Err(e) = push_config::<NS, _>(
+        &SwarmManager::new(&network),
+        swarm_state,
+        config,
+        identity,
+        &ClockSource::default(),
+    )
+    .await
+    {
+        eprintln!(
+            "Unable to push {}, it's pushed the next time the client runs: {e:?}",
+            C::CONFIG_TYPE_NAME
+        );
+    }
+
+    ConfigState::save_now(config, repo)
+        .await
+        .expect("To save config");
+}
+
+fn open_repo(db_file: &Path, db_passphrase: Option<&str>) -> db::Repository {
+    db::Repository::new(SqliteConnectionManager::file(db_file), db_passphrase)
+        .expect("To open the database")
+}
+
+fn new_network() -> LegacyNetwork {
+    LegacyNetwork::new(
         Client::builder()
             .danger_accept_invalid_certs(true)
             .build()
@@ -117,14 +1170,23 @@ async fn main() {
             "https://seed1.getsession.org/".parse().unwrap(),
             "https://seed2.getsession.org/".parse().unwrap()
         ],
-    );
+    )
+}
 
-    let identity = Identity::from_mnemonic(&std::env::var("MNEMONIC").expect("To have a mnemonic"))
-        .expect("To create an identity");
+async fn run(
+    db_file: &Path,
+    db_passphrase: Option<&str>,
+    identity: Identity,
+    print_events: bool,
+    listen: Option<SocketAddr>,
+) {
+    let repo = open_repo(db_file, db_passphrase);
 
-    repo.obtain_connection()
-        .unwrap()
-        .save_setting(None, &identity)
+    let network = new_network();
+
+    let public_identity = PublicIdentity::from(&identity);
+    repo.write(move |conn| conn.save_setting(None, &public_identity))
+        .await
         .expect("To save identity");
 
     let swarm_state = SwarmState::new(
@@ -134,11 +1196,29 @@ async fn main() {
             .try_into()
             .expect("To convert"),
     );
-    let config_state =
-        ConfigState::new(&repo, identity.ed25519_sec_key()).expect("To create a new config state");
+    let config_state = ConfigState::new(&repo, identity.ed25519_sec_key())
+        .await
+        .expect("To create a new config state");
 
     let (manual_poll_trigger_tx, manual_poll_trigger_rx) = broadcast::channel(1);
     let clock_source = ClockSource::default();
+    let events = EventBus::default();
+
+    let print_events = async {
+        if print_events {
+            events.print_json_lines().await
+        } else {
+            Ok(())
+        }
+    };
+    let serve_events = async {
+        match listen {
+            Some(addr) => service::http::serve(addr, events.clone()).await,
+            None => Ok(()),
+        }
+    };
+    let network_state_events = publish_network_state(&network, &events);
+    let prune = prune_messages(&repo, &clock_source, &events, PRUNE_INTERVAL);
 
     let gen_blinded_ids = worker::gen_blinded_ids::gen_blinded_ids(
         &identity,
@@ -172,11 +1252,12 @@ async fn main() {
         CONFIG_POLL_INTERVAL,
         manual_poll_trigger_rx,
         &clock_source,
+        &events,
     );
 
     let print_logs = config_state.log_configs();
 
-    let sync_user_profile = sync_config::<UserProfileConfigNamespace, _>(
+    let sync_user_profile = sync_config::<UserProfileConfigNamespace, _, _>(
         &batch_manager,
         swarm_state.clone(),
         None,
@@ -186,9 +1267,10 @@ async fn main() {
         &repo,
         &identity,
         &clock_source,
+        &events,
     );
 
-    let sync_user_groups = sync_config::<UserGroupsConfigNamespace, _>(
+    let sync_user_groups = sync_config::<UserGroupsConfigNamespace, _, _>(
         &batch_manager,
         swarm_state.clone(),
         None,
@@ -198,9 +1280,10 @@ async fn main() {
         &repo,
         &identity,
         &clock_source,
+        &events,
     );
 
-    let sync_convo_info_config = sync_config::<ConvoInfoVolatileConfigNamespace, _>(
+    let sync_convo_info_config = sync_config::<ConvoInfoVolatileConfigNamespace, _, _>(
         &batch_manager,
         swarm_state.clone(),
         None,
@@ -210,9 +1293,10 @@ async fn main() {
         &repo,
         &identity,
         &clock_source,
+        &events,
     );
 
-    let sync_contacts = sync_config::<ContactsNamespace, _>(
+    let sync_contacts = sync_config::<ContactsNamespace, _, _>(
         &batch_manager,
         swarm_state.clone(),
         None,
@@ -222,6 +1306,7 @@ async fn main() {
         &repo,
         &identity,
         &clock_source,
+        &events,
     );
 
     let sync_groups = sync_groups(
@@ -231,8 +1316,30 @@ async fn main() {
         config_state.user_groups_config.subscribe(),
         manual_poll_trigger_tx.subscribe(),
         &clock_source,
+        &events,
+    );
+
+    let send = send_messages(
+        &repo,
+        &batch_manager,
+        swarm_state.clone(),
+        &identity,
+        &config_state,
+        &clock_source,
+        &events,
     );
 
+    let expire_read = expire_read_messages(
+        &repo,
+        &batch_manager,
+        swarm_state.clone(),
+        &identity,
+        &clock_source,
+    );
+
+    let me = identity.session_id().into_owned();
+    let track_group_updates = track_group_updates(&repo, &me, &events, &config_state);
+
     try_join!(
         print_logs,
         run_batch,
@@ -243,6 +1350,13 @@ async fn main() {
         sync_contacts,
         sync_groups,
         gen_blinded_ids,
+        print_events,
+        serve_events,
+        network_state_events,
+        prune,
+        send,
+        expire_read,
+        track_group_updates,
     )
     .unwrap();
 }
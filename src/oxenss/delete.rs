@@ -0,0 +1,53 @@
+use super::{JsonRpcCall, StandardJsonRpcResponse};
+use crate::ed25519::ED25519PubKey;
+use crate::network::swarm::SwarmAuth;
+use crate::session_id::SessionID;
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+
+#[derive(Debug, Serialize)]
+pub struct DeleteMessagesRequest<'a> {
+    #[serde(rename = "pubkey")]
+    session_id: String,
+    pubkey_ed25519: Option<Cow<'a, ED25519PubKey>>,
+    messages: &'a [String],
+    #[serde(flatten)]
+    signature: Value,
+}
+
+impl<'a> DeleteMessagesRequest<'a> {
+    pub fn new(auth: &'a impl SwarmAuth, hashes: &'a [String]) -> anyhow::Result<Self> {
+        let sig_payload = format!("delete{}", hashes.concat());
+        let signature = serde_json::to_value(
+            auth.sign(sig_payload.as_bytes())
+                .context("Signing is required")?,
+        )?;
+
+        let session_id: SessionID = auth.session_id().into_owned().into();
+
+        Ok(Self {
+            session_id: session_id.to_string(),
+            pubkey_ed25519: match session_id {
+                SessionID::Individual(_) => Some(auth.ed25519_pub_key()),
+                _ => None,
+            },
+            messages: hashes,
+            signature,
+        })
+    }
+}
+
+impl<'a> JsonRpcCall for DeleteMessagesRequest<'a> {
+    type Response = ();
+
+    fn method_name(&self) -> &'static str {
+        "delete"
+    }
+
+    fn create_response(&self, response: Value) -> super::Result<Self::Response> {
+        StandardJsonRpcResponse::<Value>::body_from_value(response)?;
+        Ok(())
+    }
+}
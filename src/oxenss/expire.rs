@@ -0,0 +1,63 @@
+use super::{JsonRpcCall, StandardJsonRpcResponse};
+use crate::clock::Timestamp;
+use crate::ed25519::ED25519PubKey;
+use crate::network::swarm::SwarmAuth;
+use crate::session_id::SessionID;
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+
+/// Brings the expiry of messages forward. Messages already expiring earlier are left alone.
+#[derive(Debug, Serialize)]
+pub struct ExpireMessagesRequest<'a> {
+    #[serde(rename = "pubkey")]
+    session_id: String,
+    pubkey_ed25519: Option<Cow<'a, ED25519PubKey>>,
+    messages: &'a [String],
+    expiry: Timestamp,
+    shorten: bool,
+    #[serde(flatten)]
+    signature: Value,
+}
+
+impl<'a> ExpireMessagesRequest<'a> {
+    pub fn new_shorten(
+        auth: &'a impl SwarmAuth,
+        hashes: &'a [String],
+        expiry: Timestamp,
+    ) -> anyhow::Result<Self> {
+        let sig_payload = format!("expireshorten{expiry}{}", hashes.concat());
+        let signature = serde_json::to_value(
+            auth.sign(sig_payload.as_bytes())
+                .context("Signing is required")?,
+        )?;
+
+        let session_id: SessionID = auth.session_id().into_owned().into();
+
+        Ok(Self {
+            session_id: session_id.to_string(),
+            pubkey_ed25519: match session_id {
+                SessionID::Individual(_) => Some(auth.ed25519_pub_key()),
+                _ => None,
+            },
+            messages: hashes,
+            expiry,
+            shorten: true,
+            signature,
+        })
+    }
+}
+
+impl<'a> JsonRpcCall for ExpireMessagesRequest<'a> {
+    type Response = ();
+
+    fn method_name(&self) -> &'static str {
+        "expire"
+    }
+
+    fn create_response(&self, response: Value) -> super::Result<Self::Response> {
+        StandardJsonRpcResponse::<Value>::body_from_value(response)?;
+        Ok(())
+    }
+}
@@ -1,17 +1,26 @@
+use crate::clock::Timestamp;
 use crate::message_crypto::strip_message_padding;
 use crate::network::swarm::SwarmAuth;
 use crate::oxenss::namespace::{DefaultNamespace, GroupNamespace};
 use crate::protos::{Content, Envelope, WebSocketMessage, WebSocketRequestMessage};
 use crate::session_id::IndividualOrBlindedID;
 use anyhow::{bail, Context};
+use derive_more::Display;
 use prost::Message;
 
 #[derive(Clone, Debug)]
 pub struct RegularMessage {
     pub sender: IndividualOrBlindedID,
     pub content: Content,
+    /// When the sender says they sent it, which may be earlier than when it was stored.
+    pub sent_at: Option<Timestamp>,
 }
 
+/// Context of the errors of messages we have no key for, which may become readable later.
+#[derive(Debug, Display)]
+#[display("Decrypting message")]
+pub struct Undecryptable;
+
 pub trait RegularMessageDecoder: Sized {
     fn decode_and_decrypt(
         input: &[u8],
@@ -24,10 +33,11 @@ impl RegularMessageDecoder for GroupNamespace {
         input: &[u8],
         swarm_auth: &impl SwarmAuth,
     ) -> anyhow::Result<RegularMessage> {
-        let (session_id, content) = swarm_auth.decrypt(input)?;
+        let (session_id, content) = swarm_auth.decrypt(input).context(Undecryptable)?;
 
         let Envelope {
             content: Some(content),
+            timestamp,
             ..
         } = Envelope::decode(content.as_ref()).context("Decode envelope")?
         else {
@@ -38,6 +48,7 @@ impl RegularMessageDecoder for GroupNamespace {
         Ok(RegularMessage {
             sender: session_id.into(),
             content,
+            sent_at: Timestamp::from_mills(timestamp),
         })
     }
 }
@@ -60,19 +71,49 @@ impl RegularMessageDecoder for DefaultNamespace {
 
         let Envelope {
             content: Some(content),
+            timestamp,
             ..
         } = Envelope::decode(body.as_slice()).context("Decode envelope")?
         else {
             bail!("No content in envelope");
         };
 
-        let (session_id, content) = swarm_auth.decrypt(&content)?;
+        let (session_id, content) = swarm_auth.decrypt(&content).context(Undecryptable)?;
         let content =
             Content::decode(strip_message_padding(content.as_ref())).context("Decode content")?;
 
         Ok(RegularMessage {
             sender: session_id.into(),
             content,
+            sent_at: Timestamp::from_mills(timestamp),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Identity;
+
+    #[test]
+    fn only_decryption_errors_are_undecryptable() {
+        let identity = Identity::gen();
+        let envelope = Envelope {
+            content: Some(vec![1, 2, 3]),
+            ..Default::default()
+        };
+        let message = WebSocketMessage {
+            request: Some(WebSocketRequestMessage {
+                body: Some(envelope.encode_to_vec()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let e =
+            DefaultNamespace::decode_and_decrypt(&message.encode_to_vec(), &identity).unwrap_err();
+        assert!(e.downcast_ref::<Undecryptable>().is_some());
+
+        let e = DefaultNamespace::decode_and_decrypt(&[0xff, 0xff], &identity).unwrap_err();
+        assert!(e.downcast_ref::<Undecryptable>().is_none());
+    }
+}
@@ -1,13 +1,16 @@
 pub mod batch;
+pub mod delete;
 mod error;
+pub mod expire;
 pub mod http;
 pub mod message;
 pub mod namespace;
 pub mod retrieve;
 pub mod retrieve_service_node;
 pub mod retrieve_swarm_nodes;
+pub mod revoke;
 mod rpc;
-mod store;
+pub mod store;
 
 pub use error::Error;
 
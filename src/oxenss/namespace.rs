@@ -1,4 +1,4 @@
-pub trait MessageNamespace {
+pub trait MessageNamespace: 'static {
     const INT_VALUE: isize;
 
     const DISPLAY_NAME: &'static str;
@@ -0,0 +1,58 @@
+use super::{JsonRpcCall, StandardJsonRpcResponse};
+use crate::clock::Timestamp;
+use crate::config::SubaccountToken;
+use crate::hex_encode::Hex;
+use crate::network::swarm::SwarmAuth;
+use crate::session_id::SessionID;
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Stops the swarm from accepting the sub-accounts, e.g. those of removed group members.
+/// Only the group's admin key can sign it.
+#[derive(Debug, Serialize)]
+pub struct RevokeSubaccountRequest {
+    #[serde(rename = "pubkey")]
+    session_id: String,
+    revoke: Vec<Hex<36>>,
+    timestamp: Timestamp,
+    #[serde(flatten)]
+    signature: Value,
+}
+
+impl RevokeSubaccountRequest {
+    pub fn new(
+        auth: &impl SwarmAuth,
+        tokens: &[SubaccountToken],
+        timestamp: Timestamp,
+    ) -> anyhow::Result<Self> {
+        let mut sig_payload = format!("revoke_subaccount{timestamp}").into_bytes();
+        for token in tokens {
+            sig_payload.extend_from_slice(token);
+        }
+
+        let signature =
+            serde_json::to_value(auth.sign(&sig_payload).context("Signing is required")?)?;
+        let session_id: SessionID = auth.session_id().into_owned().into();
+
+        Ok(Self {
+            session_id: session_id.to_string(),
+            revoke: tokens.iter().copied().map(Hex).collect(),
+            timestamp,
+            signature,
+        })
+    }
+}
+
+impl JsonRpcCall for RevokeSubaccountRequest {
+    type Response = ();
+
+    fn method_name(&self) -> &'static str {
+        "revoke_subaccount"
+    }
+
+    fn create_response(&self, response: Value) -> super::Result<Self::Response> {
+        StandardJsonRpcResponse::<Value>::body_from_value(response)?;
+        Ok(())
+    }
+}
@@ -1,12 +1,21 @@
-use super::{namespace::MessageNamespace, JsonRpcCall};
-use crate::curve25519::{Curve25519PubKey, Curve25519SecKey};
+use super::namespace::{DefaultNamespace, MessageNamespace};
+use super::{JsonRpcCall, StandardJsonRpcResponse};
+use crate::clock::Timestamp;
+use crate::crypto::encrypt_for_recipient;
+use crate::ed25519::{ED25519PubKey, ED25519SecKey};
+use crate::message_crypto::pad_message;
 use crate::network::swarm::SwarmAuth;
-use crate::protos::Content;
-use anyhow::bail;
+use crate::protos::{
+    envelope, web_socket_message, Content, Envelope, WebSocketMessage, WebSocketRequestMessage,
+};
+use crate::session_id::{IndividualID, SessionID};
+use anyhow::{bail, Context};
 use base64::prelude::*;
 use prost::Message;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::borrow::Cow;
+use std::time::Duration;
 
 #[derive(Debug, Serialize)]
 pub struct StoreMessageRequest<'a> {
@@ -16,55 +25,96 @@ pub struct StoreMessageRequest<'a> {
     data_b64: String,
     ttl: usize,
     timestamp: u64,
+    namespace: isize,
 
     // Auth enabled parameters
     #[serde(skip_serializing_if = "Option::is_none")]
     sig_timestamp: Option<u64>,
 
-    #[serde(skip_serializing_if = "Option::is_none", rename = "pubkey_ed25519")]
-    pubkey_ed25519: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pubkey_ed25519: Option<Cow<'a, ED25519PubKey>>,
 
-    #[serde(skip_serializing_if = "Option::is_none", rename = "signature")]
-    signature_b64: Option<String>,
+    #[serde(flatten)]
+    signature: Option<Value>,
 }
 
 impl<'a> StoreMessageRequest<'a> {
-    // pub fn new_authenticated<N: MessageNamespace>(
-    //     auth: &'a impl SwarmAuth,
-    //     recipient_pub_key: &'a Curve25519PubKey,
-    //     data: &N::MessageType,
-    //     ttl: usize,
-    //     timestamp: u64,
-    // ) -> anyhow::Result<Self> {
-    //     if N::INT_VALUE == 0 {
-    //         bail!("Namespace 0 is reserved for unauthenticated messages");
-    //     }
-    //
-    //     let data = data.to_remote(auth)?;
-    //     let verify_payload = format!("store{}{timestamp}", N::INT_VALUE);
-    //     let signature = BASE64_STANDARD.encode(auth.sign(verify_payload.as_bytes()));
-    //
-    //     Ok(Self {
-    //         recipient_pub_key: Cow::Borrowed(recipient_pub_key.hex()),
-    //         data_b64: BASE64_STANDARD.encode(&data),
-    //         ttl,
-    //         timestamp,
-    //         sig_timestamp: Some(timestamp),
-    //         pubkey_ed25519: Some(auth.ed25519_pub_key().hex()),
-    //         signature_b64: Some(signature),
-    //     })
-    // }
+    /// Stores `data` as is on the swarm `auth` controls, in namespace `NS`.
+    pub fn new_authenticated<NS: MessageNamespace>(
+        auth: &'a impl SwarmAuth,
+        data: &[u8],
+        ttl: Duration,
+        timestamp: Timestamp,
+    ) -> anyhow::Result<Self> {
+        let namespace = NS::INT_VALUE;
+        if namespace == 0 {
+            bail!("Namespace 0 is reserved for unauthenticated messages");
+        }
+
+        let signature = serde_json::to_value(
+            auth.sign(format!("store{namespace}{timestamp}").as_bytes())
+                .context("Signing is required")?,
+        )?;
+
+        let session_id: SessionID = auth.session_id().into_owned().into();
 
+        Ok(Self {
+            recipient_pub_key: Cow::Owned(session_id.to_string()),
+            data_b64: BASE64_STANDARD.encode(data),
+            ttl: ttl.as_millis().try_into().context("TTL too long")?,
+            timestamp: timestamp.as_millis(),
+            namespace,
+            sig_timestamp: Some(timestamp.as_millis()),
+            pubkey_ed25519: match session_id {
+                SessionID::Individual(_) => Some(auth.ed25519_pub_key()),
+                _ => None,
+            },
+            signature: Some(signature),
+        })
+    }
+
+    /// Encrypts `content` for `recipient` and wraps it the way clients expect to find it in
+    /// the default namespace. The envelope carries when the message was `sent_at`, while
+    /// `timestamp` is when it's stored, which the TTL counts from.
     pub fn new_unauthenticated(
-        sender_key: &Curve25519SecKey,
-        recipient_key: &Curve25519PubKey,
+        sender_key: &ED25519SecKey,
+        recipient: &'a IndividualID,
         content: &Content,
-        ttl: usize,
-        timestamp: u64,
+        ttl: Duration,
+        sent_at: Timestamp,
+        timestamp: Timestamp,
     ) -> anyhow::Result<Self> {
-        let content = content.encode_to_vec();
+        let content = pad_message(Cow::Owned(content.encode_to_vec()));
+        let encrypted = encrypt_for_recipient(recipient.pub_key(), sender_key, content);
+
+        let envelope = Envelope {
+            r#type: envelope::Type::SessionMessage as i32,
+            timestamp: sent_at.as_millis(),
+            content: Some(encrypted),
+            ..Default::default()
+        };
+
+        let message = WebSocketMessage {
+            r#type: Some(web_socket_message::Type::Request as i32),
+            request: Some(WebSocketRequestMessage {
+                verb: Some("PUT".to_string()),
+                path: Some("/api/v1/message".to_string()),
+                body: Some(envelope.encode_to_vec()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
 
-        todo!()
+        Ok(Self {
+            recipient_pub_key: Cow::Borrowed(recipient.as_str()),
+            data_b64: BASE64_STANDARD.encode(message.encode_to_vec()),
+            ttl: ttl.as_millis().try_into().context("TTL too long")?,
+            timestamp: timestamp.as_millis(),
+            namespace: DefaultNamespace::INT_VALUE,
+            sig_timestamp: None,
+            pubkey_ed25519: None,
+            signature: None,
+        })
     }
 }
 
@@ -80,7 +130,41 @@ impl<'a> JsonRpcCall for StoreMessageRequest<'a> {
         "store"
     }
 
+    fn namespace(&self) -> Option<isize> {
+        Some(self.namespace)
+    }
+
     fn create_response(&self, response: serde_json::Value) -> super::Result<Self::Response> {
-        Ok(serde_json::from_value(response)?)
+        StandardJsonRpcResponse::body_from_value(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Identity;
+    use crate::oxenss::message::RegularMessageDecoder;
+
+    #[test]
+    fn envelopes_keep_the_sent_time() {
+        let (sender, recipient) = (Identity::gen(), Identity::gen());
+        let recipient_id = recipient.session_id();
+        let sent_at = Timestamp::from_mills(1000).unwrap();
+        let now = Timestamp::from_mills(5000).unwrap();
+
+        let request = StoreMessageRequest::new_unauthenticated(
+            sender.ed25519_sec_key(),
+            &recipient_id,
+            &Content::default(),
+            Duration::from_secs(60),
+            sent_at,
+            now,
+        )
+        .unwrap();
+        assert_eq!(request.timestamp, now.as_millis());
+
+        let data = BASE64_STANDARD.decode(&request.data_b64).unwrap();
+        let message = DefaultNamespace::decode_and_decrypt(&data, &recipient).unwrap();
+        assert_eq!(message.sent_at, Some(sent_at));
     }
 }
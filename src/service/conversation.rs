@@ -25,8 +25,8 @@ pub async fn list<'a>(
         move || async move {
             let conversations = state
                 .repo
-                .obtain_connection()?
-                .get_conversations(approved)?;
+                .read(move |c| c.get_conversations(approved))
+                .await?;
 
             let response = ListConversationsResponse {
                 conversations: conversations.into_iter().map(Into::into).collect(),
@@ -0,0 +1,18 @@
+use crate::events::{Event, EventBus};
+use async_stream::stream;
+use futures_core::Stream;
+use tokio::sync::broadcast::error::RecvError;
+
+pub fn subscribe(events: &EventBus) -> impl Stream<Item = Event> + Send + 'static {
+    let mut rx = events.subscribe();
+
+    stream! {
+        loop {
+            match rx.recv().await {
+                Ok(event) => yield event,
+                Err(RecvError::Lagged(n)) => log::warn!("Subscriber missed {n} events"),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use super::events;
+use crate::events::EventBus;
+use anyhow::Context;
+use axum::extract::State;
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::routing::get;
+use axum::Router;
+use futures_core::Stream;
+use futures_util::StreamExt;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+pub fn router(events: EventBus) -> Router {
+    Router::new()
+        .route("/events", get(stream_events))
+        .with_state(events)
+}
+
+pub async fn serve(addr: SocketAddr, events: EventBus) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Listening on {addr}"))?;
+    log::info!("Serving events on http://{addr}/events");
+
+    axum::serve(listener, router(events))
+        .await
+        .context("Serving HTTP")
+}
+
+/// Every event as it's published, in JSON.
+async fn stream_events(
+    State(events): State<EventBus>,
+) -> Sse<impl Stream<Item = Result<SseEvent, axum::Error>>> {
+    Sse::new(events::subscribe(&events).map(|event| SseEvent::default().json_data(event)))
+        .keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+    use crate::test_utils::GROUP;
+
+    #[tokio::test]
+    async fn published_events_reach_subscribers() {
+        let events = EventBus::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(events.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // The response only starts once the handler has subscribed
+        let mut response = reqwest::get(format!("http://{addr}/events"))
+            .await
+            .expect("To subscribe");
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+        events.publish(Event::GroupJoined {
+            group_id: GROUP.parse().unwrap(),
+        });
+        let chunk = response
+            .chunk()
+            .await
+            .expect("To receive the event")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&chunk),
+            format!("data: {{\"type\":\"group_joined\",\"group_id\":\"{GROUP}\"}}\n\n")
+        );
+    }
+}
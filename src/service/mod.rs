@@ -1,7 +1,18 @@
+use crate::config_state::ConfigState;
 use crate::db::Repository;
 
 mod conversation;
+mod events;
+pub mod http;
+pub mod profile;
 
 pub struct State<'a> {
     pub(self) repo: &'a Repository,
+    pub(self) config_state: &'a ConfigState,
+}
+
+impl<'a> State<'a> {
+    pub fn new(repo: &'a Repository, config_state: &'a ConfigState) -> Self {
+        Self { repo, config_state }
+    }
 }
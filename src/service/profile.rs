@@ -0,0 +1,83 @@
+use super::State;
+use crate::bindings::user_profile_pic;
+use crate::crypto::encrypt_profile_pic;
+use crate::file_server_api::{file_server_url, UploadFile};
+use crate::http_api::HttpCallSource;
+use crate::protos::{ProfileImagePicture, UpdateProfileRequest, UpdateProfileResponse};
+use anyhow::{anyhow, Context};
+use rand::RngCore;
+
+/// Updates our own profile. The change is pushed to the swarm by the config sync.
+pub async fn update<CS: HttpCallSource>(
+    state: &State<'_>,
+    call_source: &CS,
+    call_source_arg: CS::Arg<'_>,
+    req: UpdateProfileRequest,
+) -> anyhow::Result<UpdateProfileResponse> {
+    let UpdateProfileRequest {
+        name,
+        avatar,
+        remove_avatar,
+    } = req;
+
+    let pic = match avatar {
+        Some(avatar) => Some(upload_avatar(call_source, call_source_arg, &avatar).await?),
+        None if remove_avatar.unwrap_or(false) => Some(user_profile_pic::empty()),
+        None => None,
+    };
+
+    let mut result = Ok(());
+    state
+        .config_state
+        .user_profile_config
+        .send_if_modified(|c| {
+            result = (|| {
+                if let Some(name) = &name {
+                    c.set_name(name)?;
+                }
+                if let Some(pic) = pic {
+                    c.set_profile_pic(pic)?;
+                }
+                anyhow::Ok(())
+            })();
+            name.is_some() || pic.is_some()
+        });
+    result?;
+
+    let config = state.config_state.user_profile_config.borrow();
+    Ok(UpdateProfileResponse {
+        name: config.name().to_string(),
+        avatar: config
+            .profile_pic()
+            .filter(|p| !p.is_empty())
+            .and_then(|p| {
+                Some(ProfileImagePicture {
+                    url: p.url()?.to_string(),
+                    key: hex::encode(p.key),
+                })
+            }),
+    })
+}
+
+async fn upload_avatar<CS: HttpCallSource>(
+    call_source: &CS,
+    call_source_arg: CS::Arg<'_>,
+    avatar: &[u8],
+) -> anyhow::Result<user_profile_pic> {
+    let mut key = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut key);
+
+    let data = encrypt_profile_pic(&key, avatar)?;
+    let base_url = file_server_url();
+    let uploaded = call_source
+        .invoke(
+            &base_url,
+            call_source_arg,
+            &UploadFile { data: data.into() },
+        )
+        .await
+        .map_err(|e| anyhow!("Uploading avatar: {e}"))?
+        .context("Uploading avatar")?;
+
+    user_profile_pic::new(&uploaded.download_url(&base_url), key)
+}
@@ -0,0 +1,49 @@
+//! Fixtures shared by the unit tests.
+
+use crate::db::migrations::create_migrations;
+use rusqlite::Connection;
+use scopeguard::ScopeGuard;
+use std::path::{Path, PathBuf};
+
+pub const ME: &str = "05aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+pub const BOB: &str = "05bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+pub const EVE: &str = "05eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee";
+pub const GROUP: &str = "03dddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddd";
+
+/// An in-memory database with every migration applied.
+pub fn open_db() -> Connection {
+    migrated(Connection::open_in_memory().expect("To open db"))
+}
+
+/// A database file at `path` with every migration applied.
+pub fn open_db_at(path: &Path) -> Connection {
+    migrated(Connection::open(path).expect("To open db"))
+}
+
+fn migrated(mut conn: Connection) -> Connection {
+    create_migrations()
+        .to_latest(&mut conn)
+        .expect("To run migrations");
+    conn
+}
+
+/// Saves `ME` as our identity.
+pub fn save_my_identity(conn: &Connection) {
+    conn.execute(
+        "INSERT OR REPLACE INTO app_settings (name, id, value)
+         VALUES ('identity', '', json_object('session_id', ?, 'ed25519_pub_key', ''))",
+        [ME],
+    )
+    .expect("To save identity");
+}
+
+/// A fresh path in the temp dir. Whatever ends up there is removed on drop.
+pub fn temp_path(ext: &str) -> ScopeGuard<PathBuf, fn(PathBuf)> {
+    scopeguard::guard(
+        std::env::temp_dir().join(format!("{}.{ext}", uuid::Uuid::new_v4())),
+        |path| {
+            let _ = std::fs::remove_file(&path);
+            let _ = std::fs::remove_dir_all(&path);
+        },
+    )
+}
@@ -0,0 +1,168 @@
+use super::{Attachment, Transcript, TranscriptEntry};
+use std::fmt::Write;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; max-width: 48em; margin: 2em auto; color: #222; }
+h2 { font-size: 1em; text-align: center; color: #888; margin: 2em 0 1em; }
+.message { margin: 0.5em 0; padding: 0.5em 0.75em; border-radius: 0.5em; background: #f1f1f1; max-width: 80%; }
+.message.from-me { margin-left: auto; background: #d8f5d0; }
+.sender { font-weight: bold; }
+.time { color: #888; font-size: 0.85em; margin-left: 0.5em; }
+.body { white-space: pre-wrap; margin-top: 0.25em; }
+blockquote { margin: 0.25em 0; padding-left: 0.5em; border-left: 3px solid #aaa; color: #555; }
+.attachment img { max-width: 100%; margin-top: 0.25em; }
+.reactions { font-size: 0.85em; color: #555; margin-top: 0.25em; }
+"#;
+
+pub fn render(transcript: &Transcript) -> String {
+    let conversation = &transcript.conversation;
+    let title = if conversation.name.is_empty() {
+        &conversation.id
+    } else {
+        &conversation.name
+    };
+
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n",
+        escape(title)
+    );
+    let _ = writeln!(out, "<h1>{}</h1>", escape(title));
+    let _ = writeln!(
+        out,
+        "<p><code>{}</code> ({}), times in {}</p>",
+        escape(&conversation.id),
+        escape(&conversation.conversation_type),
+        escape(&transcript.timezone)
+    );
+
+    let mut last_day = None;
+    for entry in &transcript.messages {
+        let day = entry.time.date_naive();
+        if last_day != Some(day) {
+            let _ = writeln!(out, "<h2>{}</h2>", day.format("%Y-%m-%d"));
+            last_day = Some(day);
+        }
+
+        render_entry(&mut out, entry);
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn render_entry(out: &mut String, entry: &TranscriptEntry) {
+    let _ = writeln!(
+        out,
+        "<div class=\"message{}\" id=\"m{}\">",
+        if entry.from_me { " from-me" } else { "" },
+        entry.id
+    );
+    let _ = writeln!(
+        out,
+        "<div><span class=\"sender\" title=\"{}\">{}</span><time class=\"time\" datetime=\"{}\">{}</time></div>",
+        escape(&entry.sender),
+        escape(&entry.sender_name),
+        entry.time.to_rfc3339(),
+        entry.time.format("%H:%M:%S")
+    );
+
+    if let Some(quote) = &entry.quote {
+        let _ = writeln!(
+            out,
+            "<blockquote><div class=\"sender\">{}</div>{}</blockquote>",
+            escape(&quote.author_name),
+            escape(quote.text.as_deref().unwrap_or_default())
+        );
+    }
+
+    if let Some(body) = entry.body.as_deref().filter(|b| !b.is_empty()) {
+        let _ = writeln!(out, "<div class=\"body\">{}</div>", escape(body));
+    }
+
+    for attachment in &entry.attachments {
+        let _ = writeln!(
+            out,
+            "<div class=\"attachment\">{}</div>",
+            attachment_html(attachment)
+        );
+    }
+
+    if !entry.reactions.is_empty() {
+        let reactions: Vec<_> = entry
+            .reactions
+            .iter()
+            .map(|r| {
+                format!(
+                    "<span title=\"{}\">{} {}</span>",
+                    escape(&r.sender),
+                    escape(&r.emoji),
+                    escape(&r.sender_name)
+                )
+            })
+            .collect();
+        let _ = writeln!(
+            out,
+            "<div class=\"reactions\">{}</div>",
+            reactions.join(", ")
+        );
+    }
+
+    out.push_str("</div>\n");
+}
+
+fn attachment_html(attachment: &Attachment) -> String {
+    let target = attachment.link_target();
+
+    let label = format!("{} ({} bytes)", attachment.content_type, attachment.size);
+    if attachment.is_image() {
+        format!(
+            "<img src=\"{}\" alt=\"{}\">",
+            escape(&target),
+            escape(&label)
+        )
+    } else {
+        format!(
+            "<a href=\"{}\" download=\"{}\">{}</a>",
+            escape(&target),
+            escape(&attachment.id),
+            escape(&label)
+        )
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript::tests::sample_transcript;
+
+    #[test]
+    fn markup_is_escaped() {
+        let out = render(&sample_transcript());
+
+        assert!(out.contains("<title>&lt;b&gt;*Room*&lt;/b&gt;</title>"));
+        assert!(out.contains(">_Bob_ &lt;script&gt;</span>"));
+        assert!(out.contains("<div class=\"body\">**bold** &lt;i&gt;\n# not a title</div>"));
+        assert!(out.contains(
+            "<a href=\"http://example.org/&lt;a&gt;\" download=\"a1\">text/plain](evil) (3 bytes)</a>"
+        ));
+        assert!(!out.contains("<script>"));
+        assert!(!out.contains("<i>"));
+    }
+}
@@ -0,0 +1,5 @@
+use super::Transcript;
+
+pub fn render(transcript: &Transcript) -> anyhow::Result<String> {
+    Ok(serde_json::to_string_pretty(transcript)?)
+}
@@ -0,0 +1,145 @@
+use super::{Attachment, Transcript, TranscriptEntry};
+use std::fmt::Write;
+
+pub fn render(transcript: &Transcript) -> String {
+    let mut out = String::new();
+    let conversation = &transcript.conversation;
+    let title = if conversation.name.is_empty() {
+        &conversation.id
+    } else {
+        &conversation.name
+    };
+
+    let _ = writeln!(out, "# {}\n", escape(title));
+    let _ = writeln!(
+        out,
+        "{} ({}), times in {}\n",
+        code_span(&conversation.id),
+        escape(&conversation.conversation_type),
+        escape(&transcript.timezone)
+    );
+
+    let mut last_day = None;
+    for entry in &transcript.messages {
+        let day = entry.time.date_naive();
+        if last_day != Some(day) {
+            let _ = writeln!(out, "## {}\n", day.format("%Y-%m-%d"));
+            last_day = Some(day);
+        }
+
+        render_entry(&mut out, entry);
+    }
+
+    out
+}
+
+fn render_entry(out: &mut String, entry: &TranscriptEntry) {
+    let _ = writeln!(
+        out,
+        "**{}** _{}_\n",
+        escape(&entry.sender_name),
+        entry.time.format("%H:%M:%S")
+    );
+
+    if let Some(quote) = &entry.quote {
+        let _ = writeln!(out, "> **{}**", escape(&quote.author_name));
+        for line in quote.text.as_deref().unwrap_or_default().lines() {
+            let _ = writeln!(out, "> {}", escape(line));
+        }
+        out.push('\n');
+    }
+
+    if let Some(body) = entry.body.as_deref().filter(|b| !b.is_empty()) {
+        for line in body.lines() {
+            let _ = writeln!(out, "{}  ", escape(line));
+        }
+        out.push('\n');
+    }
+
+    for attachment in &entry.attachments {
+        let _ = writeln!(out, "{}\n", attachment_link(attachment));
+    }
+
+    if !entry.reactions.is_empty() {
+        let reactions: Vec<_> = entry
+            .reactions
+            .iter()
+            .map(|r| format!("{} {}", escape(&r.emoji), escape(&r.sender_name)))
+            .collect();
+        let _ = writeln!(out, "_Reactions: {}_\n", reactions.join(", "));
+    }
+}
+
+fn attachment_link(attachment: &Attachment) -> String {
+    let target = link_destination(&attachment.link_target());
+
+    let label = escape(&format!(
+        "{} ({} bytes)",
+        attachment.content_type, attachment.size
+    ));
+    if attachment.is_image() {
+        format!("![{label}](<{target}>)")
+    } else {
+        format!("[{label}](<{target}>)")
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '~'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Enough backticks that none in the text can close the span early
+fn code_span(text: &str) -> String {
+    let longest_run = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_run + 1);
+    if longest_run > 0 {
+        format!("{fence} {text} {fence}")
+    } else {
+        format!("{fence}{text}{fence}")
+    }
+}
+
+// For use between angle brackets, which must not appear in it unescaped, nor may line breaks
+fn link_destination(target: &str) -> String {
+    let mut escaped = String::with_capacity(target.len());
+    for c in target.chars() {
+        if matches!(c, '<' | '>' | '\\') || c.is_ascii_control() {
+            let _ = write!(escaped, "%{:02X}", c as u32);
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript::tests::sample_transcript;
+
+    #[test]
+    fn markup_is_escaped() {
+        let out = render(&sample_transcript());
+
+        assert!(out.starts_with("# \\<b\\>\\*Room\\*\\</b\\>\n"), "{out}");
+        assert!(out.contains("`` https://open.example.org/room?a=`b` `` (community)"));
+        assert!(out.contains("**\\_Bob\\_ \\<script\\>**"));
+        assert!(out.contains("\\*\\*bold\\*\\* \\<i\\>  \n\\# not a title  \n"));
+        assert!(out.contains("[text/plain\\](evil) (3 bytes)](<http://example.org/%3Ca%3E>)"));
+        assert!(out.contains("_Reactions: \\* \\`Eve\\`_"));
+    }
+}
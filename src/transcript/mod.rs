@@ -0,0 +1,412 @@
+mod html;
+mod json;
+mod markdown;
+
+use crate::base64::Base64;
+use crate::db::transcript::{TimeRange, TranscriptConversation, TranscriptRepositoryExt};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, TimeZone};
+use chrono_tz::Tz;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Markdown,
+    Html,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentMode {
+    /// Embed attachments in the transcript itself
+    Inline,
+    /// Write attachments next to the transcript and link to them
+    Files,
+}
+
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub timezone: Tz,
+    pub attachments: AttachmentMode,
+    pub range: TimeRange,
+}
+
+impl ExportOptions {
+    /// Turns a range of whole days in `timezone` into a [TimeRange], both ends inclusive.
+    pub fn days_to_range(
+        timezone: &Tz,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> anyhow::Result<TimeRange> {
+        let start_of = |date: NaiveDate| {
+            timezone
+                .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+                .earliest()
+                .map(|d| d.timestamp_millis())
+                .with_context(|| format!("{date} doesn't start in {timezone}"))
+        };
+
+        Ok(TimeRange {
+            from: from.map(start_of).transpose()?,
+            to: to
+                .map(|date| start_of(date.succ_opt().context("Date out of range")?))
+                .transpose()?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Transcript {
+    conversation: TranscriptConversation,
+    timezone: String,
+    messages: Vec<TranscriptEntry>,
+}
+
+#[derive(Serialize)]
+struct TranscriptEntry {
+    id: i64,
+    sender: String,
+    sender_name: String,
+    from_me: bool,
+    created_at: i64,
+    #[serde(serialize_with = "serialize_time")]
+    time: DateTime<Tz>,
+    body: Option<String>,
+    quote: Option<Quote>,
+    reactions: Vec<Reaction>,
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Serialize)]
+struct Quote {
+    author: String,
+    author_name: String,
+    text: Option<String>,
+    timestamp: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct Reaction {
+    sender: String,
+    sender_name: String,
+    emoji: String,
+}
+
+#[derive(Serialize)]
+struct Attachment {
+    id: String,
+    url: String,
+    content_type: String,
+    size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Base64<Vec<u8>>>,
+}
+
+impl Attachment {
+    fn is_image(&self) -> bool {
+        self.content_type.starts_with("image/")
+    }
+
+    /// Where the transcript points to for the attachment's content.
+    fn link_target(&self) -> String {
+        match (&self.file, &self.data) {
+            (Some(file), _) => file.clone(),
+            (None, Some(Base64(data))) => format!(
+                "data:{};base64,{}",
+                self.content_type,
+                base64::Engine::encode(&base64::prelude::BASE64_STANDARD, data)
+            ),
+            (None, None) => self.url.clone(),
+        }
+    }
+}
+
+/// Renders one conversation, or all of them, into `out_dir`. Returns the transcript files written.
+pub fn export(
+    conn: &Connection,
+    conversation_id: Option<&str>,
+    out_dir: &Path,
+    options: &ExportOptions,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let conversations = conn.get_transcript_conversations(conversation_id)?;
+    if let (Some(id), true) = (conversation_id, conversations.is_empty()) {
+        anyhow::bail!("Conversation {id} not found");
+    }
+
+    std::fs::create_dir_all(out_dir).with_context(|| format!("Creating {}", out_dir.display()))?;
+
+    let mut written = Vec::with_capacity(conversations.len());
+    for conversation in conversations {
+        let stem = file_stem(&conversation.id);
+        let transcript = load_transcript(conn, conversation, out_dir, &stem, options)?;
+
+        let rendered = match options.format {
+            ExportFormat::Json => json::render(&transcript)?,
+            ExportFormat::Markdown => markdown::render(&transcript),
+            ExportFormat::Html => html::render(&transcript),
+        };
+
+        let path = out_dir.join(format!("{stem}.{}", options.format.extension()));
+        std::fs::write(&path, rendered).with_context(|| format!("Writing {}", path.display()))?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+fn load_transcript(
+    conn: &Connection,
+    conversation: TranscriptConversation,
+    out_dir: &Path,
+    stem: &str,
+    options: &ExportOptions,
+) -> anyhow::Result<Transcript> {
+    let names = conn.get_display_names(&conversation)?;
+    let name_of = |id: &str| names.get(id).cloned().unwrap_or_else(|| id.to_string());
+
+    let messages = conn.get_transcript_messages(&conversation, options.range)?;
+    let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+
+    let mut reactions: HashMap<i64, Vec<Reaction>> = HashMap::new();
+    for r in conn.get_transcript_reactions(&ids)? {
+        reactions.entry(r.message_id).or_default().push(Reaction {
+            sender_name: name_of(&r.sender),
+            sender: r.sender,
+            emoji: r.emoji,
+        });
+    }
+
+    let attachment_dir = out_dir.join(format!("{stem}_attachments"));
+    let mut attachments: HashMap<i64, Vec<Attachment>> = HashMap::new();
+    for a in conn.get_transcript_attachments(&ids)? {
+        let (file, data) = match options.attachments {
+            AttachmentMode::Inline => (None, Some(Base64(a.content.clone()))),
+            AttachmentMode::Files => {
+                std::fs::create_dir_all(&attachment_dir)?;
+                let file_name = format!("{}.{}", file_stem(&a.id), extension_of(&a.content_type));
+                std::fs::write(attachment_dir.join(&file_name), &a.content)
+                    .with_context(|| format!("Writing attachment {}", a.id))?;
+                (Some(format!("{stem}_attachments/{file_name}")), None)
+            }
+        };
+
+        attachments
+            .entry(a.message_id)
+            .or_default()
+            .push(Attachment {
+                size: a.content.len(),
+                id: a.id,
+                url: a.url,
+                content_type: a.content_type,
+                file,
+                data,
+            });
+    }
+
+    let messages = messages
+        .into_iter()
+        .map(|m| {
+            let time = DateTime::from_timestamp_millis(m.created_at)
+                .with_context(|| format!("Invalid timestamp {}", m.created_at))?
+                .with_timezone(&options.timezone);
+
+            Ok(TranscriptEntry {
+                id: m.id,
+                sender_name: name_of(&m.sender),
+                sender: m.sender,
+                from_me: m.from_me,
+                created_at: m.created_at,
+                time,
+                body: m.body,
+                quote: m.quote_author.map(|author| Quote {
+                    author_name: name_of(&author),
+                    author,
+                    text: m.quote_text,
+                    timestamp: m.quote_timestamp,
+                }),
+                reactions: reactions.remove(&m.id).unwrap_or_default(),
+                attachments: attachments.remove(&m.id).unwrap_or_default(),
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(Transcript {
+        conversation,
+        timezone: options.timezone.name().to_string(),
+        messages,
+    })
+}
+
+fn serialize_time<S: serde::Serializer>(time: &DateTime<Tz>, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&time.to_rfc3339())
+}
+
+// Conversation IDs can be community URLs, and attachment IDs anything. An ID that isn't a safe
+// file name as is, on case insensitive file systems too, gets its checksum appended so that it
+// can't end up sharing a file with another.
+fn file_stem(id: &str) -> String {
+    if !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+    {
+        return id.to_string();
+    }
+
+    let readable: String = id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let checksum = crc::Crc::<u64>::new(&crc::CRC_64_XZ).checksum(id.as_bytes());
+    format!("{readable}_{checksum:016x}")
+}
+
+fn extension_of(content_type: &str) -> &'static str {
+    match content_type {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "video/mp4" => "mp4",
+        "audio/mpeg" => "mp3",
+        "audio/aac" => "aac",
+        "application/pdf" => "pdf",
+        "text/plain" => "txt",
+        _ => "bin",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Repository;
+    use crate::test_utils::{save_my_identity, temp_path, BOB, EVE, ME};
+    use r2d2_sqlite::SqliteConnectionManager;
+
+    /// A transcript with markup in every field that ends up in the output.
+    pub(super) fn sample_transcript() -> Transcript {
+        Transcript {
+            conversation: TranscriptConversation {
+                id: "https://open.example.org/room?a=`b`".into(),
+                conversation_type: "community".into(),
+                name: "<b>*Room*</b>".into(),
+                my_id: None,
+                my_blinded_id: None,
+            },
+            timezone: "UTC".into(),
+            messages: vec![TranscriptEntry {
+                id: 1,
+                sender: BOB.into(),
+                sender_name: "_Bob_ <script>".into(),
+                from_me: false,
+                created_at: 0,
+                time: DateTime::from_timestamp_millis(0)
+                    .unwrap()
+                    .with_timezone(&chrono_tz::UTC),
+                body: Some("**bold** <i>\n# not a title".into()),
+                quote: None,
+                reactions: vec![Reaction {
+                    sender: EVE.into(),
+                    sender_name: "`Eve`".into(),
+                    emoji: "*".into(),
+                }],
+                attachments: vec![Attachment {
+                    id: "a1".into(),
+                    url: "http://example.org/<a>".into(),
+                    content_type: "text/plain](evil)".into(),
+                    size: 3,
+                    file: None,
+                    data: None,
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn file_stems_dont_collide() {
+        assert_eq!(file_stem(BOB), BOB);
+
+        let stems = [
+            "https://example.org/room_a",
+            "https://example.org/room.a",
+            "https://example.org/Room_a",
+            "https://example.org/room_A",
+            "abc",
+            "Abc",
+            "",
+        ]
+        .map(file_stem);
+        for (i, stem) in stems.iter().enumerate() {
+            assert!(
+                stem.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                "{stem}"
+            );
+            for other in &stems[i + 1..] {
+                assert_ne!(stem.to_lowercase(), other.to_lowercase());
+            }
+        }
+    }
+
+    #[test]
+    fn export_resolves_sender_names() {
+        let (db_path, out_dir) = (temp_path("sqlite3"), temp_path("transcripts"));
+        let repo = Repository::new(SqliteConnectionManager::file(&*db_path), None)
+            .expect("To create repo");
+        let conn = repo.obtain_connection().expect("To get connection");
+
+        save_my_identity(&conn);
+        conn.execute_batch(&format!(
+            r#"
+            INSERT INTO configs (config_type, id, value) VALUES
+                ('UserProfileConfig', '', json_object('name', 'Me')),
+                ('ContactsConfig', '', json_array(
+                    json_object('session_id', '{BOB}', 'name', 'Bob', 'nickname', 'Bobby', 'approved', 1,
+                                'approved_me', 1, 'blocked', 0, 'priority', 0, 'profile_picture', NULL))),
+                ('ConvoInfoVolatileConfig', '', json_array(
+                    json_object('type', 'one_to_one', 'session_id', '{BOB}', 'last_read', 0, 'unread', 0)));
+            INSERT INTO messages (source, hash, content, kind, body, sender, receiver, created_at, expiration_at) VALUES
+                ('{ME}', 'h1', '{{}}', 'visible', 'Hi', '{BOB}', '{ME}', 1000, 9000),
+                ('{ME}', 'h2', '{{}}', 'visible', 'Hello', '{ME}', '{BOB}', 2000, 9000);
+            INSERT INTO message_reactions (message_id, sender, emoji, created_at)
+                SELECT id, '{EVE}', '👍', 3000 FROM messages WHERE hash = 'h1';
+            "#
+        ))
+        .expect("To insert data");
+
+        let options = ExportOptions {
+            format: ExportFormat::Json,
+            timezone: chrono_tz::UTC,
+            attachments: AttachmentMode::Inline,
+            range: TimeRange::default(),
+        };
+        let files = export(&conn, Some(BOB), &out_dir, &options).expect("To export");
+        assert_eq!(files, vec![out_dir.join(format!("{BOB}.json"))]);
+
+        let transcript: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&files[0]).expect("To read transcript"))
+                .expect("To parse transcript");
+        let messages = &transcript["messages"];
+        assert_eq!(messages[0]["sender_name"], "Bobby");
+        assert_eq!(messages[0]["from_me"], false);
+        assert_eq!(messages[1]["sender_name"], "Me");
+        assert_eq!(messages[1]["from_me"], true);
+
+        // Someone we know nothing about shows up as their ID
+        assert_eq!(messages[0]["reactions"][0]["sender_name"], EVE);
+    }
+}
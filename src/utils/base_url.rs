@@ -11,7 +11,8 @@ impl HttpBaseUrl {
 
         if (url.scheme().eq_ignore_ascii_case("http") || url.scheme().eq_ignore_ascii_case("https"))
             && url.has_host()
-            && !url.has_authority()
+            && url.username().is_empty()
+            && url.password().is_none()
             && url.query().is_none()
             && url.fragment().is_none()
         {
@@ -33,6 +34,7 @@ impl HttpUrlBuilder {
         self.0
             .path_segments_mut()
             .expect("To have path segment")
+            .pop_if_empty()
             .push(path_segment);
         self
     }
@@ -16,22 +16,28 @@ pub async fn gen_blinded_ids(
     repo: &Repository,
 ) -> anyhow::Result<()> {
     loop {
-        let conn = repo.obtain_connection()?;
-        let tx = conn.unchecked_transaction()?;
-        tx.remove_settings_by_name(<BlindedID as AppSetting>::NAME)?;
+        let ids = config_watcher
+            .borrow()
+            .get_groups()
+            .filter_map(|s| match s {
+                Group::Community(c) => Some(c),
+                _ => None,
+            })
+            .map(|g| {
+                let (blinded_id, _) =
+                    blinded_ids(identity.session_id().as_str(), &hex::encode(&g.pubkey))?;
+                anyhow::Ok((g.url_as_key(), blinded_id))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
 
-        let config = config_watcher.borrow();
-        for g in config.get_groups().filter_map(|s| match s {
-            Group::Community(c) => Some(c),
-            _ => None,
-        }) {
-            let (blinded_id, _) =
-                blinded_ids(identity.session_id().as_str(), &hex::encode(&g.pubkey))?;
-            tx.save_setting(Some(&g.url_as_key()), &blinded_id)?;
-        }
-
-        drop(config);
-        tx.commit()?;
+        repo.write(move |conn| {
+            conn.remove_settings_by_name(<BlindedID as AppSetting>::NAME)?;
+            for (url, blinded_id) in &ids {
+                conn.save_setting(Some(url.as_str()), blinded_id)?;
+            }
+            Ok(())
+        })
+        .await?;
 
         config_watcher.changed().await?;
     }
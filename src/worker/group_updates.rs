@@ -0,0 +1,61 @@
+use crate::config_state::ConfigState;
+use crate::db::messages::MessageRepositoryExt;
+use crate::db::Repository;
+use crate::events::{Event, EventBus};
+use crate::groups::GroupPromotion;
+use crate::session_id::{GroupID, IndividualID};
+use anyhow::Context;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Lists the groups we're invited to as pending conversations, takes the admin key of
+/// groups we're promoted in and marks the groups we're removed from as kicked.
+pub async fn track_group_updates(
+    repo: &Repository,
+    me: &IndividualID,
+    events: &EventBus,
+    config_state: &ConfigState,
+) -> anyhow::Result<()> {
+    let mut rx = events.subscribe();
+
+    loop {
+        match rx.recv().await {
+            Ok(Event::GroupInvited { group_id, name, .. }) => {
+                if let Err(e) = config_state.add_pending_group(&group_id, &name) {
+                    log::error!("Error adding pending group {group_id}: {e:?}");
+                }
+            }
+            Ok(Event::GroupPromoted { group_id, .. }) => {
+                if let Err(e) = accept_promotion(repo, me, config_state, &group_id).await {
+                    log::error!("Error accepting promotion in {group_id}: {e:?}");
+                }
+            }
+            Ok(Event::GroupMembersRemoved { group_id, members }) if members.contains(me) => {
+                log::info!("Removed from group {group_id}");
+                if let Err(e) = config_state.mark_group_kicked(&group_id) {
+                    log::error!("Error marking group {group_id} as kicked: {e:?}");
+                }
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(n)) => log::warn!("Missed {n} events while tracking groups"),
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn accept_promotion(
+    repo: &Repository,
+    me: &IndividualID,
+    config_state: &ConfigState,
+    group_id: &GroupID,
+) -> anyhow::Result<()> {
+    let me = me.clone();
+    let promotion = repo
+        .read(move |conn| conn.get_group_promotions(&me))
+        .await?
+        .iter()
+        .filter_map(|c| GroupPromotion::from_content(c)?.ok())
+        .find(|p| &p.group_id == group_id)
+        .context("Promotion not found")?;
+
+    config_state.accept_group_promotion(&promotion)
+}
@@ -1,12 +1,20 @@
 pub mod gen_blinded_ids;
+mod group_updates;
 mod poll_community;
 mod poll_messages;
+mod prune_messages;
+mod publish_events;
+mod send_messages;
 mod stream_messages;
 mod sync_config;
 mod sync_group;
 mod sync_group_configs;
 
+pub use group_updates::track_group_updates;
 pub use poll_messages::sync_messages;
+pub use prune_messages::prune_messages;
+pub use publish_events::publish_network_state;
+pub use send_messages::{expire_read_messages, send_messages, DEFAULT_MESSAGE_TTL};
 pub use stream_messages::stream_messages;
-pub use sync_config::sync_config;
-pub use sync_group::sync_groups;
+pub use sync_config::{push_config, sync_config, CONFIG_TTL};
+pub use sync_group::{sync_groups, GroupConfigState};
@@ -2,19 +2,27 @@ use std::borrow::Cow;
 use std::time::Duration;
 
 use anyhow::Context;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::{broadcast, mpsc};
-use tokio::try_join;
+use tokio::{select, try_join};
 
-use crate::clock::ClockSource;
-use crate::db::messages::{Message as DbMessage, MessageJobState};
-use crate::db::models::MessageSource;
-use crate::db::{messages::MessageRepositoryExt, Repository};
+use crate::app_setting::identity::PublicIdentity;
+use crate::clock::{ClockSource, Timestamp};
+use crate::config::{GroupKeys, NamedConfig};
+use crate::db::app_setting::AppSettingRepositoryExt;
+use crate::db::dead_letters::{DeadLetter, DeadLetterRepositoryExt};
+use crate::db::messages::{Message as DbMessage, MessageJobState, MessageKind};
+use crate::db::models::{ExpiryMode, ExpirySetting, MessageSource};
+use crate::db::{messages::MessageRepositoryExt, Repository, TableName};
+use crate::events::{Event, EventBus};
+use crate::groups::{accepts_invite, removed_members, GroupInvite, GroupPromotion};
 use crate::network::swarm::SwarmAuth;
-use crate::oxenss::message::{RegularMessage, RegularMessageDecoder};
+use crate::oxenss::message::{RegularMessage, RegularMessageDecoder, Undecryptable};
 use crate::oxenss::namespace::MessageNamespace;
 use crate::oxenss::retrieve::Message as ApiMessage;
 use crate::oxenss::JsonRpcCallSource;
-use crate::session_id::SessionID;
+use crate::protos::Content;
+use crate::session_id::{GroupID, IndividualID, IndividualOrBlindedID, SessionID};
 
 pub async fn sync_messages<NS, CS>(
     repo: &Repository,
@@ -24,6 +32,7 @@ pub async fn sync_messages<NS, CS>(
     poll_interval: Duration,
     manual_poll_trigger: broadcast::Receiver<()>,
     clock_source: &ClockSource,
+    events: &EventBus,
 ) -> anyhow::Result<()>
 where
     NS: MessageNamespace + RegularMessageDecoder,
@@ -48,27 +57,226 @@ where
 
     let save = async {
         while let Some(Ok(messages)) = message_rx.recv().await {
-            let conn = repo.obtain_connection()?;
-            let tx = conn
-                .unchecked_transaction()
-                .context("Starting transaction")?;
-
-            tx.save_messages(messages.iter().filter_map(|msg| {
-                create_db_message::<NS>(swarm_auth, &message_source, msg)
-                    .inspect_err(|e| log::error!("Failed to create db message: {e:?}"))
-                    .ok()
-            }))
-            .context("Saving messages")?;
-            tx.commit().context("Committing transaction")?;
+            save_messages::<NS>(
+                repo,
+                swarm_auth,
+                &session_id,
+                &message_source,
+                &messages,
+                clock_source,
+                events,
+            )
+            .await?;
         }
 
         Ok(())
     };
 
-    try_join!(stream, save)?;
+    // Undecryptable messages may become readable once the group keys or our identity change
+    let retry = async {
+        let mut events_rx = events.subscribe();
+        let mut table_changes = repo.subscribe_table_changes();
+        let session_id_str = session_id.to_string();
+
+        // Letters left over from the last run get a try at startup
+        let mut keys_changed = true;
+        loop {
+            if keys_changed {
+                let now = clock_source.now_or_uncalibrated();
+                let letters = {
+                    let source = message_source.to_string();
+                    repo.read(move |conn| {
+                        conn.get_dead_letters(Some(&source), Some(NS::INT_VALUE), now)
+                    })
+                    .await?
+                };
+                if !letters.is_empty() {
+                    log::info!(
+                        "Retrying {} undecryptable messages from {}",
+                        letters.len(),
+                        NS::DISPLAY_NAME
+                    );
+                    let messages: Vec<_> = letters.iter().map(DeadLetter::to_message).collect();
+                    save_messages::<NS>(
+                        repo,
+                        swarm_auth,
+                        &session_id,
+                        &message_source,
+                        &messages,
+                        clock_source,
+                        events,
+                    )
+                    .await?;
+                }
+            }
+
+            keys_changed = select! {
+                event = events_rx.recv() => match event {
+                    Ok(Event::ConfigMerged { kind, id }) => {
+                        kind == GroupKeys::CONFIG_TYPE_NAME && id.as_ref() == Some(&session_id_str)
+                    }
+                    Ok(_) => false,
+                    Err(RecvError::Lagged(_)) => true,
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                changes = table_changes.recv() => match changes {
+                    Ok(changes) => {
+                        let row_ids: Vec<_> = changes
+                            .iter()
+                            .filter(|c| c.table == TableName::AppSettings)
+                            .map(|c| c.row_id)
+                            .collect();
+                        !row_ids.is_empty()
+                            && repo
+                                .read(move |conn| conn.is_setting_row::<PublicIdentity>(&row_ids))
+                                .await?
+                    }
+                    Err(RecvError::Lagged(_)) => true,
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+            };
+        }
+    };
+
+    try_join!(stream, save, retry)?;
     Ok(())
 }
 
+/// Saves the messages that could be decrypted, and puts the ones we have no key for in the dead
+/// letters until they can be. Messages that are broken in any other way are dropped.
+async fn save_messages<NS>(
+    repo: &Repository,
+    swarm_auth: &impl SwarmAuth,
+    session_id: &SessionID,
+    message_source: &MessageSource<'_>,
+    messages: &[ApiMessage],
+    clock_source: &ClockSource,
+    events: &EventBus,
+) -> anyhow::Result<()>
+where
+    NS: MessageNamespace + RegularMessageDecoder,
+{
+    let now = clock_source.now_or_uncalibrated();
+    let mut db_messages = Vec::with_capacity(messages.len());
+    let mut dead_letters = Vec::new();
+    let mut dropped = Vec::new();
+    for msg in messages {
+        match create_db_message::<NS>(swarm_auth, message_source, msg) {
+            Ok(m) => db_messages.push(m.into_owned()),
+            Err(e) if e.downcast_ref::<Undecryptable>().is_some() => {
+                log::warn!("Failed to decrypt message {}: {e:?}", msg.hash);
+                dead_letters.push(DeadLetter::new(
+                    message_source,
+                    NS::INT_VALUE,
+                    msg,
+                    format!("{e:#}"),
+                    now,
+                ));
+            }
+            // No key will make these any better
+            Err(e) => {
+                log::warn!("Dropping message {}: {e:?}", msg.hash);
+                dropped.push(msg.hash.clone());
+            }
+        }
+    }
+
+    let source = message_source.to_string();
+    let saved = repo
+        .write(move |conn| {
+            let hashes: Vec<_> = db_messages
+                .iter()
+                .filter_map(|m| m.hash.as_deref())
+                .chain(dropped.iter().map(String::as_str))
+                .collect();
+            conn.remove_dead_letters(&source, NS::INT_VALUE, &hashes)?;
+            conn.save_dead_letters(&dead_letters)?;
+            conn.save_messages(db_messages.into_iter())
+        })
+        .await
+        .context("Saving messages")?;
+
+    for msg in saved {
+        match session_id {
+            SessionID::Individual(me) => publish_group_updates(&msg, me, events),
+            SessionID::Group(group_id) => publish_member_changes(&msg, group_id, events),
+            _ => {}
+        }
+
+        events.publish(Event::MessageReceived {
+            source: msg.source.to_string(),
+            hash: msg.hash.unwrap_or_default().into_owned(),
+            sender: msg.sender.into_owned(),
+            receiver: msg.receiver.into_owned(),
+            kind: msg.kind,
+            created_at: msg.created_at,
+        });
+    }
+
+    Ok(())
+}
+
+fn publish_group_updates(msg: &DbMessage, me: &IndividualID, events: &EventBus) {
+    let IndividualOrBlindedID::Individual(sender) = msg.sender.as_ref() else {
+        return;
+    };
+
+    if msg.kind != MessageKind::Control || msg.receiver.as_ref() != &SessionID::from(me.clone()) {
+        return;
+    }
+
+    let Ok(content) = serde_json::from_str::<Content>(&msg.content) else {
+        return;
+    };
+
+    match GroupInvite::from_content(&content, me) {
+        Some(Ok(invite)) => events.publish(Event::GroupInvited {
+            group_id: invite.group_id,
+            inviter: sender.clone(),
+            name: invite.name,
+        }),
+        Some(Err(e)) => log::warn!("Ignoring group invite from {sender}: {e:?}"),
+        None => {}
+    }
+
+    match GroupPromotion::from_content(&content) {
+        Some(Ok(promotion)) => events.publish(Event::GroupPromoted {
+            group_id: promotion.group_id,
+            promoter: sender.clone(),
+        }),
+        Some(Err(e)) => log::warn!("Ignoring group promotion from {sender}: {e:?}"),
+        None => {}
+    }
+}
+
+fn publish_member_changes(msg: &DbMessage, group_id: &GroupID, events: &EventBus) {
+    if msg.kind != MessageKind::Control {
+        return;
+    }
+
+    let Ok(content) = serde_json::from_str::<Content>(&msg.content) else {
+        return;
+    };
+
+    match removed_members(&content, group_id) {
+        Some(Ok(members)) => events.publish(Event::GroupMembersRemoved {
+            group_id: group_id.clone(),
+            members,
+        }),
+        Some(Err(e)) => log::warn!("Ignoring member change in {group_id}: {e:?}"),
+        None => {}
+    }
+
+    if let IndividualOrBlindedID::Individual(sender) = msg.sender.as_ref() {
+        if accepts_invite(&content) {
+            events.publish(Event::GroupInviteAccepted {
+                group_id: group_id.clone(),
+                member: sender.clone(),
+            });
+        }
+    }
+}
+
 fn create_db_message<'a, Decoder: RegularMessageDecoder>(
     auth: &'a impl SwarmAuth,
     source: &'a MessageSource<'_>,
@@ -79,7 +287,11 @@ fn create_db_message<'a, Decoder: RegularMessageDecoder>(
         created,
     }: &'a ApiMessage,
 ) -> anyhow::Result<DbMessage<'a>> {
-    let RegularMessage { sender, content } = Decoder::decode_and_decrypt(data.as_slice(), auth)?;
+    let RegularMessage {
+        sender,
+        content,
+        sent_at,
+    } = Decoder::decode_and_decrypt(data.as_slice(), auth)?;
 
     let receiver = match content
         .data_message
@@ -90,16 +302,50 @@ fn create_db_message<'a, Decoder: RegularMessageDecoder>(
         None => auth.session_id().into_owned().into(),
     };
 
+    let expire_timer = content
+        .expiration_timer
+        .or_else(|| content.data_message.as_ref().and_then(|d| d.expire_timer))
+        .filter(|t| *t != 0);
+
+    let expiry = ExpirySetting::new(
+        ExpiryMode::from_proto(content.expiration_type()),
+        expire_timer.unwrap_or_default(),
+    );
+
+    // The swarm keeps messages for their TTL, which may be longer than the timer the sender set
+    let expiration_at = match (expiry.mode, expiry.timer()) {
+        (ExpiryMode::AfterSend, Some(timer)) => {
+            Timestamp::from_mills(created.as_millis() + timer.as_millis() as u64)
+                .map_or(*expiration, |t| t.min(*expiration))
+        }
+        _ => *expiration,
+    };
+
     Ok(DbMessage {
-        source,
-        hash: Some(&hash),
+        source: source.clone(),
+        hash: Some(Cow::Borrowed(hash.as_str())),
         content: Cow::Owned(
             serde_json::to_string(&content).context("Serialising message content")?,
         ),
+        kind: MessageKind::of(&content),
+        body: content
+            .data_message
+            .as_ref()
+            .and_then(|d| d.body.clone())
+            .filter(|b| !b.is_empty())
+            .map(Cow::Owned),
+        attachment_count: content
+            .data_message
+            .as_ref()
+            .map_or(0, |d| d.attachments.len()),
+        expire_timer,
+        expiry_mode: expiry.mode,
         sender: Cow::Owned(sender),
         receiver: Cow::Owned(receiver),
-        created_at: *created,
-        expiration_at: *expiration,
+        // Our own sends are queued with the time they were sent at, which may be long before
+        // they reach the swarm
+        created_at: sent_at.unwrap_or(*created),
+        expiration_at,
         quoting_timestamp: content
             .data_message
             .as_ref()
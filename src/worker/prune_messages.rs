@@ -0,0 +1,46 @@
+use crate::clock::ClockSource;
+use crate::db::dead_letters::DeadLetterRepositoryExt;
+use crate::db::retention::RetentionRepositoryExt;
+use crate::db::Repository;
+use crate::events::{Event, EventBus};
+use std::time::Duration;
+
+pub async fn prune_messages(
+    repo: &Repository,
+    clock_source: &ClockSource,
+    events: &EventBus,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    loop {
+        let now = clock_source.now_or_uncalibrated();
+        match repo
+            .write(move |conn| {
+                let report = conn.prune_messages(now)?;
+                let dead_letters = conn.remove_expired_dead_letters(now)?;
+                Ok((report, dead_letters))
+            })
+            .await
+        {
+            Ok((report, dead_letters)) => {
+                if dead_letters > 0 {
+                    log::info!("Removed {dead_letters} expired dead letters");
+                }
+
+                if report.is_empty() {
+                    log::debug!("Nothing to prune");
+                } else {
+                    log::info!(
+                        "Pruned {} messages and {} attachments ({} bytes)",
+                        report.removed_messages(),
+                        report.evicted_attachments,
+                        report.evicted_attachment_bytes
+                    );
+                }
+                events.publish(Event::MessagesPruned { report });
+            }
+            Err(e) => log::error!("Error pruning messages: {e:?}"),
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,17 @@
+use crate::events::{Event, EventBus, NetworkStatus};
+use crate::network::Network;
+
+pub async fn publish_network_state(
+    network: &impl Network,
+    events: &EventBus,
+) -> anyhow::Result<()> {
+    let mut rx = network.watch_state();
+    loop {
+        let state = NetworkStatus::from(&*rx.borrow_and_update());
+        events.publish(Event::NetworkStateChanged { state });
+
+        if rx.changed().await.is_err() {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use crate::clock::{ClockSource, Timestamp};
+use crate::config_state::ConfigState;
+use crate::db::messages::{MessageRepositoryExt, PendingMessage};
+use crate::db::models::{ExpiryMode, ExpirySetting, MessageSource};
+use crate::db::watch::with_changes;
+use crate::db::{Repository, TableName};
+use crate::events::{Event, EventBus};
+use crate::groups::send_group_message;
+use crate::identity::Identity;
+use crate::network::swarm::{SwarmAuth, SwarmState};
+use crate::oxenss::expire::ExpireMessagesRequest;
+use crate::oxenss::store::StoreMessageRequest;
+use crate::oxenss::{JsonRpcCallSource, JsonRpcCallSourceExt};
+use crate::protos::Content;
+use crate::session_id::SessionID;
+use crate::worker::GroupConfigState;
+use anyhow::{anyhow, bail, Context};
+use futures_util::StreamExt;
+use std::collections::BTreeMap;
+use std::pin::pin;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How long the swarm keeps messages that don't disappear.
+pub const DEFAULT_MESSAGE_TTL: Duration = Duration::from_secs(14 * 24 * 3600);
+
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sends the messages waiting in the database, applying the conversation's disappearing
+/// messages setting at the time of sending.
+pub async fn send_messages<CS>(
+    repo: &Repository,
+    call_source: &CS,
+    swarm_state: SwarmState,
+    identity: &Identity,
+    config_state: &ConfigState,
+    clock: &ClockSource,
+    events: &EventBus,
+) -> anyhow::Result<()>
+where
+    CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
+{
+    let source: MessageSource = SessionID::from(identity.session_id().into_owned()).into();
+    let (source, swarm_state) = (&source, &swarm_state);
+
+    let mut changes = pin!(with_changes(
+        repo.subscribe_table_changes(),
+        &[TableName::Messages],
+        MIN_INTERVAL,
+        move || async move {
+            let pending = {
+                let source = source.clone();
+                repo.read(move |conn| conn.get_pending_sends(&source))
+                    .await?
+            };
+
+            for message in pending {
+                let (id, created_at) = (message.id, message.created_at);
+                let now = clock.now_or_uncalibrated();
+
+                match send_message(
+                    call_source,
+                    swarm_state,
+                    identity,
+                    config_state,
+                    message,
+                    now,
+                )
+                .await
+                {
+                    Ok(sent) => {
+                        let hash = sent.hash.clone();
+                        repo.write(move |conn| {
+                            conn.mark_message_sent(
+                                id,
+                                &sent.hash,
+                                &sent.content,
+                                sent.expiry,
+                                now,
+                                sent.expiration_at,
+                            )
+                        })
+                        .await?;
+
+                        events.publish(Event::MessageSent {
+                            source: source.to_string(),
+                            hash,
+                            created_at,
+                        });
+                    }
+
+                    Err(e) => {
+                        log::error!("Error sending message {id}: {e:?}");
+                        let error = format!("{e:#}");
+                        {
+                            let error = error.clone();
+                            repo.write(move |conn| conn.mark_message_failed(id, &error, now))
+                                .await?;
+                        }
+
+                        events.publish(Event::MessageFailed {
+                            source: source.to_string(),
+                            created_at,
+                            error,
+                        });
+                    }
+                }
+            }
+
+            anyhow::Ok(())
+        },
+    ));
+
+    while let Some(result) = changes.next().await {
+        if let Err(e) = result {
+            log::error!("Error sending messages: {e:?}");
+        }
+    }
+
+    Ok(())
+}
+
+struct SentMessage {
+    hash: String,
+    content: Content,
+    expiry: ExpirySetting,
+    expiration_at: Timestamp,
+}
+
+async fn send_message<CS>(
+    call_source: &CS,
+    swarm_state: &SwarmState,
+    identity: &Identity,
+    config_state: &ConfigState,
+    PendingMessage {
+        mut content,
+        receiver,
+        created_at,
+        ..
+    }: PendingMessage,
+    now: Timestamp,
+) -> anyhow::Result<SentMessage>
+where
+    CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
+{
+    let SessionID::Individual(receiver) = receiver else {
+        bail!("Sending to {receiver} is not supported");
+    };
+
+    let me = identity.session_id();
+    let expiry = config_state.expiry_setting(&me, &receiver);
+    apply_expiry(&mut content, expiry);
+
+    // After-read messages wait on the receiver's swarm until they're read
+    let timer = expiry.timer();
+    let receiver_ttl = match expiry.mode {
+        ExpiryMode::AfterSend => timer.unwrap_or(DEFAULT_MESSAGE_TTL),
+        _ => DEFAULT_MESSAGE_TTL,
+    };
+    let own_ttl = timer.unwrap_or(DEFAULT_MESSAGE_TTL);
+
+    if receiver != *me {
+        let req = StoreMessageRequest::new_unauthenticated(
+            identity.ed25519_sec_key(),
+            &receiver,
+            &content,
+            receiver_ttl,
+            created_at,
+            now,
+        )?;
+
+        call_source
+            .perform_json_rpc(SwarmState::new(receiver.clone().into()), &req)
+            .await
+            .with_context(|| format!("Storing message on {receiver}'s swarm"))?;
+
+        // The copy for our other devices says who it was meant for
+        if let Some(data_message) = content.data_message.as_mut() {
+            data_message.sync_target = Some(receiver.to_string());
+        }
+    }
+
+    let req = StoreMessageRequest::new_unauthenticated(
+        identity.ed25519_sec_key(),
+        &me,
+        &content,
+        own_ttl,
+        created_at,
+        now,
+    )?;
+
+    let hash = call_source
+        .perform_json_rpc(swarm_state.clone(), &req)
+        .await
+        .context("Storing message on our swarm")?
+        .hash;
+
+    Ok(SentMessage {
+        hash,
+        content,
+        expiry,
+        // The TTL counts from when the swarm got it
+        expiration_at: Timestamp::from_mills(now.as_millis() + own_ttl.as_millis() as u64)
+            .context("Invalid expiration")?,
+    })
+}
+
+/// Sends the messages waiting to be posted to the group, with the group's disappearing
+/// messages setting. They keep their row, which the poller recognises once it sees them on the
+/// group swarm.
+pub async fn send_group_messages<CS>(
+    repo: &Repository,
+    call_source: &CS,
+    identity: &Identity,
+    configs: &watch::Sender<GroupConfigState>,
+    auth: &impl SwarmAuth,
+    clock: &ClockSource,
+    events: &EventBus,
+) -> anyhow::Result<()>
+where
+    CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
+{
+    let group_id = configs.borrow().group_id.clone();
+    let source: MessageSource = SessionID::from(group_id.clone()).into();
+    let (source, group_id) = (&source, &group_id);
+    let configs = &configs.subscribe();
+
+    let mut changes = pin!(with_changes(
+        repo.subscribe_table_changes(),
+        &[TableName::Messages],
+        MIN_INTERVAL,
+        move || async move {
+            let pending = {
+                let source = source.clone();
+                repo.read(move |conn| conn.get_pending_sends(&source))
+                    .await?
+            };
+
+            for PendingMessage {
+                id,
+                mut content,
+                receiver,
+                created_at,
+            } in pending
+            {
+                let now = clock.now_or_uncalibrated();
+                let expiry = configs.borrow().group_info.expiry_setting();
+                apply_expiry(&mut content, expiry);
+                let ttl = expiry.timer().unwrap_or(DEFAULT_MESSAGE_TTL);
+
+                let result = if receiver == SessionID::from(group_id.clone()) {
+                    send_group_message(
+                        call_source,
+                        configs,
+                        auth,
+                        &identity.session_id(),
+                        &content,
+                        ttl,
+                        created_at,
+                        now,
+                    )
+                    .await
+                } else {
+                    Err(anyhow!(
+                        "Sending to {receiver} from {group_id} is not supported"
+                    ))
+                };
+
+                match result {
+                    // The poller may save our message from the group swarm before it's marked as
+                    // sent here. Its copy carries `created_at` as the sent timestamp, so the
+                    // UNIQUE (source, created_at) index makes its INSERT OR IGNORE a no-op and
+                    // this row stays the only one.
+                    Ok(hash) => {
+                        let expiration_at =
+                            Timestamp::from_mills(now.as_millis() + ttl.as_millis() as u64)
+                                .context("Invalid expiration")?;
+                        {
+                            let hash = hash.clone();
+                            repo.write(move |conn| {
+                                conn.mark_message_sent(
+                                    id,
+                                    &hash,
+                                    &content,
+                                    expiry,
+                                    now,
+                                    expiration_at,
+                                )
+                            })
+                            .await?;
+                        }
+
+                        events.publish(Event::MessageSent {
+                            source: source.to_string(),
+                            hash,
+                            created_at,
+                        });
+                    }
+
+                    Err(e) => {
+                        log::error!("Error sending message {id} to {group_id}: {e:?}");
+                        let error = format!("{e:#}");
+                        {
+                            let error = error.clone();
+                            repo.write(move |conn| conn.mark_message_failed(id, &error, now))
+                                .await?;
+                        }
+
+                        events.publish(Event::MessageFailed {
+                            source: source.to_string(),
+                            created_at,
+                            error,
+                        });
+                    }
+                }
+            }
+
+            anyhow::Ok(())
+        },
+    ));
+
+    while let Some(result) = changes.next().await {
+        if let Err(e) = result {
+            log::error!("Error sending messages to {group_id}: {e:?}");
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_expiry(content: &mut Content, expiry: ExpirySetting) {
+    if expiry.is_enabled() {
+        content.expiration_type = Some(expiry.mode.to_proto() as i32);
+        content.expiration_timer = Some(expiry.timer_seconds);
+    } else {
+        content.expiration_type = None;
+        content.expiration_timer = None;
+    }
+}
+
+/// Starts the countdown of after-read messages once their conversation has been read, and asks
+/// our swarm to drop them when it ends.
+pub async fn expire_read_messages<CS>(
+    repo: &Repository,
+    call_source: &CS,
+    swarm_state: SwarmState,
+    identity: &Identity,
+    clock: &ClockSource,
+) -> anyhow::Result<()>
+where
+    CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
+{
+    let my_source = SessionID::from(identity.session_id().into_owned()).to_string();
+    let (my_source, swarm_state) = (my_source.as_str(), &swarm_state);
+
+    let mut changes = pin!(with_changes(
+        repo.subscribe_table_changes(),
+        &[TableName::Messages, TableName::Configs],
+        MIN_INTERVAL,
+        move || async move {
+            let now = clock.now_or_uncalibrated();
+            let expiring = repo
+                .write(move |conn| conn.start_after_read_timers(now))
+                .await?;
+
+            // Only our own swarm takes orders from us
+            let mut by_expiry = BTreeMap::<Timestamp, Vec<String>>::new();
+            for message in expiring {
+                if message.source.eq_ignore_ascii_case(my_source) {
+                    by_expiry
+                        .entry(message.expiration_at)
+                        .or_default()
+                        .push(message.hash);
+                }
+            }
+
+            for (expiry, hashes) in by_expiry {
+                let req = ExpireMessagesRequest::new_shorten(identity, &hashes, expiry)?;
+                call_source
+                    .perform_json_rpc(swarm_state.clone(), &req)
+                    .await
+                    .context("Shortening message expiry")?;
+                log::info!("{} read messages now expire at {expiry}", hashes.len());
+            }
+
+            anyhow::Ok(())
+        },
+    ));
+
+    while let Some(result) = changes.next().await {
+        if let Err(e) = result {
+            log::error!("Error expiring read messages: {e:?}");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos::content::ExpirationType;
+
+    #[test]
+    fn expiry_is_set_on_the_content() {
+        let mut content = Content::default();
+        apply_expiry(&mut content, ExpirySetting::new(ExpiryMode::AfterRead, 60));
+        assert_eq!(
+            content.expiration_type,
+            Some(ExpirationType::DeleteAfterRead as i32)
+        );
+        assert_eq!(content.expiration_timer, Some(60));
+
+        apply_expiry(&mut content, ExpirySetting::new(ExpiryMode::AfterSend, 0));
+        assert_eq!(content.expiration_type, None);
+        assert_eq!(content.expiration_timer, None);
+    }
+}
@@ -33,9 +33,10 @@ where
     );
 
     loop {
+        let source = message_source.clone();
         let last_hash = repo
-            .obtain_connection()?
-            .get_last_message_hash::<NS>(&message_source)
+            .read(move |c| c.get_last_message_hash::<NS>(&source))
+            .await
             .context("Retrieving latest hash")?;
         let resp = call_source
             .perform_json_rpc(
@@ -52,8 +53,10 @@ where
         match resp {
             Ok(mut resp) => {
                 if let Some(last_hash) = resp.latest_hash() {
-                    repo.obtain_connection()?
-                        .save_last_message_hash::<NS>(&message_source, last_hash)
+                    let source = message_source.clone();
+                    let last_hash = last_hash.to_string();
+                    repo.write(move |c| c.save_last_message_hash::<NS>(&source, &last_hash))
+                        .await
                         .context("Saving latest message hash")?;
                 }
 
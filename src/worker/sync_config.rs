@@ -5,27 +5,37 @@ use tokio::sync::{broadcast, mpsc, watch};
 use tokio::time::sleep;
 use tokio::try_join;
 
+use crate::bindings;
 use crate::clock::ClockSource;
 use crate::config::Config;
-use crate::db::config::ConfigRepositoryExt;
+use crate::cwrapper::CWrapper;
+use crate::db::config::{ConfigRecord, ConfigRepositoryExt};
 use crate::db::Repository;
+use crate::events::{Event, EventBus};
 use crate::network::swarm::SwarmAuth;
+use crate::oxenss::delete::DeleteMessagesRequest;
+use crate::oxenss::store::StoreMessageRequest;
 use crate::oxenss::{namespace::MessageNamespace, JsonRpcCallSource, JsonRpcCallSourceExt};
 
-pub async fn sync_config<NS, CS>(
+/// How long the swarm keeps a config message, unless a newer one makes it obsolete first.
+pub const CONFIG_TTL: Duration = Duration::from_secs(30 * 24 * 3600);
+
+pub async fn sync_config<NS, CS, C>(
     call_source: &CS,
     call_source_arg: CS::SourceArg<'_>,
     config_id: Option<&str>,
-    watcher: &watch::Sender<impl for<'a> Config<MergeArg<'a> = ()>>,
+    watcher: &watch::Sender<C>,
     poll_interval: Duration,
     manual_trigger: broadcast::Receiver<()>,
     repo: &Repository,
     swarm_auth: &impl SwarmAuth,
     clock_source: &ClockSource,
+    events: &EventBus,
 ) -> anyhow::Result<()>
 where
     NS: MessageNamespace,
     CS: JsonRpcCallSource,
+    C: for<'a> Config<MergeArg<'a> = (), PushData = CWrapper<bindings::config_push_data>>,
     for<'a> <CS as JsonRpcCallSource>::SourceArg<'a>: Clone,
 {
     let (msg_tx, mut msg_rx) = mpsc::channel(10);
@@ -47,18 +57,33 @@ where
                 continue;
             }
 
+            let mut merged = false;
             watcher.send_if_modified(|c| {
-                if let Err(e) = c.merge(&messages, ()) {
-                    log::error!("Error merging messages into config: {e:?}");
+                match c.merge(&messages, ()) {
+                    Ok(_) => merged = true,
+                    Err(e) => log::error!("Error merging messages into config: {e:?}"),
                 }
                 true
             });
+
+            if merged {
+                events.publish(Event::ConfigMerged {
+                    kind: C::CONFIG_TYPE_NAME,
+                    id: config_id.map(ToString::to_string),
+                });
+            }
         }
         Ok(())
     };
 
     let save = save_config_to_db::<NS, _>(watcher, repo, config_id);
-    let push = push_config_if_needed::<NS, _>(call_source, call_source_arg, watcher, swarm_auth);
+    let push = push_config_if_needed::<NS, _>(
+        call_source,
+        call_source_arg,
+        watcher,
+        swarm_auth,
+        clock_source,
+    );
 
     try_join!(streaming, streamed_to_config, save, push)?;
     Ok(())
@@ -70,22 +95,19 @@ pub(super) async fn save_config_to_db<NS: MessageNamespace, C: Config>(
     config_id: Option<&str>,
 ) -> anyhow::Result<()> {
     let mut config_rx = config.subscribe();
+    let config_id = config_id.map(ToString::to_string);
     loop {
-        let mut err = None;
+        let mut record = None;
         config.send_if_modified(|config| {
-            if let Err(e) = repo
-                .obtain_connection()
-                .and_then(move |c| c.save_config(config, config_id))
-            {
-                err.replace(e);
-            }
-
+            record.replace(ConfigRecord::take(config));
             false
         });
 
-        if let Some(e) = err {
-            return Err(e);
-        }
+        let record = record.context("Taking config record")??;
+        let config_id = config_id.clone();
+        repo.write(move |c| c.save_config_record(&record, config_id.as_deref()))
+            .await
+            .context("Saving config")?;
 
         config_rx.changed().await.context("Waiting for config")?;
     }
@@ -94,8 +116,9 @@ pub(super) async fn save_config_to_db<NS: MessageNamespace, C: Config>(
 pub(super) async fn push_config_if_needed<NS, CS>(
     call_source: &CS,
     call_source_arg: CS::SourceArg<'_>,
-    config: &watch::Sender<impl Config>,
+    config: &watch::Sender<impl Config<PushData = CWrapper<bindings::config_push_data>>>,
     swarm_auth: &impl SwarmAuth,
+    clock_source: &ClockSource,
 ) -> anyhow::Result<()>
 where
     NS: MessageNamespace,
@@ -105,19 +128,13 @@ where
     let mut config_rx = config.subscribe();
 
     while config_rx.wait_for(|c| c.needs_push()).await.is_ok() {
-        let mut push_data = None;
-        config.send_if_modified(|c| {
-            push_data.replace(c.push());
-            false
-        });
-
-        let push = async {
-            let push_data = push_data
-                .context("Empty push data")?
-                .context("Error getting pushing data from config system")?;
-
-            anyhow::Ok(())
-        };
+        let push = push_config::<NS, _>(
+            call_source,
+            call_source_arg.clone(),
+            config,
+            swarm_auth,
+            clock_source,
+        );
 
         if let Err(e) = push.await {
             let duration = Duration::from_secs(5);
@@ -130,3 +147,67 @@ where
     }
     Ok(())
 }
+
+/// Pushes the config once if it has changes the swarm doesn't have yet.
+pub async fn push_config<NS, CS>(
+    call_source: &CS,
+    call_source_arg: CS::SourceArg<'_>,
+    config: &watch::Sender<impl Config<PushData = CWrapper<bindings::config_push_data>>>,
+    swarm_auth: &impl SwarmAuth,
+    clock_source: &ClockSource,
+) -> anyhow::Result<()>
+where
+    NS: MessageNamespace,
+    CS: JsonRpcCallSource,
+    for<'a> <CS as JsonRpcCallSource>::SourceArg<'a>: Clone,
+{
+    let mut push_data = None;
+    config.send_if_modified(|c| {
+        push_data = c.needs_push().then(|| c.push());
+        false
+    });
+
+    let Some(push_data) = push_data else {
+        return Ok(());
+    };
+    let push_data = push_data.context("Error getting pushing data from config system")?;
+
+    let req = StoreMessageRequest::new_authenticated::<NS>(
+        swarm_auth,
+        push_data.config_data(),
+        CONFIG_TTL,
+        clock_source.now_or_uncalibrated(),
+    )?;
+
+    let hash = call_source
+        .perform_json_rpc(call_source_arg.clone(), &req)
+        .await
+        .context("Storing config")?
+        .hash;
+
+    // Confirming marks the config as needing a dump, which the saving task picks up
+    let seqno = push_data.seqno;
+    config.send_modify(|c| c.confirm_pushed(seqno, &hash));
+
+    let obsolete: Vec<String> = push_data
+        .obsolete_message_hashes()
+        .filter_map(|h| h.to_str().ok())
+        .map(ToString::to_string)
+        .collect();
+
+    if !obsolete.is_empty() {
+        let req = DeleteMessagesRequest::new(swarm_auth, &obsolete)?;
+        if let Err(e) = call_source.perform_json_rpc(call_source_arg, &req).await {
+            log::warn!(
+                "Failed to delete {} obsolete configs: {e:?}",
+                obsolete.len()
+            );
+        }
+    }
+
+    log::info!(
+        "Pushed config to namespace {}, seqno = {seqno}, hash = {hash}",
+        NS::INT_VALUE
+    );
+    Ok(())
+}
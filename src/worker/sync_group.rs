@@ -1,13 +1,15 @@
 use crate::base64::Base64;
 use crate::clock::ClockSource;
 use crate::config::{
-    Config, GroupConfig, GroupInfo, GroupInfoConfig, GroupMemberConfig, NamedConfig,
+    Config, GroupConfig, GroupInfo, GroupInfoConfig, GroupMemberConfig, MemberStatus, NamedConfig,
     SubaccountAuth, UserGroupsConfig,
 };
 use crate::config::{Group, GroupKeys};
 use crate::db::config::ConfigRepositoryExt;
 use crate::db::Repository;
 use crate::ed25519::{ED25519PubKey, ED25519SecKey};
+use crate::events::{Event, EventBus};
+use crate::groups::accept_member_invite;
 use crate::identity::Identity;
 use crate::network::swarm::{SwarmAuth, SwarmState};
 use crate::oxenss::namespace::{
@@ -21,6 +23,7 @@ use serde::Serialize;
 use std::borrow::Cow;
 use std::pin::{pin, Pin};
 use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::{broadcast, watch};
 use tokio::{select, try_join};
 
@@ -32,42 +35,41 @@ struct GroupSyncState<F> {
 }
 
 pub struct GroupConfigState {
-    pub(super) group_id: GroupID,
-    pub(super) group_keys: GroupKeys,
-    pub(super) group_info: GroupInfoConfig,
-    pub(super) group_members: GroupMemberConfig,
+    pub(crate) group_id: GroupID,
+    pub(crate) group_keys: GroupKeys,
+    pub(crate) group_info: GroupInfoConfig,
+    pub(crate) group_members: GroupMemberConfig,
 }
 
 impl GroupConfigState {
-    pub fn new(
+    pub async fn new(
         repo: &Repository,
         user_key: &ED25519SecKey,
         group_id: GroupID,
         group_admin_key: Option<&ED25519SecKey>,
     ) -> anyhow::Result<Self> {
-        let mut group_info = GroupInfoConfig::new(
-            &group_id,
-            group_admin_key,
-            Some(
-                repo.obtain_connection()?
-                    .get_config_dump(GroupInfoConfig::CONFIG_TYPE_NAME, Some(group_id.as_str()))
-                    .context("Failed to get group info dump")?
-                    .as_ref(),
-            ),
-        )
-        .context("Failed to create group info config")?;
+        let (info_dump, members_dump, keys_dump) = {
+            let id = group_id.to_string();
+            repo.read(move |conn| {
+                let dump = |name: &str| conn.get_config_dump(name, Some(&id));
+                Ok((
+                    dump(GroupInfoConfig::CONFIG_TYPE_NAME)
+                        .context("Failed to get group info dump")?,
+                    dump(GroupMemberConfig::CONFIG_TYPE_NAME)
+                        .context("Failed to get group members dump")?,
+                    dump(GroupKeys::CONFIG_TYPE_NAME).context("Failed to get group keys dump")?,
+                ))
+            })
+            .await?
+        };
 
-        let mut group_members = GroupMemberConfig::new(
-            &group_id,
-            group_admin_key,
-            Some(
-                repo.obtain_connection()?
-                    .get_config_dump(GroupMemberConfig::CONFIG_TYPE_NAME, Some(group_id.as_str()))
-                    .context("Failed to get group members dump")?
-                    .as_ref(),
-            ),
-        )
-        .context("Failed to create group members config")?;
+        let mut group_info =
+            GroupInfoConfig::new(&group_id, group_admin_key, Some(info_dump.as_slice()))
+                .context("Failed to create group info config")?;
+
+        let mut group_members =
+            GroupMemberConfig::new(&group_id, group_admin_key, Some(members_dump.as_slice()))
+                .context("Failed to create group members config")?;
 
         let group_keys = GroupKeys::new(
             user_key,
@@ -75,10 +77,7 @@ impl GroupConfigState {
             group_admin_key,
             &mut group_info,
             &mut group_members,
-            repo.obtain_connection()?
-                .get_config_dump(GroupKeys::CONFIG_TYPE_NAME, Some(group_id.as_str()))
-                .context("Failed to get group members dump")?
-                .as_ref(),
+            &keys_dump,
         )
         .context("Error creating group keys")?;
 
@@ -98,12 +97,15 @@ pub async fn sync_groups<CS>(
     mut config: watch::Receiver<UserGroupsConfig>,
     manual_sync_trigger: broadcast::Receiver<()>,
     clock: &ClockSource,
+    events: &EventBus,
 ) -> anyhow::Result<()>
 where
     CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
 {
     let mut group_sync_states: Vec<GroupSyncState<_>> = Default::default();
     let mut update_group_futures = true;
+    // Groups we already had at startup are not newly joined
+    let mut initial_load = true;
 
     loop {
         if update_group_futures {
@@ -113,13 +115,16 @@ where
                 .borrow()
                 .get_groups()
                 .filter_map(|s| match s {
-                    Group::Group(g) => Some(g),
+                    // Pending invites have nothing to poll with until accepted
+                    Group::Group(g) if !g.is_invited() => Some(g),
                     _ => None,
                 })
                 .collect();
 
+            // Kicked groups stay read-only, with nothing left to poll
             let mut group_ids = groups
                 .iter()
+                .filter(|g| !g.is_kicked())
                 .map(|g| g.group_id().unwrap())
                 .collect::<Vec<_>>();
 
@@ -128,18 +133,38 @@ where
             // Go through the future list and spawn new future when necessary
             for group in groups {
                 let group_id = group.group_id().unwrap();
+                if group.is_kicked() {
+                    if group_sync_states
+                        .binary_search_by_key(&&group_id, |s| &s.group_id)
+                        .is_ok()
+                    {
+                        log::info!("Kicked from group {group_id}, stop syncing");
+                        events.publish(Event::GroupKicked { group_id });
+                    }
+                    continue;
+                }
+
                 match group_sync_states.binary_search_by_key(&&group_id, |s| &s.group_id) {
                     Ok(index) if group_sync_states[index].syncing_group != group => {
                         log::info!("Group changed, restarting sync for group {:?}", group_id);
-                        let mut sync_state = &mut group_sync_states[index];
+                        let sync_state = &mut group_sync_states[index];
+                        if let (Some(sec_key), None) =
+                            (group.sec_key(), sync_state.syncing_group.sec_key())
+                        {
+                            log::info!("Promoted to admin of group {group_id}");
+                            load_admin_key(&sync_state.configs, &sec_key, &identity.session_id());
+                        }
+                        sync_state.syncing_group = group.clone();
                         sync_state.task = Box::pin(sync_group(
                             call_source,
+                            identity,
                             repo,
                             group,
                             sync_state.configs.clone(),
                             config.clone(),
                             manual_sync_trigger.resubscribe(),
                             clock,
+                            events,
                         ));
                     }
 
@@ -149,12 +174,21 @@ where
 
                     Err(index) => {
                         log::info!("New group found, starting sync for group {:?}", group_id);
-                        let configs = watch::channel(GroupConfigState::new(
-                            repo,
-                            identity.ed25519_sec_key(),
-                            group_id.clone(),
-                            group.sec_key().as_ref(),
-                        )?)
+                        if !initial_load {
+                            events.publish(Event::GroupJoined {
+                                group_id: group_id.clone(),
+                            });
+                        }
+
+                        let configs = watch::channel(
+                            GroupConfigState::new(
+                                repo,
+                                identity.ed25519_sec_key(),
+                                group_id.clone(),
+                                group.sec_key().as_ref(),
+                            )
+                            .await?,
+                        )
                         .0;
 
                         group_sync_states.insert(
@@ -165,12 +199,14 @@ where
                                 configs: configs.clone(),
                                 task: Box::pin(sync_group(
                                     call_source,
+                                    identity,
                                     repo,
                                     group,
                                     configs,
                                     config.clone(),
                                     manual_sync_trigger.resubscribe(),
                                     clock,
+                                    events,
                                 )),
                             },
                         );
@@ -180,7 +216,8 @@ where
 
             // Remove all the group futures that are not in the group_ids list
             group_sync_states.retain(|state| group_ids.contains(&state.group_id));
-            update_group_futures = false
+            update_group_futures = false;
+            initial_load = false;
         }
 
         if group_sync_states.is_empty() {
@@ -212,22 +249,70 @@ where
     }
 }
 
+/// Lets the group configs sign as admin, and marks our promotion as accepted.
+fn load_admin_key(
+    configs: &watch::Sender<GroupConfigState>,
+    sec_key: &ED25519SecKey,
+    me: &IndividualID,
+) {
+    configs.send_modify(|s| {
+        if let Err(e) =
+            s.group_keys
+                .load_admin_key(sec_key, &mut s.group_info, &mut s.group_members)
+        {
+            log::error!("Error loading admin key of {}: {e:?}", s.group_id);
+            return;
+        }
+
+        if let Some(mut member) = s.group_members.get(me) {
+            member.set_admin(true);
+            member.set_promotion_status(MemberStatus::Accepted);
+            s.group_members.set_member(&member);
+        }
+    });
+}
+
 async fn sync_group<CS>(
     call_source: &CS,
+    identity: &Identity,
     repo: &Repository,
     group: GroupInfo,
     group_config_state: watch::Sender<GroupConfigState>,
     user_groups_state: watch::Receiver<UserGroupsConfig>,
     manual_sync_trigger: broadcast::Receiver<()>,
     clock: &ClockSource,
+    events: &EventBus,
 ) -> anyhow::Result<()>
 where
     CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
 {
     let group_id = group.group_id().context("Invalid group id in the info")?;
     let group_swarm = SwarmState::new(group_id.clone().into());
+    let me = identity.session_id();
     let group_auth = (group_config_state.subscribe(), user_groups_state);
 
+    // Subscribed before polling so no response from the group is missed
+    let mut events_rx = events.subscribe();
+    let accept_invites = async {
+        loop {
+            match events_rx.recv().await {
+                Ok(Event::GroupInviteAccepted {
+                    group_id: id,
+                    member,
+                }) if id == group_id => {
+                    if accept_member_invite(&group_config_state, &member) {
+                        log::info!("{member} accepted the invite to {group_id}");
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(n)) => {
+                    log::warn!("Missed {n} events while tracking invites to {group_id}")
+                }
+                Err(RecvError::Closed) => return anyhow::Ok(()),
+            }
+        }
+    };
+
     let poll = super::sync_messages::<GroupNamespace, _>(
         repo,
         call_source,
@@ -236,6 +321,7 @@ where
         Duration::from_secs(10),
         manual_sync_trigger.resubscribe(),
         clock,
+        events,
     );
 
     let (info_tx, info_rx) = tokio::sync::mpsc::channel(10);
@@ -293,6 +379,15 @@ where
                     .merge(&messages, (&mut state.group_info, &mut state.group_members));
                 true
             });
+
+            if super::sync_group_configs::lost_group_keys(&group_config_state.borrow()) {
+                log::info!("No key left to read {group_id} with");
+                events.publish(Event::GroupMembersRemoved {
+                    group_id: group_id.clone(),
+                    members: vec![me.into_owned()],
+                });
+                return Ok(());
+            }
         }
         _ => {}
     }
@@ -303,9 +398,39 @@ where
         info_rx,
         keys_rx,
         members_rx,
+        &me,
+        events,
+    );
+
+    let push = super::sync_group_configs::push_group_configs_if_needed(
+        call_source,
+        &group_swarm,
+        repo,
+        &group_config_state,
+        &group_auth,
+        clock,
+    );
+
+    let send = super::send_messages::send_group_messages(
+        repo,
+        call_source,
+        identity,
+        &group_config_state,
+        &group_auth,
+        clock,
+        events,
     );
 
-    try_join!(poll, poll_info, poll_members, poll_keys, sync_configs)?;
+    try_join!(
+        poll,
+        poll_info,
+        poll_members,
+        poll_keys,
+        sync_configs,
+        push,
+        send,
+        accept_invites
+    )?;
     Ok(())
 }
 
@@ -1,10 +1,20 @@
 use super::sync_group::GroupConfigState;
-use crate::config::Config;
-use crate::db::config::ConfigRepositoryExt;
+use crate::clock::{ClockSource, Timestamp};
+use crate::config::{Config, GroupInfoConfig, GroupKeys, GroupMemberConfig, NamedConfig};
+use crate::db::config::{ConfigRecord, ConfigRepositoryExt};
+use crate::db::messages::MessageRepositoryExt;
 use crate::db::Repository;
+use crate::events::{Event, EventBus};
+use crate::groups::{push_group_configs, save_group};
+use crate::network::swarm::{SwarmAuth, SwarmState};
 use crate::oxenss::retrieve::Message;
+use crate::oxenss::JsonRpcCallSource;
+use crate::session_id::IndividualID;
+use anyhow::Context;
+use std::time::Duration;
 use tokio::select;
 use tokio::sync::{mpsc, watch};
+use tokio::time::sleep;
 
 pub async fn save_group_configs(
     repo: &Repository,
@@ -12,6 +22,8 @@ pub async fn save_group_configs(
     mut group_info_config_messages: mpsc::Receiver<anyhow::Result<Vec<Message>>>,
     mut group_key_config_messages: mpsc::Receiver<anyhow::Result<Vec<Message>>>,
     mut group_members_config_messages: mpsc::Receiver<anyhow::Result<Vec<Message>>>,
+    me: &IndividualID,
+    events: &EventBus,
 ) -> anyhow::Result<()> {
     loop {
         let (info, key, members) = select! {
@@ -31,23 +43,55 @@ pub async fn save_group_configs(
             || matches!(&members, Some(Ok(_)))
         {
             let mut err = None;
+            let mut records = Vec::new();
+            let mut merged = Vec::new();
+            let mut purge = None;
+            let mut removed_members = Vec::new();
             config.send_if_modified(|state| {
                 if let Some(Ok(messages)) = info {
-                    let _ = state.group_info.merge(&messages, ());
+                    let deleted_before = delete_before(&state.group_info);
+                    if state.group_info.merge(&messages, ()).is_ok() {
+                        merged.push(GroupInfoConfig::CONFIG_TYPE_NAME);
+
+                        let (messages_before, attachments_before) =
+                            delete_before(&state.group_info);
+                        if messages_before > deleted_before.0
+                            || attachments_before > deleted_before.1
+                        {
+                            purge = Some((messages_before, attachments_before));
+                        }
+                    }
                 }
 
                 if let Some(Ok(messages)) = members {
-                    let _ = state.group_members.merge(&messages, ());
+                    let members_before = member_ids(&state.group_members);
+                    if state.group_members.merge(&messages, ()).is_ok() {
+                        merged.push(GroupMemberConfig::CONFIG_TYPE_NAME);
+
+                        let members_after = member_ids(&state.group_members);
+                        removed_members = members_before
+                            .into_iter()
+                            .filter(|id| !members_after.contains(id))
+                            .collect();
+                    }
                 }
 
                 if let Some(Ok(messages)) = key {
-                    let _ = state
+                    if state
                         .group_keys
-                        .merge(&messages, (&mut state.group_info, &mut state.group_members));
+                        .merge(&messages, (&mut state.group_info, &mut state.group_members))
+                        .is_ok()
+                    {
+                        merged.push(GroupKeys::CONFIG_TYPE_NAME);
+                        if lost_group_keys(state) && !removed_members.contains(me) {
+                            removed_members.push(me.clone());
+                        }
+                    }
                 }
 
-                if let Err(e) = state.save_to_db(repo) {
-                    err.replace(e);
+                match state.take_records() {
+                    Ok(r) => records = r,
+                    Err(e) => err = Some(e),
                 }
 
                 true
@@ -56,23 +100,181 @@ pub async fn save_group_configs(
             if let Some(e) = err {
                 return Err(e);
             }
+
+            let group_id = config.borrow().group_id.clone();
+            repo.write({
+                let group_id = group_id.clone();
+                move |conn| {
+                    for record in &records {
+                        conn.save_config_record(record, Some(group_id.as_str()))?;
+                    }
+
+                    if let Some((messages_before, attachments_before)) = purge {
+                        let (messages, attachments) = conn.purge_group_messages(
+                            &group_id,
+                            messages_before,
+                            attachments_before,
+                        )?;
+                        log::info!(
+                            "Purged {messages} messages and {attachments} attachments from {group_id}"
+                        );
+                    }
+                    Ok(())
+                }
+            })
+            .await?;
+
+            for kind in merged {
+                events.publish(Event::ConfigMerged {
+                    kind,
+                    id: Some(group_id.to_string()),
+                });
+            }
+
+            if !removed_members.is_empty() {
+                events.publish(Event::GroupMembersRemoved {
+                    group_id,
+                    members: removed_members,
+                });
+            }
         } else if info.is_none() || key.is_none() || members.is_none() {
             return Ok(());
         }
     }
 }
 
+/// Pushes the edits an admin makes to the group configs. Members can't push, so this idles
+/// until we're promoted.
+pub async fn push_group_configs_if_needed<CS>(
+    call_source: &CS,
+    swarm: &SwarmState,
+    repo: &Repository,
+    config: &watch::Sender<GroupConfigState>,
+    auth: &impl SwarmAuth,
+    clock: &ClockSource,
+) -> anyhow::Result<()>
+where
+    CS: for<'a> JsonRpcCallSource<SourceArg<'a> = SwarmState>,
+{
+    let mut config_rx = config.subscribe();
+    loop {
+        config_rx
+            .wait_for(|s| {
+                s.group_keys.is_admin()
+                    && (s.group_keys.needs_push()
+                        || s.group_info.needs_push()
+                        || s.group_members.needs_push())
+            })
+            .await
+            .context("Waiting for group configs")?;
+
+        match push_group_configs(call_source, swarm, config, auth, clock).await {
+            // Confirming marks the configs as needing a dump
+            Ok(()) => save_group(repo, config).await?,
+            Err(e) => {
+                let duration = Duration::from_secs(5);
+                log::error!(
+                    "Failed to push group configs, wait for {}s before retrying: {e:?}",
+                    duration.as_secs()
+                );
+                sleep(duration).await;
+            }
+        }
+    }
+}
+
+/// Members are given the new key whenever the group is rekeyed, so being left with none means
+/// we've been removed.
+pub(super) fn lost_group_keys(state: &GroupConfigState) -> bool {
+    !state.group_keys.is_admin() && state.group_keys.key_count() == 0
+}
+
+fn member_ids(members: &GroupMemberConfig) -> Vec<IndividualID> {
+    members.members().map(|m| m.session_id().clone()).collect()
+}
+
+fn delete_before(info: &GroupInfoConfig) -> (Option<Timestamp>, Option<Timestamp>) {
+    (info.delete_before(), info.delete_attach_before())
+}
+
 impl GroupConfigState {
-    fn save_to_db(&mut self, repo: &Repository) -> anyhow::Result<()> {
-        let conn = repo.obtain_connection()?;
-        let tx = conn.unchecked_transaction()?;
+    pub(crate) fn take_records(&mut self) -> anyhow::Result<Vec<ConfigRecord>> {
+        Ok(vec![
+            ConfigRecord::take(&mut self.group_info)?,
+            ConfigRecord::take(&mut self.group_members)?,
+            ConfigRecord::take(&mut self.group_keys)?,
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base64::Base64;
+    use crate::clock::local_timestamp;
+    use crate::config::{IndividualConfig, UserGroupsConfig};
+    use crate::groups::create_group;
+    use crate::identity::Identity;
+    use crate::network::swarm::SwarmAuth;
+    use crate::test_utils::temp_path;
+    use r2d2_sqlite::SqliteConnectionManager;
+
+    fn keys_message(admin: &mut GroupConfigState, hash: &str) -> Message {
+        let data = admin
+            .group_keys
+            .rekey(&mut admin.group_info, &mut admin.group_members)
+            .expect("To rekey")
+            .to_vec();
+        Message {
+            data: Base64(data),
+            hash: hash.to_string(),
+            expiration: local_timestamp(),
+            created: local_timestamp(),
+        }
+    }
+
+    fn merge(member: &mut GroupConfigState, message: Message) {
+        member
+            .group_keys
+            .merge(
+                &[message],
+                (&mut member.group_info, &mut member.group_members),
+            )
+            .expect("To merge keys");
+    }
+
+    #[tokio::test]
+    async fn members_without_keys_were_removed() {
+        let path = temp_path("sqlite3");
+        let repo =
+            Repository::new(SqliteConnectionManager::file(&*path), None).expect("To create repo");
+        let admin = Identity::gen();
+        let user_groups =
+            watch::channel(UserGroupsConfig::new(admin.ed25519_sec_key(), None).unwrap()).0;
+        let mut group = create_group(&repo, &admin, &user_groups, "Group", None, "Admin")
+            .await
+            .expect("To create group");
+
+        let bob = Identity::gen();
+        let mut member =
+            GroupConfigState::new(&repo, bob.ed25519_sec_key(), group.group_id.clone(), None)
+                .await
+                .expect("To load group as member");
+
+        // A key for everyone but us
+        merge(&mut member, keys_message(&mut group, "first"));
+        assert!(lost_group_keys(&member));
 
-        let group_id = self.group_id.as_str();
-        tx.save_config(&mut self.group_info, Some(group_id))?;
-        tx.save_config(&mut self.group_members, Some(group_id))?;
-        tx.save_config(&mut self.group_keys, Some(group_id))?;
+        let mut bob_member = group
+            .group_members
+            .get_or_construct_member(&bob.session_id())
+            .unwrap();
+        bob_member.set_name("Bob").unwrap();
+        group.group_members.set_member(&bob_member);
+        merge(&mut member, keys_message(&mut group, "second"));
+        assert!(!lost_group_keys(&member));
 
-        tx.commit()?;
-        Ok(())
+        // Admins hold the secret key, whatever the keys messages say
+        assert!(!lost_group_keys(&group));
     }
 }